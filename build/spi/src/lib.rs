@@ -21,15 +21,111 @@ pub struct SpiGlobalConfig {
 pub struct SpiConfig {
     pub controller: usize,
     pub fifo_depth: Option<usize>,
+    /// Size of the window used to copy data to and from the client's leases.
+    /// Larger values mean fewer borrow syscalls on bulk transfers, at the cost
+    /// of server stack. Defaults to 16.
+    pub lease_buffer_size: Option<usize>,
+    /// Smallest clock divider (i.e. fastest SCK) that any device on this
+    /// controller may use. Defaults to `DIV2`, which allows anything.
+    pub min_clock_divider: Option<ClockDivider>,
+    /// Largest clock divider (i.e. slowest SCK) that any device on this
+    /// controller may use. Defaults to `DIV256`, which allows anything.
+    pub max_clock_divider: Option<ClockDivider>,
+    /// Size of the server-side buffer used by asynchronous exchanges
+    /// (`start_exchange`), which can't use the client's leases because the
+    /// client carries on running. Required if, and only if, the server is
+    /// built with its `async-exchange` feature.
+    pub async_buffer_size: Option<usize>,
+    /// If set, a lock whose holder makes no SPI calls for this many
+    /// milliseconds is forcibly released, unless the holder asked for its own
+    /// timeout when locking. Only the standalone server task enforces this.
+    pub lock_timeout_ms: Option<u32>,
     pub mux_options: BTreeMap<String, SpiMuxOptionConfig>,
     pub devices: IndexMap<String, DeviceDescriptorConfig>,
+    /// DMA streams used for large transfers, if the server is built with its
+    /// `dma` feature.
+    pub dma: Option<SpiDmaConfig>,
+    /// Settings for acting as an SPI target (slave), if the server is built
+    /// with its `target` feature.
+    pub target: Option<SpiTargetConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SpiTargetConfig {
+    /// Mux option to use while acting as a target. Its `extra_pins` must
+    /// route the SS pin to the peripheral.
+    pub mux: String,
+    /// SPI mode (0-3) that the host uses. Defaults to mode 0.
+    #[serde(default)]
+    pub spi_mode: u8,
+    /// Byte sent if the host clocks out more than we've prepared. Defaults
+    /// to 0x00.
+    #[serde(default)]
+    pub underrun_byte: u8,
+    /// Most bytes that can be prepared for one transfer. Defaults to 256.
+    pub buffer_size: Option<usize>,
+}
+
+impl SpiTargetConfig {
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size.unwrap_or(256)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SpiDmaConfig {
+    /// Which DMA controller (1 or 2) the streams belong to.
+    pub controller: u8,
+    pub tx_stream: u8,
+    pub rx_stream: u8,
+    /// Transfers shorter than this many bytes are moved by the CPU instead,
+    /// since setting up the streams isn't free. Defaults to 64.
+    pub threshold: Option<usize>,
+    /// Size of each of the TX and RX bounce buffers, which have to be in
+    /// DMA-reachable memory (the `spi_dma` section). Larger transfers are
+    /// split into chunks of this size. Defaults to 1024.
+    pub buffer_size: Option<usize>,
+}
+
+impl SpiDmaConfig {
+    pub fn threshold(&self) -> usize {
+        self.threshold.unwrap_or(64)
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size.unwrap_or(1024)
+    }
+}
+
+/// Returns the DMAMUX1 request lines for the TX and RX data of SPI
+/// `controller`, or `None` if its requests aren't routed through DMAMUX1
+/// (SPI6 uses the BDMA instead).
+pub fn dmamux_requests(controller: usize) -> Option<(u8, u8)> {
+    // From the DMAMUX1 request mapping table in the reference manual.
+    match controller {
+        1 => Some((38, 37)),
+        2 => Some((40, 39)),
+        3 => Some((62, 61)),
+        4 => Some((84, 83)),
+        5 => Some((86, 85)),
+        _ => None,
+    }
 }
 
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct SpiMuxOptionConfig {
     pub outputs: Vec<AfPinSetConfig>,
-    pub input: AfPinConfig,
+    /// CIPO. This may be omitted if the mux option is only used by half-duplex
+    /// devices, which use COPI in both directions.
+    pub input: Option<AfPinConfig>,
+    /// Additional pins switched to their alternate function along with the
+    /// outputs, such as the IO2/IO3 lines of a quad-capable flash, or its
+    /// WP#/HOLD# pins. These are driven high when the mux is inactive.
+    #[serde(default)]
+    pub extra_pins: Vec<AfPinSetConfig>,
     #[serde(default)]
     pub swap_data: bool,
 }
@@ -83,9 +179,87 @@ pub struct DeviceDescriptorConfig {
     #[serde(default)]
     pub clock_divider: ClockDivider,
     pub cs: Vec<GpioPinConfig>,
+    /// Byte shifted out once the caller's TX data runs out (e.g. during the
+    /// read phase of a transfer). Defaults to 0x00.
+    #[serde(default)]
+    pub idle_byte: u8,
+    #[serde(default)]
+    pub comm_mode: CommMode,
+    /// Idle SCK cycles (0-15) to insert between frames, for devices that can't
+    /// keep up with back-to-back frames. This is a count of SCK cycles rather
+    /// than a time, so the gap scales with the device's clock divider (which
+    /// can be overridden at runtime). Defaults to 0.
+    #[serde(default)]
+    pub inter_frame_gap_cycles: u8,
+    /// Polynomial for an 8-bit CRC appended to and checked at the end of each
+    /// transfer, for devices that require one. Only 8-bit frames support it.
+    #[serde(default)]
+    pub crc_poly: Option<u8>,
+    /// SPI mode (0-3), which selects the clock polarity (CPOL, the upper bit)
+    /// and phase (CPHA, the lower bit). Defaults to mode 0.
+    #[serde(default)]
+    pub spi_mode: u8,
+    /// Bits per frame: 8, 16, or 32. Wider frames are packed big-endian from
+    /// the caller's buffers, so the bytes go out in the same order as with
+    /// 8-bit frames, and transfers must be a whole number of frames. Only
+    /// full-duplex devices support wider frames. Defaults to 8.
+    #[serde(default = "default_frame_size")]
+    pub frame_size: u8,
+    /// Microseconds to wait after asserting CS before the first clock edge,
+    /// for devices that miss the start of a transfer without it. Defaults
+    /// to 0.
+    #[serde(default)]
+    pub cs_setup_us: u16,
+    /// Microseconds to wait after the last clock edge before deasserting CS.
+    /// Defaults to 0.
+    #[serde(default)]
+    pub cs_hold_us: u16,
+    /// Order in which the bits of each frame are shifted out and in. Only
+    /// devices with 8-bit frames can be LSB-first. Defaults to MSB-first.
+    #[serde(default)]
+    pub bit_order: BitOrder,
+    /// Frame format: ordinary (Motorola) SPI, or TI synchronous serial. TI
+    /// devices take their frame sync from the controller's hardware SS pin,
+    /// which the mux option has to route (e.g. in `extra_pins`). Defaults to
+    /// Motorola.
+    #[serde(default)]
+    pub protocol: SpiProtocol,
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+fn default_frame_size() -> u8 {
+    8
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub enum CommMode {
+    /// Separate COPI and CIPO lines, shifting in both directions at once.
+    #[default]
+    FullDuplex,
+    /// A single bidirectional data line (COPI), switching direction between
+    /// the write and read phases of a transfer.
+    HalfDuplex,
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub enum BitOrder {
+    /// Most significant bit first, as nearly every SPI device expects.
+    #[default]
+    MsbFirst,
+    /// Least significant bit first, as used by some shift register chains.
+    LsbFirst,
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub enum SpiProtocol {
+    #[default]
+    Motorola,
+    /// TI synchronous serial frame format, which fixes the clock mode and bit
+    /// order.
+    Ti,
+}
+
+// Variants are in increasing order, so that comparisons work.
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ClockDivider {
     DIV2,
     DIV4,
@@ -131,6 +305,44 @@ impl ToTokens for SpiConfig {
             let cs = &dev.cs;
             let div: syn::Ident =
                 syn::parse_str(&format!("{:?}", dev.clock_divider)).unwrap();
+            let idle_byte = dev.idle_byte;
+            let frame_size = dev.frame_size;
+            let cs_setup_us = dev.cs_setup_us;
+            let cs_hold_us = dev.cs_hold_us;
+            let inter_frame_gap_cycles = dev.inter_frame_gap_cycles;
+            let crc_poly = match dev.crc_poly {
+                Some(poly) => quote::quote! { Some(#poly) },
+                None => quote::quote! { None },
+            };
+            let comm: syn::Ident = syn::parse_str(match dev.comm_mode {
+                CommMode::FullDuplex => "FULLDUPLEX",
+                CommMode::HalfDuplex => "HALFDUPLEX",
+            })
+            .unwrap();
+            let lsbfrst: syn::Ident = syn::parse_str(match dev.bit_order {
+                BitOrder::MsbFirst => "MSBFIRST",
+                BitOrder::LsbFirst => "LSBFIRST",
+            })
+            .unwrap();
+            let sp: syn::Ident = syn::parse_str(match dev.protocol {
+                SpiProtocol::Motorola => "MOTOROLA",
+                SpiProtocol::Ti => "TI",
+            })
+            .unwrap();
+            let cpol: syn::Ident =
+                syn::parse_str(if dev.spi_mode & 0b10 == 0 {
+                    "IDLELOW"
+                } else {
+                    "IDLEHIGH"
+                })
+                .unwrap();
+            let cpha: syn::Ident =
+                syn::parse_str(if dev.spi_mode & 0b01 == 0 {
+                    "FIRSTEDGE"
+                } else {
+                    "SECONDEDGE"
+                })
+                .unwrap();
             quote::quote! {
                 DeviceDescriptor {
                     mux_index: #mux_index,
@@ -138,6 +350,17 @@ impl ToTokens for SpiConfig {
                     // `spi1` here is _not_ a typo/oversight, the PAC calls all
                     // SPI types spi1.
                     clock_divider: device::spi1::cfg1::MBR_A::#div,
+                    idle_byte: #idle_byte,
+                    comm_mode: device::spi1::cfg2::COMM_A::#comm,
+                    inter_frame_gap_cycles: #inter_frame_gap_cycles,
+                    crc_poly: #crc_poly,
+                    cpol: device::spi1::cfg2::CPOL_A::#cpol,
+                    cpha: device::spi1::cfg2::CPHA_A::#cpha,
+                    cs_setup_us: #cs_setup_us,
+                    cs_hold_us: #cs_hold_us,
+                    frame_size: #frame_size,
+                    bit_order: device::spi1::cfg2::LSBFRST_A::#lsbfrst,
+                    protocol: device::spi1::cfg2::SP_A::#sp,
                 }
             }
         });
@@ -154,14 +377,90 @@ impl ToTokens for SpiConfig {
         // If the user does not specify a fifo depth, we default to the
         // _minimum_ on any SPI block on the STM32H7, which is 8.
         let fifo_depth = self.fifo_depth.unwrap_or(8);
+        let lease_buffer_size = self.lease_buffer_size.unwrap_or(16);
+        let lock_timeout_ms = self.lock_timeout_ms.unwrap_or(0);
+        let min_div: syn::Ident = syn::parse_str(&format!(
+            "{:?}",
+            self.min_clock_divider.unwrap_or(ClockDivider::DIV2)
+        ))
+        .unwrap();
+        let max_div: syn::Ident = syn::parse_str(&format!(
+            "{:?}",
+            self.max_clock_divider.unwrap_or(ClockDivider::DIV256)
+        ))
+        .unwrap();
+
+        // The DMA config is only emitted if present, since the types it uses
+        // only exist when the server is built with DMA support.
+        if let Some(dma) = &self.dma {
+            let controller = dma.controller;
+            let tx_stream = usize::from(dma.tx_stream);
+            let rx_stream = usize::from(dma.rx_stream);
+            let (tx_request, rx_request) =
+                dmamux_requests(self.controller).unwrap();
+            let threshold = dma.threshold();
+            let buffer_size = dma.buffer_size();
+            tokens.append_all(quote::quote! {
+                const DMA_BUFFER_SIZE: usize = #buffer_size;
+                const DMA_CONFIG: DmaConfig = DmaConfig {
+                    controller: #controller,
+                    tx_stream: #tx_stream,
+                    rx_stream: #rx_stream,
+                    tx_request: #tx_request,
+                    rx_request: #rx_request,
+                    threshold: #threshold,
+                };
+            });
+        }
+
+        // Likewise for the asynchronous exchange buffer.
+        if let Some(async_buffer_size) = self.async_buffer_size {
+            tokens.append_all(quote::quote! {
+                const ASYNC_BUFFER_SIZE: usize = #async_buffer_size;
+            });
+        }
+
+        // Likewise for the target config.
+        if let Some(target) = &self.target {
+            let mux_index = mux_indices[&target.mux];
+            let cpol: syn::Ident =
+                syn::parse_str(if target.spi_mode & 0b10 == 0 {
+                    "IDLELOW"
+                } else {
+                    "IDLEHIGH"
+                })
+                .unwrap();
+            let cpha: syn::Ident =
+                syn::parse_str(if target.spi_mode & 0b01 == 0 {
+                    "FIRSTEDGE"
+                } else {
+                    "SECONDEDGE"
+                })
+                .unwrap();
+            let underrun_byte = target.underrun_byte;
+            let buffer_size = target.buffer_size();
+            tokens.append_all(quote::quote! {
+                const TARGET_BUFFER_SIZE: usize = #buffer_size;
+                const TARGET_CONFIG: TargetConfig = TargetConfig {
+                    mux_index: #mux_index,
+                    cpol: device::spi1::cfg2::CPOL_A::#cpol,
+                    cpha: device::spi1::cfg2::CPHA_A::#cpha,
+                    underrun_byte: #underrun_byte,
+                };
+            });
+        }
 
         tokens.append_all(quote::quote! {
             const FIFO_DEPTH: usize = #fifo_depth;
+            pub const LEASE_BUFFER_SIZE: usize = #lease_buffer_size;
+            pub const LOCK_TIMEOUT_MS: u32 = #lock_timeout_ms;
             const CONFIG: ServerConfig = ServerConfig {
                 registers: device::#devname::ptr(),
                 peripheral: sys_api::Peripheral::#pname,
                 mux_options: &[ #(#muxes),* ],
                 devices: &[ #(#device_code),* ],
+                min_clock_divider: device::spi1::cfg1::MBR_A::#min_div,
+                max_clock_divider: device::spi1::cfg1::MBR_A::#max_div,
             };
             pub mod devices {
                 #(#device_names)*
@@ -173,12 +472,17 @@ impl ToTokens for SpiConfig {
 impl ToTokens for SpiMuxOptionConfig {
     fn to_tokens(&self, tokens: &mut TokenStream) {
         let outputs = &self.outputs;
-        let input = &self.input;
+        let input = match &self.input {
+            Some(input) => quote::quote! { Some(#input) },
+            None => quote::quote! { None },
+        };
+        let extra_pins = &self.extra_pins;
         let swap_data = self.swap_data;
         tokens.append_all(quote::quote! {
             SpiMuxOption {
                 outputs: &[ #(#outputs),* ],
                 input: #input,
+                extra_pins: &[ #(#extra_pins),* ],
                 swap_data: #swap_data,
             }
         });
@@ -196,6 +196,19 @@ pub fn task_extern_regions<T: DeserializeOwned>() -> Result<IndexMap<String, T>>
     Ok(t)
 }
 
+/// Pulls the memories that the task's `sections` are placed in, keyed by
+/// section name. Each is a table with `address`, `size` and `dma` keys.
+pub fn task_section_regions<T: DeserializeOwned>() -> Result<IndexMap<String, T>>
+{
+    let t =
+        toml_from_env::<IndexMap<String, T>>("HUBRIS_TASK_SECTION_REGIONS")?
+            .ok_or_else(|| {
+                anyhow!("HUBRIS_TASK_SECTION_REGIONS is not defined")
+            })?;
+
+    Ok(t)
+}
+
 /// Pulls the full task configuration block of a different task
 pub fn other_task_full_config<T: DeserializeOwned>(
     name: &str,
@@ -377,6 +377,40 @@ impl Config {
             toml::to_string(&extern_regions).unwrap(),
         );
 
+        //
+        // Expose the memories that the task's sections are placed in, so that
+        // a task can check that a section lands somewhere suitable (e.g. in
+        // memory that DMA can reach).
+        //
+        let mut section_regions = IndexMap::new();
+
+        for (section, name) in &task_toml.sections {
+            if let Some(r) = self.outputs.get(name) {
+                let region = SectionRegion {
+                    address: r[0].address,
+                    size: r[0].size,
+                    dma: r[0].dma,
+                };
+
+                if !r.iter().all(|r| {
+                    (r.address, r.size, r.dma)
+                        == (region.address, region.size, region.dma)
+                }) {
+                    return Err(format!(
+                        "section region {name} has inconsistent \
+                        address/size/dma across images: {r:?}"
+                    ));
+                }
+
+                section_regions.insert(section, region);
+            }
+        }
+
+        out.env.insert(
+            "HUBRIS_TASK_SECTION_REGIONS".to_string(),
+            toml::to_string(&section_regions).unwrap(),
+        );
+
         Ok(out)
     }
 
@@ -653,6 +687,15 @@ pub struct Output {
     pub dma: bool,
 }
 
+/// The memory that one of a task's `sections` is placed in, as passed to its
+/// build script
+#[derive(Clone, Debug, Serialize)]
+struct SectionRegion {
+    address: u32,
+    size: u32,
+    dma: bool,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Peripheral {
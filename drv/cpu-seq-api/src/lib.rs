@@ -9,6 +9,7 @@
 use counters::Count;
 use derive_idol_err::IdolError;
 use userlib::{sys_send, FromPrimitive};
+use zerocopy::{AsBytes, FromBytes};
 
 // Re-export PowerState for client convenience.
 pub use drv_cpu_power_state::PowerState;
@@ -26,11 +27,99 @@ pub enum SeqError {
     A0TimeoutGroupC,
     A0Timeout,
     I2cFault,
+    PowerGoodTimeout,
+    BadFpgaIdent,
+    SpiFault,
 
     #[idol(server_death)]
     ServerRestarted,
 }
 
+/// Identity of the image loaded into the sequencer FPGA, as returned by
+/// `Sequencer::read_fpga_version`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct FpgaVersion {
+    /// Design version, from the VER0:3 registers
+    pub version: u32,
+    /// Short git hash of the design, from the SHA0:3 registers
+    pub sha: u32,
+    /// Design identifier, from the ID0:1 registers
+    pub ident: u16,
+    _pad: [u8; 2],
+}
+
+impl FpgaVersion {
+    pub fn new(ident: u16, version: u32, sha: u32) -> Self {
+        Self {
+            version,
+            sha,
+            ident,
+            _pad: [0; 2],
+        }
+    }
+}
+
+/// Record of the most recent power state transition requested of the
+/// sequencer, as returned by `Sequencer::get_last_transition`.
+///
+/// This is kept in raw form so that it can be sent over IPC; use the accessors
+/// to decode it. A record that's all zeroes means no transition has been
+/// requested since the sequencer started.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct StateTransition {
+    /// Value of the system timer when the transition was requested
+    pub timestamp: u64,
+    from: u8,
+    to: u8,
+    result: u8,
+    _pad: [u8; 5],
+}
+
+impl StateTransition {
+    pub fn new(
+        timestamp: u64,
+        from: PowerState,
+        to: PowerState,
+        result: Result<(), SeqError>,
+    ) -> Self {
+        Self {
+            timestamp,
+            from: from as u8,
+            to: to as u8,
+            // `SeqError` starts at 1, leaving 0 for success.
+            result: match result {
+                Ok(()) => 0,
+                Err(e) => e as u8,
+            },
+            _pad: [0; 5],
+        }
+    }
+
+    /// The state we were in when the transition was requested, or `None` if
+    /// there has been no transition.
+    pub fn previous_state(&self) -> Option<PowerState> {
+        PowerState::from_u8(self.from)
+    }
+
+    /// The state that was requested, or `None` if there has been no
+    /// transition.
+    pub fn requested_state(&self) -> Option<PowerState> {
+        PowerState::from_u8(self.to)
+    }
+
+    /// The outcome of the transition, or `None` if there has been no
+    /// transition.
+    pub fn result(&self) -> Option<Result<(), SeqError>> {
+        self.previous_state()?;
+        match self.result {
+            0 => Some(Ok(())),
+            e => SeqError::from_u8(e).map(Err),
+        }
+    }
+}
+
 // On Gimlet, we have two banks of up to 8 DIMMs apiece. Export the "two banks"
 // bit of knowledge here so it can be used by gimlet-seq-server, spd, and
 // packrat, all of which want to know at compile-time how many banks there are.
@@ -41,6 +41,12 @@ impl From<Ecp5UsingSpiError> for u8 {
             Ecp5UsingSpiError::SpiError(e) => match e {
                 SpiError::BadTransferSize => 3,
                 SpiError::TaskRestarted => 4,
+                SpiError::Busy => 5,
+                SpiError::LockExpired => 6,
+                SpiError::Aborted
+                | SpiError::CrcError
+                | SpiError::Overrun
+                | SpiError::ResultExpired => 7,
             },
         }
     }
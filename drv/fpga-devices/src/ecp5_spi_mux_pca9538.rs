@@ -39,6 +39,12 @@ impl From<Error> for u8 {
             Error::SpiError(e) => match e {
                 SpiError::BadTransferSize => 3,
                 SpiError::TaskRestarted => 4,
+                SpiError::Busy => 5,
+                SpiError::LockExpired => 6,
+                SpiError::Aborted
+                | SpiError::CrcError
+                | SpiError::Overrun
+                | SpiError::ResultExpired => 7,
             },
             Error::I2cError(e) => 8 + (e as u8),
         }
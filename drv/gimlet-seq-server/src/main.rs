@@ -17,7 +17,7 @@ use userlib::{
     sys_set_timer, task_slot, units, RecvMessage, TaskId, UnwrapLite,
 };
 
-use drv_cpu_seq_api::{PowerState, SeqError};
+use drv_cpu_seq_api::{FpgaVersion, PowerState, SeqError, StateTransition};
 use drv_hf_api as hf_api;
 use drv_i2c_api as i2c;
 use drv_ice40_spi_program as ice40;
@@ -61,6 +61,24 @@ enum I2cTxn {
     SocOff,
 }
 
+/// Sequencer FPGA power rails with a power-good signal
+#[derive(Copy, Clone, PartialEq)]
+enum Ice40Rail {
+    V1P2,
+    V3P3,
+}
+
+/// How long to wait for a sequencer FPGA rail to report power-good before
+/// giving up.
+///
+/// Both rails are small LDOs feeding only the iCE40. The sequence below
+/// already relies on the LT3072 on V1P2 having settled, with a meaningful PG,
+/// 1ms after it's enabled, and V2P5 (chained off V3P3) takes about 500us in
+/// practice. We wait 2ms after each enable before looking at PG, so a rail
+/// that still isn't good 100ms after that is two orders of magnitude past its
+/// expected ramp: it isn't slow, it's faulted.
+const ICE40_PG_TIMEOUT_MS: u64 = 100;
+
 #[derive(Copy, Clone, PartialEq, Count)]
 enum Trace {
     Ice40Rails(bool, bool),
@@ -71,8 +89,12 @@ enum Trace {
     Programming,
     Ice40PowerGoodV1P2(#[count(children)] bool),
     Ice40PowerGoodV3P3(#[count(children)] bool),
+    /// The given sequencer FPGA rail never reported power-good
+    Ice40PowerGoodTimeout(Ice40Rail),
     RailsOff,
     Ident(u16),
+    /// The FPGA came up after programming with an unexpected identifier
+    BadIdent(u16),
     A2Status(u8),
     A2,
     A0FailureDetails(Addr, u8),
@@ -160,9 +182,20 @@ fn main() -> ! {
         }
 
         // Initializing the sequencer failed.
-        Err(_) => {
+        Err(e) => {
             // Tell everyone that something's broken, as loudly as possible.
-            ringbuf_entry!(Trace::StartFailed(SeqError::I2cFault));
+            ringbuf_entry!(Trace::StartFailed(e));
+
+            // Before we had a timeout, a sequencer FPGA rail that never came
+            // up left us polling its PG pin forever; a rail that came up late
+            // would eventually let us proceed. Rather than park for good on a
+            // timeout, restart and run the power-up sequence again, so that a
+            // transient fault doesn't leave the sequencer FPGA unpowered until
+            // the SP is reset.
+            if e == SeqError::PowerGoodTimeout {
+                panic!();
+            }
+
             // Leave FAULT_PIN_L low (which is done at the start of init)
 
             // All these moments will be lost in time, like tears in rain...
@@ -182,6 +215,7 @@ fn main() -> ! {
 
 struct ServerImpl<S: SpiServer> {
     state: PowerState,
+    last_transition: StateTransition,
     sys: sys_api::Sys,
     seq: seq_spi::SequencerFpga<S>,
     jefe: Jefe,
@@ -198,7 +232,7 @@ impl<S: SpiServer + Clone> ServerImpl<S> {
         jefe: Jefe,
         spi: S,
         hf: hf_api::HostFlash,
-    ) -> Result<Self, i2c::ResponseCode> {
+    ) -> Result<Self, SeqError> {
         // Ensure the SP fault pin is configured as an open-drain output, and pull
         // it low to make the sequencer restart externally visible.
         sys.gpio_configure_output(
@@ -290,19 +324,7 @@ impl<S: SpiServer + Clone> ServerImpl<S> {
         hl::sleep_for(2);
 
         // Now, monitor the PG pin.
-        loop {
-            // active high
-            let pg = sys.gpio_read_input(PGS_PORT) & PG_V1P2_MASK != 0;
-            ringbuf_entry!(Trace::Ice40PowerGoodV1P2(pg));
-            if pg {
-                break;
-            }
-
-            // Do _not_ burn CPU constantly polling, it's rude. We could also set up
-            // pin-change interrupts but we only do this once per power on, so it
-            // seems like a lot of work.
-            hl::sleep_for(2);
-        }
+        wait_for_ice40_power_good(sys, Ice40Rail::V1P2)?;
 
         // We believe V1P2 is good. Now, for V3P3! Set it active (high).
         sys.gpio_set(ENABLE_V3P3);
@@ -311,17 +333,7 @@ impl<S: SpiServer + Clone> ServerImpl<S> {
         hl::sleep_for(2);
 
         // Now, monitor the PG pin.
-        loop {
-            // active high
-            let pg = sys.gpio_read_input(PGS_PORT) & PG_V3P3_MASK != 0;
-            ringbuf_entry!(Trace::Ice40PowerGoodV3P3(pg));
-            if pg {
-                break;
-            }
-
-            // Do _not_ burn CPU constantly polling, it's rude.
-            hl::sleep_for(2);
-        }
+        wait_for_ice40_power_good(sys, Ice40Rail::V3P3)?;
 
         // Now, V2P5 is chained off V3P3 and comes up on its own with no
         // synchronization. It takes about 500us in practice. We'll delay for 1ms,
@@ -407,12 +419,16 @@ impl<S: SpiServer + Clone> ServerImpl<S> {
 
         ringbuf_entry!(Trace::Programmed);
 
-        vcore_soc_off()?;
+        vcore_soc_off().map_err(|_| SeqError::I2cFault)?;
 
         ringbuf_entry!(Trace::RailsOff);
 
         let ident = seq.read_ident().unwrap_lite();
         ringbuf_entry!(Trace::Ident(ident));
+        if ident != seq_spi::EXPECTED_IDENT {
+            ringbuf_entry!(Trace::BadIdent(ident));
+            return Err(SeqError::BadFpgaIdent);
+        }
 
         loop {
             let mut status = [0u8];
@@ -444,7 +460,8 @@ impl<S: SpiServer + Clone> ServerImpl<S> {
                 ringbuf_entry!(Trace::ClockConfigWrite);
                 Ok(())
             }
-        })?;
+        })
+        .map_err(|_| SeqError::I2cFault)?;
 
         // Populate packrat with our mac address and identity.
         let packrat = Packrat::from(PACKRAT.get_task_id());
@@ -463,7 +480,8 @@ impl<S: SpiServer + Clone> ServerImpl<S> {
         // Per JEDEC 1791.12a, we must wait for tINIT (10ms) between power on and
         // sending the first SPD command.
         hl::sleep_for(10);
-        read_spd_data_and_load_packrat(&packrat, I2C.get_task_id())?;
+        read_spd_data_and_load_packrat(&packrat, I2C.get_task_id())
+            .map_err(|_| SeqError::I2cFault)?;
 
         // Turn on the chassis LED once we reach A2
         sys.gpio_set(CHASSIS_LED);
@@ -472,6 +490,7 @@ impl<S: SpiServer + Clone> ServerImpl<S> {
 
         let mut server = Self {
             state: PowerState::A2,
+            last_transition: StateTransition::default(),
             sys: sys.clone(),
             seq,
             jefe,
@@ -482,7 +501,7 @@ impl<S: SpiServer + Clone> ServerImpl<S> {
 
         // Power on, unless suppressed by the `stay-in-a2` feature
         if !cfg!(feature = "stay-in-a2") {
-            _ = server.set_state_internal(PowerState::A0);
+            _ = server.set_state_recorded(PowerState::A0);
         }
 
         //
@@ -624,6 +643,43 @@ impl<S: SpiServer> NotificationHandler for ServerImpl<S> {
     }
 }
 
+/// Polls the power-good pin for one of the sequencer FPGA's rails until it
+/// goes high, giving up after `ICE40_PG_TIMEOUT_MS`.
+fn wait_for_ice40_power_good(
+    sys: &sys_api::Sys,
+    rail: Ice40Rail,
+) -> Result<(), SeqError> {
+    let deadline = sys_get_timer().now + ICE40_PG_TIMEOUT_MS;
+    loop {
+        // active high
+        let pg = sys.gpio_read_input(PGS_PORT);
+        let good = match rail {
+            Ice40Rail::V1P2 => {
+                let good = pg & PG_V1P2_MASK != 0;
+                ringbuf_entry!(Trace::Ice40PowerGoodV1P2(good));
+                good
+            }
+            Ice40Rail::V3P3 => {
+                let good = pg & PG_V3P3_MASK != 0;
+                ringbuf_entry!(Trace::Ice40PowerGoodV3P3(good));
+                good
+            }
+        };
+        if good {
+            return Ok(());
+        }
+        if sys_get_timer().now >= deadline {
+            ringbuf_entry!(Trace::Ice40PowerGoodTimeout(rail));
+            return Err(SeqError::PowerGoodTimeout);
+        }
+
+        // Do _not_ burn CPU constantly polling, it's rude. We could also set up
+        // pin-change interrupts but we only do this once per power on, so it
+        // seems like a lot of work.
+        hl::sleep_for(2);
+    }
+}
+
 fn retry_i2c_txn<T, E>(
     which: I2cTxn,
     mut txn: impl FnMut() -> Result<T, E>,
@@ -663,6 +719,19 @@ impl<S: SpiServer> ServerImpl<S> {
         self.jefe.set_state(state as u32);
     }
 
+    /// Wraps `set_state_internal`, remembering the request and its outcome
+    /// for `get_last_transition`.
+    fn set_state_recorded(
+        &mut self,
+        state: PowerState,
+    ) -> Result<(), SeqError> {
+        let from = self.state;
+        let now = sys_get_timer().now;
+        let result = self.set_state_internal(state);
+        self.last_transition = StateTransition::new(now, from, state, result);
+        result
+    }
+
     fn set_state_internal(
         &mut self,
         state: PowerState,
@@ -1033,7 +1102,14 @@ impl<S: SpiServer> idl::InOrderSequencerImpl for ServerImpl<S> {
         _: &RecvMessage,
         state: PowerState,
     ) -> Result<(), RequestError<SeqError>> {
-        self.set_state_internal(state).map_err(RequestError::from)
+        self.set_state_recorded(state).map_err(RequestError::from)
+    }
+
+    fn get_last_transition(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<StateTransition, RequestError<core::convert::Infallible>> {
+        Ok(self.last_transition)
     }
 
     fn send_hardware_nmi(
@@ -1070,6 +1146,17 @@ impl<S: SpiServer> idl::InOrderSequencerImpl for ServerImpl<S> {
 
         Ok(buf)
     }
+
+    fn read_fpga_version(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<FpgaVersion, RequestError<SeqError>> {
+        let ident = self.seq.read_ident().map_err(|_| SeqError::SpiFault)?;
+        let version =
+            self.seq.read_version().map_err(|_| SeqError::SpiFault)?;
+        let sha = self.seq.read_sha().map_err(|_| SeqError::SpiFault)?;
+        Ok(FpgaVersion::new(ident, version, sha))
+    }
 }
 
 fn read_spd_data_and_load_packrat(
@@ -1403,7 +1490,7 @@ cfg_if::cfg_if! {
 }
 
 mod idl {
-    use super::SeqError;
+    use super::{FpgaVersion, SeqError, StateTransition};
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
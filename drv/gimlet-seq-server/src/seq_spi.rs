@@ -57,6 +57,21 @@ impl<S: SpiServer> SequencerFpga<S> {
         }
     }
 
+    /// Reads the VER0:3 registers, which hold the design version.
+    pub fn read_version(&self) -> Result<u32, spi_api::SpiError> {
+        let mut version = 0;
+        self.read_bytes(Addr::VER0, version.as_bytes_mut())?;
+        Ok(version)
+    }
+
+    /// Reads the SHA0:3 registers, which hold the short git hash of the
+    /// design.
+    pub fn read_sha(&self) -> Result<u32, spi_api::SpiError> {
+        let mut sha = 0;
+        self.read_bytes(Addr::SHA0, sha.as_bytes_mut())?;
+        Ok(sha)
+    }
+
     /// Reads the 32-bit checksum register, which should match
     /// `GIMLET_BITSTREAM_CHECKSUM` if the image is loaded and hasn't changed.
     pub fn read_checksum(&self) -> Result<u32, spi_api::SpiError> {
@@ -7,15 +7,15 @@
 #![no_std]
 #![no_main]
 
-use drv_cpu_seq_api::PowerState;
+use drv_cpu_seq_api::{FpgaVersion, PowerState, StateTransition};
 use drv_spi_api::{SpiDevice, SpiServer};
 use drv_stm32xx_sys_api as sys_api;
 use idol_runtime::{NotificationHandler, RequestError};
 use sha3::{Digest, Sha3_256};
 use task_jefe_api::Jefe;
 use userlib::{
-    hl, sys_recv_notification, task_slot, FromPrimitive, RecvMessage,
-    UnwrapLite,
+    hl, sys_get_timer, sys_recv_notification, task_slot, FromPrimitive,
+    RecvMessage, UnwrapLite,
 };
 
 use ringbuf::{counted_ringbuf, ringbuf_entry, Count};
@@ -89,6 +89,7 @@ struct ServerImpl<S: SpiServer> {
     jefe: Jefe,
     sys: sys_api::Sys,
     seq: SpiDevice<S>,
+    last_transition: StateTransition,
 }
 
 const FAULT_PIN_L: sys_api::PinSet = sys_api::Port::A.pin(15);
@@ -246,6 +247,7 @@ impl<S: SpiServer + Clone> ServerImpl<S> {
             sys: sys.clone(),
             jefe: Jefe::from(JEFE.get_task_id()),
             seq,
+            last_transition: StateTransition::default(),
         };
         server.set_state_impl(PowerState::A2);
 
@@ -286,7 +288,8 @@ impl<S: SpiServer + Clone> idl::InOrderSequencerImpl for ServerImpl<S> {
         _: &RecvMessage,
         state: PowerState,
     ) -> Result<(), RequestError<drv_cpu_seq_api::SeqError>> {
-        match (self.get_state_impl(), state) {
+        let from = self.get_state_impl();
+        let result = match (from, state) {
             (PowerState::A2, PowerState::A0)
             | (PowerState::A0, PowerState::A2)
             | (PowerState::A0PlusHP, PowerState::A2)
@@ -295,10 +298,18 @@ impl<S: SpiServer + Clone> idl::InOrderSequencerImpl for ServerImpl<S> {
                 Ok(())
             }
 
-            _ => Err(RequestError::Runtime(
-                drv_cpu_seq_api::SeqError::IllegalTransition,
-            )),
-        }
+            _ => Err(drv_cpu_seq_api::SeqError::IllegalTransition),
+        };
+        self.last_transition =
+            StateTransition::new(sys_get_timer().now, from, state, result);
+        result.map_err(RequestError::from)
+    }
+
+    fn get_last_transition(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<StateTransition, RequestError<core::convert::Infallible>> {
+        Ok(self.last_transition)
     }
 
     fn send_hardware_nmi(
@@ -314,6 +325,13 @@ impl<S: SpiServer + Clone> idl::InOrderSequencerImpl for ServerImpl<S> {
     ) -> Result<[u8; 64], RequestError<core::convert::Infallible>> {
         Ok([0; 64])
     }
+
+    fn read_fpga_version(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<FpgaVersion, RequestError<drv_cpu_seq_api::SeqError>> {
+        Ok(FpgaVersion::default())
+    }
 }
 
 impl<S: SpiServer> NotificationHandler for ServerImpl<S> {
@@ -327,7 +345,7 @@ impl<S: SpiServer> NotificationHandler for ServerImpl<S> {
 }
 
 mod idl {
-    use drv_cpu_seq_api::SeqError;
+    use drv_cpu_seq_api::{FpgaVersion, SeqError, StateTransition};
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
 
@@ -99,6 +99,10 @@ impl Isl68224 {
         pmbus_rail_write!(self.device, self.rail, OPERATION, op)
     }
 
+    pub fn clear_faults(&self) -> Result<(), Error> {
+        pmbus_write!(self.device, CLEAR_FAULTS)
+    }
+
     pub fn read_phase_current(&self, phase: Phase) -> Result<Amperes, Error> {
         let iout = pmbus_rail_phase_read!(
             self.device,
@@ -109,6 +113,12 @@ impl Isl68224 {
         Ok(Amperes(iout.get()?.0))
     }
 
+    pub fn read_status_word(&self) -> Result<u16, Error> {
+        let (val, _) =
+            pmbus_rail_read!(self.device, self.rail, STATUS_WORD)?.raw();
+        Ok(val as u16)
+    }
+
     pub fn i2c_device(&self) -> &I2cDevice {
         &self.device
     }
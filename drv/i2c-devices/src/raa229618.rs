@@ -140,6 +140,12 @@ impl Raa229618 {
         Ok(Amperes(iout.get()?.0))
     }
 
+    pub fn read_status_word(&self) -> Result<u16, Error> {
+        let (val, _) =
+            pmbus_rail_read!(self.device, self.rail, STATUS_WORD)?.raw();
+        Ok(val as u16)
+    }
+
     pub fn i2c_device(&self) -> &I2cDevice {
         &self.device
     }
@@ -30,6 +30,7 @@ impl From<idol_runtime::ServerDeath> for Error {
     }
 }
 
+#[derive(Copy, Clone)]
 pub enum VLanMode {
     /// Configure VLAN tags 0x301 and 0x302 for (upstream) ports 1 and 2
     /// respectively.  Allow untagged frames on any port, but drop tagged
@@ -7,10 +7,10 @@
 #![no_std]
 #![no_main]
 
-use drv_cpu_seq_api::{PowerState, SeqError};
+use drv_cpu_seq_api::{FpgaVersion, PowerState, SeqError, StateTransition};
 use idol_runtime::{NotificationHandler, RequestError};
 use task_jefe_api::Jefe;
-use userlib::{FromPrimitive, RecvMessage, UnwrapLite};
+use userlib::{sys_get_timer, FromPrimitive, RecvMessage, UnwrapLite};
 
 userlib::task_slot!(JEFE, jefe);
 
@@ -26,11 +26,15 @@ fn main() -> ! {
 
 struct ServerImpl {
     jefe: Jefe,
+    last_transition: StateTransition,
 }
 
 impl ServerImpl {
     fn init(jefe: Jefe) -> Self {
-        let me = Self { jefe };
+        let me = Self {
+            jefe,
+            last_transition: StateTransition::default(),
+        };
         me.set_state_impl(PowerState::A2);
         me
     }
@@ -59,7 +63,8 @@ impl idl::InOrderSequencerImpl for ServerImpl {
         _: &RecvMessage,
         state: PowerState,
     ) -> Result<(), RequestError<SeqError>> {
-        match (self.get_state_impl(), state) {
+        let from = self.get_state_impl();
+        let result = match (from, state) {
             (PowerState::A2, PowerState::A0)
             | (PowerState::A0, PowerState::A2)
             | (PowerState::A0PlusHP, PowerState::A2)
@@ -68,8 +73,18 @@ impl idl::InOrderSequencerImpl for ServerImpl {
                 Ok(())
             }
 
-            _ => Err(RequestError::Runtime(SeqError::IllegalTransition)),
-        }
+            _ => Err(SeqError::IllegalTransition),
+        };
+        self.last_transition =
+            StateTransition::new(sys_get_timer().now, from, state, result);
+        result.map_err(RequestError::from)
+    }
+
+    fn get_last_transition(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<StateTransition, RequestError<core::convert::Infallible>> {
+        Ok(self.last_transition)
     }
 
     fn send_hardware_nmi(
@@ -85,6 +100,13 @@ impl idl::InOrderSequencerImpl for ServerImpl {
     ) -> Result<[u8; 64], RequestError<core::convert::Infallible>> {
         Ok([0; 64])
     }
+
+    fn read_fpga_version(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<FpgaVersion, RequestError<SeqError>> {
+        Ok(FpgaVersion::default())
+    }
 }
 
 impl NotificationHandler for ServerImpl {
@@ -99,7 +121,7 @@ impl NotificationHandler for ServerImpl {
 }
 
 mod idl {
-    use super::SeqError;
+    use super::{FpgaVersion, SeqError, StateTransition};
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
@@ -16,7 +16,7 @@ pub use vsc85xx::{
 
 pub use vsc7448::{
     config::{PortConfig, PortDev, PortMode, PortSerdes, Speed},
-    VscError,
+    SerdesStatus, VscError,
 };
 
 /// Maximum number of ports
@@ -35,9 +35,24 @@ pub struct PacketCount {
     pub multicast: u32,
     pub unicast: u32,
     pub broadcast: u32,
+
+    /// Frames received with a bad FCS; always 0 for the `tx` direction
+    ///
+    /// This is only available over IPC (e.g. to Humility): the MGS protocol's
+    /// `PacketCount` has no such field, so `control-plane-agent` drops it.
+    pub crc_err: u32,
 }
 
 #[derive(Copy, Clone, Debug, Serialize, SerializedSize, Deserialize)]
+/// Per-port frame counters
+///
+/// Counters are read one register at a time, so they are not an atomic
+/// snapshot; traffic arriving mid-read may be reflected in some fields and
+/// not others.  The hardware counters are **not** clear-on-read: they
+/// accumulate (and wrap at 32 bits) until `reset_port_counters` is called.
+///
+/// Dropped frames are accounted in the queue system rather than the per-port
+/// statistics block, so they are not reported here.
 #[repr(C)]
 pub struct PortCounters {
     pub rx: PacketCount,
@@ -53,6 +53,15 @@ impl MainboardController {
         self.user_design.read(Addr::ID0)
     }
 
+    /// Reset the FPGA and begin loading a bitstream supplied by the caller,
+    /// rather than the one in auxiliary flash.
+    pub fn start_bitstream_load(
+        &mut self,
+        bitstream_type: BitstreamType,
+    ) -> Result<Bitstream, FpgaError> {
+        self.fpga.start_bitstream_load(bitstream_type)
+    }
+
     /// Load the mainboard controller bitstream.
     #[cfg(feature = "bitstream")]
     pub fn load_bitstream(
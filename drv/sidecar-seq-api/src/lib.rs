@@ -11,7 +11,7 @@ use drv_fpga_api::FpgaError;
 pub use drv_sidecar_mainboard_controller::{
     fan_modules::{FanModuleStatus, NUM_FAN_MODULES},
     tofino2::{
-        DebugPortState, DirectBarSegment, SpiEepromInstruction,
+        DebugPortState, DirectBarSegment, SpiEepromInstruction, Tofino2Vid,
         TofinoPcieReset, TofinoPowerRail, TofinoSeqError, TofinoSeqState,
         TofinoSeqStep,
     },
@@ -19,6 +19,7 @@ pub use drv_sidecar_mainboard_controller::{
 
 use hubpack::SerializedSize;
 use serde::{Deserialize, Serialize};
+use userlib::units::Volts;
 use userlib::*;
 use zerocopy::AsBytes;
 
@@ -35,6 +36,39 @@ pub enum SeqError {
     SetVddCoreVoutFailed,
     NoFrontIOBoard,
     FrontIOBoardPowerFault,
+    VddCoreMismatch,
+    PowerDownTimeout,
+    BadClockConfigChunk,
+    BadClockConfig,
+    VddCoreStillPowered,
+    /// The sequencer aborted because a rail didn't report power good in time
+    SequencerPowerGoodTimeout,
+    /// The sequencer aborted because a rail reported a fault
+    SequencerPowerFault,
+    /// The sequencer aborted because a regulator reported VRHOT
+    SequencerVrHot,
+    /// The sequencer aborted because a rail lost power good
+    SequencerPowerAbort,
+    /// The sequencer aborted at the request of software
+    SequencerSoftwareAbort,
+    /// The sequencer aborted because the VID wasn't acknowledged in time
+    SequencerVidAckTimeout,
+    /// The sequencer aborted because of a Tofino thermal alert
+    SequencerThermalAlert,
+    /// Tofino isn't currently presenting a VID
+    NoTofinoVid,
+    /// There is no fault recorded at the requested index
+    NoTofinoFault,
+    /// A bitstream chunk didn't follow on from the previous one
+    BadBitstreamChunk,
+    /// The mainboard controller isn't running the expected design
+    InvalidMainboardControllerId,
+    /// A clock generator register didn't read back as configured
+    ClockConfigurationMismatch,
+    /// The Tofino temperature sensor's THERM_B limit couldn't be set
+    ThermLimitFailed,
+    /// There is no Tofino event at the requested index
+    NoTofinoEvent,
 
     #[idol(server_death)]
     ServerRestarted,
@@ -46,6 +80,23 @@ impl From<FpgaError> for SeqError {
     }
 }
 
+/// Converts the reason the sequencer recorded for aborting into an error.
+impl From<TofinoSeqError> for SeqError {
+    fn from(e: TofinoSeqError) -> Self {
+        match e {
+            // Not an abort, but callers only ask once the sequencer stopped.
+            TofinoSeqError::None => Self::SequencerError,
+            TofinoSeqError::PowerGoodTimeout => Self::SequencerPowerGoodTimeout,
+            TofinoSeqError::PowerFault => Self::SequencerPowerFault,
+            TofinoSeqError::PowerVrHot => Self::SequencerVrHot,
+            TofinoSeqError::PowerAbort => Self::SequencerPowerAbort,
+            TofinoSeqError::SoftwareAbort => Self::SequencerSoftwareAbort,
+            TofinoSeqError::VidAckTimeout => Self::SequencerVidAckTimeout,
+            TofinoSeqError::ThermalAlert => Self::SequencerThermalAlert,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, AsBytes)]
 #[repr(u8)]
 pub enum TofinoSequencerPolicy {
@@ -54,6 +105,24 @@ pub enum TofinoSequencerPolicy {
     RestartOnFault = 2,
 }
 
+/// Converts a Tofino2 VID into the VDDCORE voltage it commands. A raw VID can
+/// be checked with `Tofino2Vid::from_u8` first.
+///
+/// The voltages are those from `Tofino2Vid`, i.e. derived from load testing the
+/// PDN rather than the nominal values in TF2-DS2.
+pub fn vid_to_volts(vid: Tofino2Vid) -> Volts {
+    Volts(match vid {
+        Tofino2Vid::V0P922 => 0.922,
+        Tofino2Vid::V0P893 => 0.893,
+        Tofino2Vid::V0P867 => 0.867,
+        Tofino2Vid::V0P847 => 0.847,
+        Tofino2Vid::V0P831 => 0.831,
+        Tofino2Vid::V0P815 => 0.815,
+        Tofino2Vid::V0P790 => 0.790,
+        Tofino2Vid::V0P759 => 0.759,
+    })
+}
+
 #[derive(
     Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
 )]
@@ -61,4 +130,152 @@ pub struct FanModulePresence(pub [bool; NUM_FAN_MODULES]);
 
 pub use drv_sidecar_mainboard_controller::fan_modules::FanModuleIndex;
 
+/// The PMBus regulated rails powering Tofino, for which the sequencer collects
+/// telemetry
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    FromPrimitive,
+    Deserialize,
+    Serialize,
+    SerializedSize,
+)]
+pub enum TofinoPmbusRail {
+    VddCore = 0,
+    Vddt = 1,
+    Vdda15 = 2,
+    Vdd18 = 3,
+    Vdda18 = 4,
+}
+
+pub const NUM_TOFINO_PMBUS_RAILS: usize = 5;
+
+/// The most recent telemetry collected from one of the `TofinoPmbusRail`s
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    PartialEq,
+    Deserialize,
+    Serialize,
+    SerializedSize,
+)]
+pub struct RailTelemetry {
+    /// Output voltage, in volts
+    pub vout: f32,
+    /// Output current, in amperes
+    pub iout: f32,
+    /// Raw PMBus STATUS_WORD
+    pub status_word: u16,
+    /// Time of the reading in ms since boot, or 0 if the rail has not yet been
+    /// read successfully
+    pub timestamp: u64,
+}
+
+/// Devices given a one-time configuration by the sequencer, in the order in
+/// which they are initialized
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, AsBytes)]
+#[repr(u8)]
+pub enum DeviceInit {
+    /// Loading the clock generator configuration
+    ClockGenerator = 0,
+    /// Setting the Tofino temperature sensor's THERM_B limit
+    TofinoThermLimit = 1,
+}
+
+pub const NUM_DEVICE_INITS: usize = 2;
+
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, AsBytes)]
+#[repr(u8)]
+pub enum DeviceInitStatus {
+    NotRun = 0,
+    Complete = 1,
+    /// Initialization wasn't needed, e.g. because Tofino was already running
+    Skipped = 2,
+    Failed = 3,
+}
+
+/// Why loading a clock generator configuration failed
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub enum ClockConfigFailure {
+    /// Writing a packet failed, even after retrying. `page` is the page of the
+    /// register map selected when it was written.
+    Write { packet: u32, page: u16 },
+    /// The register at `offset` in `page` didn't read back as written
+    Verify { page: u16, offset: u8 },
+}
+
+/// Number of faults kept in the Tofino fault log
+pub const TOFINO_FAULT_LOG_LEN: usize = 4;
+
+/// What led the sequencer to shut Tofino down
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub enum TofinoFaultSource {
+    /// The sequencer FPGA aborted the power sequence
+    Sequencer,
+    /// A regulator reported an output fault in its STATUS_WORD
+    Rail(TofinoPmbusRail),
+}
+
+/// A fault which caused Tofino to be shut down, along with a snapshot of the
+/// sequencer and rail status taken when it was handled
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub struct TofinoFault {
+    /// Time the fault was handled, in ms since boot
+    pub timestamp: u64,
+    pub source: TofinoFaultSource,
+    /// Raw `TofinoSeqError` recorded by the sequencer
+    pub seq_error: u8,
+    /// Raw `TofinoSeqState` in which the sequencer aborted, if it did
+    pub seq_error_state: u8,
+    /// Raw `TofinoSeqStep` in which the sequencer aborted, if it did
+    pub seq_error_step: u8,
+    /// Most recent STATUS_WORD of each rail, in `TofinoPmbusRail` order
+    pub rail_status: [u16; NUM_TOFINO_PMBUS_RAILS],
+}
+
+/// Number of events kept in the Tofino event log
+pub const TOFINO_EVENT_LOG_LEN: usize = 32;
+
+/// A power event recorded in the Tofino event log
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub enum TofinoEventKind {
+    /// The sequencer was observed in a new state, given as raw `TofinoSeqState`
+    /// and `TofinoSeqStep`
+    StateChange { state: u8, step: u8 },
+    /// Power up was started
+    PowerUp,
+    /// Power up failed, and Tofino was powered down
+    PowerUpFailed,
+    /// VDDCORE was set according to the given raw `Tofino2Vid`
+    Vid(u8),
+    /// Power down was started
+    PowerDown,
+    /// Tofino was shut down because of a fault, recorded in the fault log
+    Fault(TofinoFaultSource),
+    /// A latched fault was cleared
+    FaultCleared,
+}
+
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub struct TofinoEvent {
+    /// Time of the event, in ms since boot
+    pub timestamp: u64,
+    pub kind: TofinoEventKind,
+}
+
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -3,6 +3,7 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::*;
+use static_cell::ClaimOnceCell;
 
 #[cfg_attr(
     any(
@@ -14,37 +15,253 @@ use crate::*;
 )]
 mod payload;
 
+// The clock generator payload is the only board-specific part of this task; a
+// new board needs a payload above. Fail clearly rather than with a missing
+// module error.
+#[cfg(not(any(
+    target_board = "sidecar-b",
+    target_board = "sidecar-c",
+    target_board = "sidecar-d"
+)))]
+compile_error!("unsupported target board: no clock generator payload");
+
+/// Space for a clock configuration staged over IPC. The built-in payload is
+/// about 3.5 KiB once length-prefixed, so this leaves some headroom.
+const STAGED_CONFIG_SIZE: usize = 4096;
+
+/// Number of times a packet is written before the load is abandoned
+const WRITE_ATTEMPTS: usize = 3;
+
+/// Delay between attempts to write a packet
+const WRITE_RETRY_MS: u64 = 2;
+
+/// Register through which the 8A34001 selects the page of its register map
+/// addressed by other writes
+const PAGE_REGISTER: u8 = 0xfc;
+
+/// Registers read back once a configuration has been loaded, as addresses in
+/// the register map. This is the configuration of the clock inputs, INPUT[0]
+/// through INPUT[15], which unlike the status and command registers written
+/// elsewhere in a payload reads back as written.
+const VERIFIED_REGISTERS: core::ops::Range<u16> = 0xc1b0..0xc2e0;
+
+/// Where a configuration being written to the clock generator has got to.
+#[derive(Default)]
+struct Progress {
+    packet: usize,
+    page: u16,
+}
+
 pub(crate) struct ClockGenerator {
     pub device: I2cDevice,
     pub config_loaded: bool,
+    /// Why the most recent attempt to load a configuration failed, if it did
+    pub last_failure: Option<ClockConfigFailure>,
+    /// Clock configuration uploaded by `stage_chunk`, of which the first
+    /// `staged_len` bytes have arrived.
+    staged: &'static mut [u8; STAGED_CONFIG_SIZE],
+    staged_len: usize,
 }
 
 impl ClockGenerator {
     pub fn new(i2c_task: userlib::TaskId) -> Self {
+        static STAGED: ClaimOnceCell<[u8; STAGED_CONFIG_SIZE]> =
+            ClaimOnceCell::new([0; STAGED_CONFIG_SIZE]);
         Self {
             device: i2c_config::devices::idt8a34001(i2c_task)[0],
             config_loaded: false,
+            last_failure: None,
+            staged: STAGED.claim(),
+            staged_len: 0,
         }
     }
 
     pub fn load_config(&mut self) -> Result<(), SeqError> {
         ringbuf_entry!(Trace::LoadingClockConfiguration);
 
-        let mut packet = 0;
+        let mut progress = Progress::default();
+        let mut page = 0;
+        let result = payload::idt8a3xxxx_payload(|buf| {
+            self.write_packet(&mut progress, buf)
+        })
+        .and_then(|()| {
+            payload::idt8a3xxxx_payload(|buf| {
+                self.verify_packet(&mut page, buf)
+            })
+        });
 
-        payload::idt8a3xxxx_payload(|buf| match self.device.write(buf) {
-            Err(err) => {
-                ringbuf_entry!(Trace::ClockConfigurationError(packet, err));
-                Err(SeqError::ClockConfigurationFailed)
+        self.finish_load(result)
+    }
+
+    /// Returns the part of the staging buffer that a chunk of `len` bytes at
+    /// `offset` should be copied into. Once the copy has succeeded, the caller
+    /// must call `commit_chunk` with the chunk's end offset.
+    ///
+    /// A chunk at offset 0 starts a new configuration; otherwise, chunks must
+    /// arrive in order, so that a lost chunk is caught here rather than
+    /// loaded into the clock generator.
+    pub fn stage_chunk(
+        &mut self,
+        offset: usize,
+        len: usize,
+    ) -> Result<&mut [u8], SeqError> {
+        if offset == 0 {
+            self.staged_len = 0;
+        }
+        if offset != self.staged_len {
+            return Err(SeqError::BadClockConfigChunk);
+        }
+        let end = offset
+            .checked_add(len)
+            .filter(|&end| end <= STAGED_CONFIG_SIZE)
+            .ok_or(SeqError::BadClockConfigChunk)?;
+        Ok(&mut self.staged[offset..end])
+    }
+
+    /// Marks the staging buffer as filled up to `end`, after a chunk returned
+    /// by `stage_chunk` has been copied in. Until then, a chunk whose copy
+    /// failed can be sent again at the same offset.
+    pub fn commit_chunk(&mut self, end: usize) {
+        self.staged_len = end;
+    }
+
+    /// Loads the configuration uploaded by `stage_chunk` into the clock
+    /// generator, in the same way as the built-in payload.
+    ///
+    /// The staged configuration is a sequence of I2C writes, each preceded by
+    /// a length byte.
+    pub fn load_staged_config(&mut self) -> Result<(), SeqError> {
+        let staged = &self.staged[..self.staged_len];
+
+        // Check the framing before touching the device, so that a truncated
+        // upload doesn't leave the clock generator half-configured.
+        let mut rest = staged;
+        while let Some((&len, tail)) = rest.split_first() {
+            let len = usize::from(len);
+            if len == 0 || len > tail.len() {
+                return Err(SeqError::BadClockConfig);
             }
+            rest = &tail[len..];
+        }
+        if staged.is_empty() {
+            return Err(SeqError::BadClockConfig);
+        }
+
+        ringbuf_entry!(Trace::LoadingClockConfiguration);
+
+        let mut progress = Progress::default();
+        let mut page = 0;
+        let result = for_each_packet(staged, |buf| {
+            self.write_packet(&mut progress, buf)
+        })
+        .and_then(|()| {
+            for_each_packet(staged, |buf| self.verify_packet(&mut page, buf))
+        });
 
-            Ok(_) => {
-                packet += 1;
+        self.staged_len = 0;
+        self.finish_load(result)
+    }
+
+    fn finish_load(
+        &mut self,
+        result: Result<(), ClockConfigFailure>,
+    ) -> Result<(), SeqError> {
+        self.last_failure = result.err();
+        match result {
+            Ok(()) => {
+                self.config_loaded = true;
                 Ok(())
             }
-        })?;
+            Err(ClockConfigFailure::Write { .. }) => {
+                Err(SeqError::ClockConfigurationFailed)
+            }
+            Err(ClockConfigFailure::Verify { .. }) => {
+                Err(SeqError::ClockConfigurationMismatch)
+            }
+        }
+    }
+
+    /// Writes a packet, retrying a few times so that a glitch on the bus
+    /// doesn't abandon the whole configuration.
+    fn write_packet(
+        &self,
+        progress: &mut Progress,
+        buf: &[u8],
+    ) -> Result<(), ClockConfigFailure> {
+        if let [PAGE_REGISTER, lo, hi, ..] = *buf {
+            progress.page = u16::from_le_bytes([lo, hi]);
+        }
+
+        for attempt in 1..=WRITE_ATTEMPTS {
+            match self.device.write(buf) {
+                Ok(()) => {
+                    progress.packet += 1;
+                    return Ok(());
+                }
+                Err(err) => {
+                    ringbuf_entry!(Trace::ClockConfigurationError(
+                        progress.packet,
+                        err
+                    ));
+                    if attempt < WRITE_ATTEMPTS {
+                        hl::sleep_for(WRITE_RETRY_MS);
+                    }
+                }
+            }
+        }
+
+        Err(ClockConfigFailure::Write {
+            packet: progress.packet as u32,
+            page: progress.page,
+        })
+    }
+
+    /// Selects pages as the load did, and reads back any packet which wrote
+    /// to `VERIFIED_REGISTERS`.
+    fn verify_packet(
+        &self,
+        page: &mut u16,
+        buf: &[u8],
+    ) -> Result<(), ClockConfigFailure> {
+        let Some((&offset, data)) = buf.split_first() else {
+            return Ok(());
+        };
+        let mismatch = |page| {
+            ringbuf_entry!(Trace::ClockConfigurationMismatch { page, offset });
+            ClockConfigFailure::Verify { page, offset }
+        };
+
+        if offset == PAGE_REGISTER {
+            if let [lo, hi, ..] = *data {
+                *page = u16::from_le_bytes([lo, hi]);
+            }
+            return self.device.write(buf).map_err(|_| mismatch(*page));
+        }
+
+        if !VERIFIED_REGISTERS.contains(&(*page | u16::from(offset))) {
+            return Ok(());
+        }
+
+        let mut readback = [0u8; u8::MAX as usize];
+        let readback = &mut readback[..data.len()];
+        match self.device.read_reg_into(offset, readback) {
+            Ok(_) if readback == data => Ok(()),
+            _ => Err(mismatch(*page)),
+        }
+    }
+}
 
-        self.config_loaded = true;
-        Ok(())
+/// Calls `func` on each packet of a staged configuration, whose framing has
+/// already been checked.
+fn for_each_packet<E>(
+    staged: &[u8],
+    mut func: impl FnMut(&[u8]) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut rest = staged;
+    while let Some((&len, tail)) = rest.split_first() {
+        let (buf, next) = tail.split_at(usize::from(len));
+        func(buf)?;
+        rest = next;
     }
+    Ok(())
 }
@@ -0,0 +1,114 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! One-time configuration of the devices around Tofino, run from a table so
+//! that each device's outcome is recorded and any of them can be run again
+//! over IPC.
+
+use crate::*;
+use drv_sidecar_seq_api::{DeviceInit, DeviceInitStatus, NUM_DEVICE_INITS};
+
+struct InitEntry {
+    device: DeviceInit,
+    /// Whether a failure at startup is fatal to the task, restarting it to try
+    /// again, rather than only being recorded
+    required: bool,
+    init: fn(&mut ServerImpl) -> Result<DeviceInitStatus, SeqError>,
+}
+
+/// The devices to initialize, in order. Entries are indexed by `DeviceInit`.
+static INIT_TABLE: [InitEntry; NUM_DEVICE_INITS] = [
+    InitEntry {
+        device: DeviceInit::ClockGenerator,
+        required: true,
+        init: init_clock_generator,
+    },
+    InitEntry {
+        device: DeviceInit::TofinoThermLimit,
+        required: true,
+        init: init_tofino_therm_limit,
+    },
+];
+
+pub(crate) struct DeviceInits {
+    status: [DeviceInitStatus; NUM_DEVICE_INITS],
+}
+
+impl DeviceInits {
+    pub fn new() -> Self {
+        Self {
+            status: [DeviceInitStatus::NotRun; NUM_DEVICE_INITS],
+        }
+    }
+
+    pub fn status(&self, device: DeviceInit) -> DeviceInitStatus {
+        self.status[device as usize]
+    }
+}
+
+impl ServerImpl {
+    /// Initializes every device in the table, in order.
+    pub(crate) fn run_device_inits(&mut self) {
+        for entry in &INIT_TABLE {
+            if self.run_device_init(entry.device).is_err() && entry.required {
+                panic!();
+            }
+        }
+    }
+
+    /// Initializes a single device, recording the outcome.
+    pub(crate) fn run_device_init(
+        &mut self,
+        device: DeviceInit,
+    ) -> Result<DeviceInitStatus, SeqError> {
+        let entry = &INIT_TABLE[device as usize];
+        let result = (entry.init)(self);
+
+        let status = match result {
+            Ok(status) => status,
+            Err(e) => {
+                ringbuf_entry!(Trace::DeviceInitFailed(device, e));
+                DeviceInitStatus::Failed
+            }
+        };
+        self.device_inits.status[device as usize] = status;
+
+        result
+    }
+}
+
+fn init_clock_generator(
+    server: &mut ServerImpl,
+) -> Result<DeviceInitStatus, SeqError> {
+    // Reconfiguring the clock generator would pull the clocks out from under
+    // a running Tofino, which will have been left that way by a previous
+    // instance of this task.
+    if let Ok(TofinoSeqState::A0) = server.tofino.sequencer.state() {
+        ringbuf_entry!(Trace::SkipLoadingClockConfiguration);
+        server.clock_generator.config_loaded = true;
+        return Ok(DeviceInitStatus::Skipped);
+    }
+
+    server.clock_generator.load_config()?;
+    ringbuf_entry!(Trace::ClockConfigurationComplete);
+    Ok(DeviceInitStatus::Complete)
+}
+
+fn init_tofino_therm_limit(
+    server: &mut ServerImpl,
+) -> Result<DeviceInitStatus, SeqError> {
+    // Configure the TMP451 attached to the Tofino to trigger its THERM_B
+    // line at 90°C, rather than the default of 108°C.  The THERM_B line
+    // is monitored by the sequencer FPGA and will cut power to the system,
+    // because the Tofino doesn't have built-in protection against thermal
+    // overruns.
+    let tmp451 = drv_i2c_devices::tmp451::Tmp451::new(
+        &i2c_config::devices::tmp451_tf2(server.i2c_task),
+        drv_i2c_devices::tmp451::Target::Remote,
+    );
+    tmp451
+        .write_reg(drv_i2c_devices::tmp451::Register::RemoteTempThermBLimit, 90)
+        .map_err(|_| SeqError::ThermLimitFailed)?;
+    Ok(DeviceInitStatus::Complete)
+}
@@ -8,10 +8,11 @@
 #![no_main]
 
 use crate::clock_generator::ClockGenerator;
+use crate::device_init::DeviceInits;
 use crate::front_io::FrontIOBoard;
 use crate::tofino::Tofino;
 use core::convert::Infallible;
-use drv_fpga_api::{DeviceState, FpgaError, WriteOp};
+use drv_fpga_api::{Bitstream, BitstreamType, DeviceState, FpgaError, WriteOp};
 use drv_i2c_api::{I2cDevice, ResponseCode};
 use drv_packrat_vpd_loader::{read_vpd_and_load_packrat, Packrat};
 use drv_sidecar_front_io::phy_smi::PhyOscState;
@@ -20,7 +21,9 @@ use drv_sidecar_mainboard_controller::front_io::*;
 use drv_sidecar_mainboard_controller::tofino2::*;
 use drv_sidecar_mainboard_controller::MainboardController;
 use drv_sidecar_seq_api::{
-    FanModuleIndex, FanModulePresence, SeqError, TofinoSequencerPolicy,
+    ClockConfigFailure, DeviceInit, DeviceInitStatus, FanModuleIndex,
+    FanModulePresence, RailTelemetry, SeqError, TofinoEvent, TofinoFault,
+    TofinoFaultSource, TofinoPmbusRail, TofinoSequencerPolicy,
 };
 use idol_runtime::{
     ClientError, Leased, NotificationHandler, RequestError, R, W,
@@ -33,11 +36,16 @@ task_slot!(MAINBOARD, mainboard);
 task_slot!(FRONT_IO, front_io);
 task_slot!(AUXFLASH, auxflash);
 task_slot!(PACKRAT, packrat);
+#[cfg(feature = "fault-irq")]
+task_slot!(SYS, sys);
 
 include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
 
 mod clock_generator;
+mod device_init;
 mod front_io;
+mod ring_log;
+mod telemetry;
 mod tofino;
 
 #[allow(dead_code)]
@@ -48,6 +56,8 @@ enum Trace {
     FpgaInitComplete,
     FpgaBitstreamError(u32),
     LoadingFpgaBitstream,
+    LoadingStreamedFpgaBitstream,
+    StreamedFpgaBitstreamLoaded,
     SkipLoadingBitstream,
     MainboardControllerId(u32),
     MainboardControllerChecksum(u32),
@@ -58,7 +68,12 @@ enum Trace {
     LoadingClockConfiguration,
     SkipLoadingClockConfiguration,
     ClockConfigurationError(usize, ResponseCode),
+    ClockConfigurationMismatch {
+        page: u16,
+        offset: u8,
+    },
     ClockConfigurationComplete,
+    DeviceInitFailed(DeviceInit, SeqError),
     TofinoSequencerError(SeqError),
     TofinoSequencerPolicyUpdate(TofinoSequencerPolicy),
     TofinoSequencerTick(TofinoSequencerPolicy, TofinoStateDetails),
@@ -73,8 +88,24 @@ enum Trace {
     TofinoBar0RegisterValue(TofinoBar0Registers, u32),
     TofinoCfgRegisterValue(TofinoCfgRegisters, u32),
     TofinoPowerUp,
+    TofinoPowerUpFailed(SeqError),
     TofinoPowerDown,
+    TofinoPowerDownStep(tofino::PowerDownStep),
+    TofinoPowerDownTimeout(tofino::PowerDownStep),
+    VddCoreStillPowered(userlib::units::Volts),
     SetVddCoreVout(userlib::units::Volts),
+    VddCoreVoutMismatch {
+        commanded: userlib::units::Volts,
+        actual: userlib::units::Volts,
+    },
+    RailTelemetryReadFailed(TofinoPmbusRail),
+    RailClearFaultsFailed(TofinoPmbusRail),
+    TofinoFaultIrq,
+    TofinoProtectiveShutdown(TofinoFaultSource),
+    RailStatus {
+        rail: TofinoPmbusRail,
+        status_word: u16,
+    },
     SetPCIePresent,
     ClearPCIePresent,
     ClearingTofinoSequencerFault(TofinoSeqError),
@@ -109,6 +140,25 @@ ringbuf!(Trace, 32, Trace::None);
 
 const TIMER_INTERVAL: u64 = 1000;
 
+#[cfg(feature = "fault-irq")]
+const FAULT_IRQ_MASK: u32 = notifications::TOFINO_FAULT_MASK;
+#[cfg(not(feature = "fault-irq"))]
+const FAULT_IRQ_MASK: u32 = 0;
+
+/// Enables the interrupt from the (active low) fault line, which is disabled
+/// each time it fires.
+#[cfg(feature = "fault-irq")]
+fn enable_fault_irq() {
+    use drv_stm32xx_sys_api::{IrqControl, Sys};
+
+    Sys::from(SYS.get_task_id())
+        .gpio_irq_control(FAULT_IRQ_MASK, IrqControl::Enable)
+        .unwrap_lite();
+}
+
+#[cfg(not(feature = "fault-irq"))]
+fn enable_fault_irq() {}
+
 #[derive(Copy, Clone, PartialEq)]
 enum TofinoStateDetails {
     A0 {
@@ -124,9 +174,19 @@ enum TofinoStateDetails {
     },
 }
 
+/// A bitstream being streamed into the mainboard controller by a client
+struct BitstreamLoad {
+    bitstream: Bitstream,
+    /// Number of bytes loaded so far
+    offset: usize,
+}
+
 struct ServerImpl {
+    i2c_task: TaskId,
     mainboard_controller: MainboardController,
+    bitstream_load: Option<BitstreamLoad>,
     clock_generator: ClockGenerator,
+    device_inits: DeviceInits,
     tofino: Tofino,
     front_io_hsc: HotSwapController,
     front_io_board: Option<FrontIOBoard>,
@@ -377,6 +437,20 @@ impl idl::InOrderSequencerImpl for ServerImpl {
             .map_err(RequestError::from)
     }
 
+    fn tofino_vid(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<Tofino2Vid, RequestError<SeqError>> {
+        match self.tofino.sequencer.vid() {
+            Ok(Some(vid)) => Ok(vid),
+            Ok(None) => Err(SeqError::NoTofinoVid.into()),
+            Err(FpgaError::InvalidValue) => {
+                Err(SeqError::InvalidTofinoVid.into())
+            }
+            Err(e) => Err(SeqError::from(e).into()),
+        }
+    }
+
     fn clear_tofino_seq_error(
         &mut self,
         _: &RecvMessage,
@@ -384,6 +458,7 @@ impl idl::InOrderSequencerImpl for ServerImpl {
         if let Ok(e) = self.tofino.sequencer.error().map_err(SeqError::from) {
             ringbuf_entry!(Trace::ClearingTofinoSequencerFault(e));
         }
+        self.tofino.clear_fault();
         Ok(self
             .tofino
             .sequencer
@@ -402,6 +477,51 @@ impl idl::InOrderSequencerImpl for ServerImpl {
             .map_err(RequestError::from)
     }
 
+    fn read_rail_telemetry(
+        &mut self,
+        _: &RecvMessage,
+        rail: TofinoPmbusRail,
+    ) -> Result<RailTelemetry, RequestError<SeqError>> {
+        Ok(self.tofino.rail_telemetry.reading(rail))
+    }
+
+    fn tofino_fault(
+        &mut self,
+        _: &RecvMessage,
+        index: u8,
+    ) -> Result<TofinoFault, RequestError<SeqError>> {
+        self.tofino
+            .fault_log
+            .get(index.into())
+            .ok_or(SeqError::NoTofinoFault.into())
+    }
+
+    fn clear_tofino_fault_log(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<SeqError>> {
+        self.tofino.fault_log.clear();
+        Ok(())
+    }
+
+    fn read_log_entry(
+        &mut self,
+        _: &RecvMessage,
+        index: u8,
+    ) -> Result<TofinoEvent, RequestError<SeqError>> {
+        self.tofino
+            .event_log
+            .get(index.into())
+            .ok_or(SeqError::NoTofinoEvent.into())
+    }
+
+    fn log_len(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u8, RequestError<Infallible>> {
+        Ok(self.tofino.event_log.len() as u8)
+    }
+
     fn tofino_pcie_hotplug_ctrl(
         &mut self,
         _: &userlib::RecvMessage,
@@ -481,6 +601,27 @@ impl idl::InOrderSequencerImpl for ServerImpl {
         Ok(self.clock_generator.load_config()?)
     }
 
+    fn load_clock_config_chunk(
+        &mut self,
+        _: &RecvMessage,
+        offset: u32,
+        data: Leased<R, [u8]>,
+    ) -> Result<(), RequestError<SeqError>> {
+        let offset = offset as usize;
+        let buf = self.clock_generator.stage_chunk(offset, data.len())?;
+        data.read_range(0..buf.len(), buf)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        self.clock_generator.commit_chunk(offset + data.len());
+        Ok(())
+    }
+
+    fn finish_clock_config(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<SeqError>> {
+        Ok(self.clock_generator.load_staged_config()?)
+    }
+
     fn is_clock_config_loaded(
         &mut self,
         _: &RecvMessage,
@@ -488,6 +629,125 @@ impl idl::InOrderSequencerImpl for ServerImpl {
         Ok(self.clock_generator.config_loaded)
     }
 
+    fn load_mainboard_controller_bitstream_chunk(
+        &mut self,
+        _: &RecvMessage,
+        offset: u32,
+        data: Leased<R, [u8]>,
+    ) -> Result<(), RequestError<SeqError>> {
+        let offset = offset as usize;
+
+        if offset == 0 {
+            if let Some(mut load) = self.bitstream_load.take() {
+                let _ = load.bitstream.cancel_load();
+            }
+
+            // Resetting the mainboard controller collapses the Tofino PDN, so
+            // insist that Tofino is already off and will stay that way.
+            if self.tofino.policy != TofinoSequencerPolicy::Disabled
+                || self.tofino.sequencer.state().map_err(SeqError::from)?
+                    != TofinoSeqState::A2
+            {
+                return Err(SeqError::IllegalTransition.into());
+            }
+
+            ringbuf_entry!(Trace::LoadingStreamedFpgaBitstream);
+            let bitstream = self
+                .mainboard_controller
+                .start_bitstream_load(BitstreamType::Compressed)
+                .map_err(SeqError::from)?;
+            self.bitstream_load = Some(BitstreamLoad {
+                bitstream,
+                offset: 0,
+            });
+        }
+
+        let load = self
+            .bitstream_load
+            .as_mut()
+            .filter(|load| load.offset == offset)
+            .ok_or(SeqError::BadBitstreamChunk)?;
+
+        let mut buf = [0u8; 128];
+        let chunk = &mut buf[..data.len()];
+        data.read_range(0..chunk.len(), chunk)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+
+        if let Err(e) = load.bitstream.continue_load(chunk) {
+            ringbuf_entry!(Trace::FpgaBitstreamError(u32::from(e)));
+            let _ = load.bitstream.cancel_load();
+            self.bitstream_load = None;
+            return Err(SeqError::from(e).into());
+        }
+        load.offset += chunk.len();
+
+        Ok(())
+    }
+
+    fn finish_mainboard_controller_bitstream(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<SeqError>> {
+        let mut load = self
+            .bitstream_load
+            .take()
+            .ok_or(SeqError::BadBitstreamChunk)?;
+
+        if let Err(e) = load.bitstream.finish_load() {
+            ringbuf_entry!(Trace::FpgaBitstreamError(u32::from(e)));
+            return Err(SeqError::from(e).into());
+        }
+        // Release our lock on the FPGA.
+        drop(load);
+
+        let ident = self
+            .mainboard_controller
+            .read_ident()
+            .map_err(SeqError::from)?;
+        if ident.id.get() != MainboardController::EXPECTED_ID {
+            // Don't leave a design we can't talk to running.
+            ringbuf_entry!(Trace::InvalidMainboardControllerId(
+                ident.id.into()
+            ));
+            let _ = self.mainboard_controller.reset();
+            return Err(SeqError::InvalidMainboardControllerId.into());
+        }
+
+        // The checksum registers are deliberately left unset, so that when
+        // this task next starts it finds a mismatch and reloads the bitstream
+        // it was built with.
+        ringbuf_entry!(Trace::StreamedFpgaBitstreamLoaded);
+        ringbuf_entry!(Trace::MainboardControllerChecksum(
+            ident.checksum.into()
+        ));
+        ringbuf_entry!(Trace::MainboardControllerVersion(ident.version.into()));
+        ringbuf_entry!(Trace::MainboardControllerSha(ident.sha.into()));
+        Ok(())
+    }
+
+    fn clock_config_failure(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<Option<ClockConfigFailure>, RequestError<SeqError>> {
+        Ok(self.clock_generator.last_failure)
+    }
+
+    fn device_init_status(
+        &mut self,
+        _: &RecvMessage,
+        device: DeviceInit,
+    ) -> Result<DeviceInitStatus, RequestError<Infallible>> {
+        Ok(self.device_inits.status(device))
+    }
+
+    fn rerun_device_init(
+        &mut self,
+        _: &RecvMessage,
+        device: DeviceInit,
+    ) -> Result<DeviceInitStatus, RequestError<SeqError>> {
+        Ok(self.run_device_init(device)?)
+    }
+
     fn front_io_board_present(
         &mut self,
         _: &RecvMessage,
@@ -755,10 +1015,40 @@ impl idl::InOrderSequencerImpl for ServerImpl {
 
 impl NotificationHandler for ServerImpl {
     fn current_notification_mask(&self) -> u32 {
-        notifications::TIMER_MASK
+        notifications::TIMER_MASK | FAULT_IRQ_MASK
     }
 
-    fn handle_notification(&mut self, _bits: u32) {
+    fn handle_notification(&mut self, bits: u32) {
+        // The mainboard controller isn't running its design while a bitstream
+        // is being streamed into it, so there's nothing to be done until the
+        // load is finished.
+        if self.bitstream_load.is_some() {
+            if bits & FAULT_IRQ_MASK != 0 {
+                enable_fault_irq();
+            }
+            if bits & notifications::TIMER_MASK != 0 {
+                sys_set_timer(
+                    Some(sys_get_timer().now + TIMER_INTERVAL),
+                    notifications::TIMER_MASK,
+                );
+            }
+            return;
+        }
+
+        // Faults are handled as soon as they're signalled, rather than waiting
+        // for the next tick.
+        if bits & FAULT_IRQ_MASK != 0 {
+            ringbuf_entry!(Trace::TofinoFaultIrq);
+            if let Err(e) = self.tofino.check_faults() {
+                ringbuf_entry!(Trace::TofinoSequencerError(e));
+            }
+            enable_fault_irq();
+
+            if bits & notifications::TIMER_MASK == 0 {
+                return;
+            }
+        }
+
         let start = sys_get_timer().now;
 
         // Determine if the front IO board has been initialized and no further
@@ -774,6 +1064,10 @@ impl NotificationHandler for ServerImpl {
             ringbuf_entry!(Trace::TofinoSequencerError(e));
         }
 
+        if let Err(e) = self.tofino.check_faults() {
+            ringbuf_entry!(Trace::TofinoSequencerError(e));
+        }
+
         // Change status of LED blink variable, keeping anything gating on/off
         // with it in phase
         self.led_blink_on = !self.led_blink_on;
@@ -813,8 +1107,11 @@ fn main() -> ! {
     let fan_modules = FanModules::new(MAINBOARD.get_task_id());
 
     let mut server = ServerImpl {
+        i2c_task,
         mainboard_controller,
+        bitstream_load: None,
         clock_generator,
+        device_inits: DeviceInits::new(),
         tofino,
         front_io_hsc,
         front_io_board: None,
@@ -936,19 +1233,18 @@ fn main() -> ! {
     // TODO (arjen): Implement reset control through the mainboard controller.
     userlib::hl::sleep_for(100);
 
+    // If Tofino was left running by a previous instance of this task, keep it
+    // that way.
     if let TofinoSeqState::A0 = server
         .tofino
         .sequencer
         .state()
         .unwrap_or(TofinoSeqState::Init)
     {
-        ringbuf_entry!(Trace::SkipLoadingClockConfiguration);
-        server.clock_generator.config_loaded = true;
         server.tofino.policy = TofinoSequencerPolicy::LatchOffOnFault;
-    } else if server.clock_generator.load_config().is_err() {
-        panic!()
     }
-    ringbuf_entry!(Trace::ClockConfigurationComplete);
+
+    server.run_device_inits();
 
     // Enable the front IO hot swap controller and probe for a front IO board.
     match server.front_io_board_preinit() {
@@ -977,19 +1273,6 @@ fn main() -> ! {
         Err(_) => panic!("unknown front IO board preinit failure"),
     }
 
-    // Configure the TMP451 attached to the Tofino to trigger its THERM_B
-    // line at 90°C, rather than the default of 108°C.  The THERM_B line
-    // is monitored by the sequencer FPGA and will cut power to the system,
-    // because the Tofino doesn't have built-in protection against thermal
-    // overruns.
-    let tmp451 = drv_i2c_devices::tmp451::Tmp451::new(
-        &i2c_config::devices::tmp451_tf2(i2c_task),
-        drv_i2c_devices::tmp451::Target::Remote,
-    );
-    tmp451
-        .write_reg(drv_i2c_devices::tmp451::Register::RemoteTempThermBLimit, 90)
-        .unwrap_lite();
-
     // Before starting Tofino, we may need to clear sequencer abort state. This
     // will discard fault state when the SP resets, but this is acceptable for
     // now and an incentive to do more automated reporting.
@@ -1009,6 +1292,16 @@ fn main() -> ! {
         server.tofino.policy = TofinoSequencerPolicy::LatchOffOnFault;
     }
 
+    // Listen to the fault line, on boards which route it to the SP.
+    #[cfg(feature = "fault-irq")]
+    {
+        use drv_stm32xx_sys_api::{Edge, Sys};
+
+        Sys::from(SYS.get_task_id())
+            .gpio_irq_configure(FAULT_IRQ_MASK, Edge::Falling);
+        enable_fault_irq();
+    }
+
     //
     // This will put our timer in the past, and should immediately kick us.
     //
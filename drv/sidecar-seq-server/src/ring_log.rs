@@ -0,0 +1,51 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Small fixed-size logs, used to keep the faults which caused Tofino to be
+//! shut down and a history of its power events, so that they can be retrieved
+//! after the fact rather than pieced together from the ringbuf.
+
+pub(crate) struct RingLog<T: Copy, const N: usize> {
+    entries: [Option<T>; N],
+    /// Slot which the next entry will be written to
+    next: usize,
+    /// Number of entries held, which stops growing once the log is full
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> Default for RingLog<T, N> {
+    fn default() -> Self {
+        Self {
+            entries: [None; N],
+            next: 0,
+            len: 0,
+        }
+    }
+}
+
+impl<T: Copy, const N: usize> RingLog<T, N> {
+    /// Records an entry, overwriting the oldest one if the log is full.
+    pub fn record(&mut self, entry: T) {
+        self.entries[self.next] = Some(entry);
+        self.next = (self.next + 1) % N;
+        self.len = usize::min(self.len + 1, N);
+    }
+
+    /// Returns the entry `index` entries back from the most recent one.
+    pub fn get(&self, index: usize) -> Option<T> {
+        if index >= self.len {
+            return None;
+        }
+        let slot = (self.next + N - 1 - index) % N;
+        self.entries[slot]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
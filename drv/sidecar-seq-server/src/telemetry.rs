@@ -0,0 +1,143 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Periodic collection of telemetry from the PMBus regulators powering Tofino.
+
+use crate::*;
+use drv_i2c_devices::{
+    isl68224::Isl68224, raa229618::Raa229618, CurrentSensor, VoltageSensor,
+};
+use drv_sidecar_seq_api::{RailTelemetry, NUM_TOFINO_PMBUS_RAILS};
+
+const RAILS: [TofinoPmbusRail; NUM_TOFINO_PMBUS_RAILS] = [
+    TofinoPmbusRail::VddCore,
+    TofinoPmbusRail::Vddt,
+    TofinoPmbusRail::Vdda15,
+    TofinoPmbusRail::Vdd18,
+    TofinoPmbusRail::Vdda18,
+];
+
+/// STATUS_WORD bits for output overvoltage and overcurrent faults, either of
+/// which means the regulator has shut its output down
+const OUTPUT_FAULTS: u16 = (1 << 5) | (1 << 4);
+
+pub(crate) struct TofinoRailTelemetry {
+    vddcore: Raa229618,
+    vddt: Raa229618,
+    vdda15: Raa229618,
+    vdd18: Isl68224,
+    vdda18: Isl68224,
+    readings: [RailTelemetry; NUM_TOFINO_PMBUS_RAILS],
+}
+
+impl TofinoRailTelemetry {
+    pub fn new(i2c_task: userlib::TaskId) -> Self {
+        let raa229618 =
+            |(device, rail): (I2cDevice, u8)| Raa229618::new(&device, rail);
+        let isl68224 =
+            |(device, rail): (I2cDevice, u8)| Isl68224::new(&device, rail);
+
+        Self {
+            vddcore: raa229618(i2c_config::pmbus::v0p8_tf2_vdd_core(i2c_task)),
+            vddt: raa229618(i2c_config::pmbus::v0p9_tf2_vddt(i2c_task)),
+            vdda15: raa229618(i2c_config::pmbus::v1p5_tf2_vdda(i2c_task)),
+            vdd18: isl68224(i2c_config::pmbus::v1p8_tf2_vdd(i2c_task)),
+            vdda18: isl68224(i2c_config::pmbus::v1p8_tf2_vdda(i2c_task)),
+            readings: [RailTelemetry::default(); NUM_TOFINO_PMBUS_RAILS],
+        }
+    }
+
+    /// Returns the most recent telemetry for the given rail.
+    pub fn reading(&self, rail: TofinoPmbusRail) -> RailTelemetry {
+        self.readings[rail as usize]
+    }
+
+    /// Reads each rail, replacing its previous reading. A rail which can't be
+    /// read keeps its previous reading, the age of which is evident from its
+    /// timestamp.
+    pub fn update(&mut self) {
+        for rail in RAILS {
+            let Some(reading) = self.read(rail) else {
+                ringbuf_entry!(Trace::RailTelemetryReadFailed(rail));
+                continue;
+            };
+
+            // Only trace a non-zero STATUS_WORD when it changes, to avoid
+            // flooding the ringbuf with a persistent warning.
+            let previous = self.readings[rail as usize].status_word;
+            if reading.status_word != 0 && reading.status_word != previous {
+                ringbuf_entry!(Trace::RailStatus {
+                    rail,
+                    status_word: reading.status_word,
+                });
+            }
+
+            self.readings[rail as usize] = reading;
+        }
+    }
+
+    /// Returns the most recent STATUS_WORD of each rail.
+    pub fn status_words(&self) -> [u16; NUM_TOFINO_PMBUS_RAILS] {
+        self.readings.map(|r| r.status_word)
+    }
+
+    /// Returns the first rail whose most recent STATUS_WORD reports an output
+    /// fault, if any.
+    pub fn faulted_rail(&self) -> Option<TofinoPmbusRail> {
+        RAILS.into_iter().find(|&rail| {
+            self.readings[rail as usize].status_word & OUTPUT_FAULTS != 0
+        })
+    }
+
+    /// Clears the faults latched by each regulator, and discards the readings
+    /// which reported them.
+    pub fn clear_faults(&mut self) {
+        let results = [
+            self.vddcore.clear_faults().is_ok(),
+            self.vddt.clear_faults().is_ok(),
+            self.vdda15.clear_faults().is_ok(),
+            self.vdd18.clear_faults().is_ok(),
+            self.vdda18.clear_faults().is_ok(),
+        ];
+        for (rail, ok) in RAILS.into_iter().zip(results) {
+            if !ok {
+                ringbuf_entry!(Trace::RailClearFaultsFailed(rail));
+            }
+        }
+        self.readings = [RailTelemetry::default(); NUM_TOFINO_PMBUS_RAILS];
+    }
+
+    fn read(&self, rail: TofinoPmbusRail) -> Option<RailTelemetry> {
+        let (vout, iout, status_word) = match rail {
+            TofinoPmbusRail::VddCore => read_raa229618(&self.vddcore),
+            TofinoPmbusRail::Vddt => read_raa229618(&self.vddt),
+            TofinoPmbusRail::Vdda15 => read_raa229618(&self.vdda15),
+            TofinoPmbusRail::Vdd18 => read_isl68224(&self.vdd18),
+            TofinoPmbusRail::Vdda18 => read_isl68224(&self.vdda18),
+        }?;
+
+        Some(RailTelemetry {
+            vout,
+            iout,
+            status_word,
+            timestamp: sys_get_timer().now,
+        })
+    }
+}
+
+fn read_raa229618(device: &Raa229618) -> Option<(f32, f32, u16)> {
+    Some((
+        device.read_vout().ok()?.0,
+        device.read_iout().ok()?.0,
+        device.read_status_word().ok()?,
+    ))
+}
+
+fn read_isl68224(device: &Isl68224) -> Option<(f32, f32, u16)> {
+    Some((
+        device.read_vout().ok()?.0,
+        device.read_iout().ok()?.0,
+        device.read_status_word().ok()?,
+    ))
+}
@@ -2,8 +2,64 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use crate::ring_log::RingLog;
+use crate::telemetry::TofinoRailTelemetry;
 use crate::*;
-use drv_i2c_devices::raa229618::Raa229618;
+use drv_i2c_devices::{raa229618::Raa229618, VoltageSensor};
+use drv_sidecar_seq_api::{
+    TofinoEventKind, TOFINO_EVENT_LOG_LEN, TOFINO_FAULT_LOG_LEN,
+};
+use userlib::units::Volts;
+
+/// Time allowed for VDDCORE to slew to a new VID before reading it back
+const VDDCORE_SETTLE_MS: u64 = 2;
+
+/// Maximum allowed difference between the commanded and measured VDDCORE
+const VDDCORE_VOUT_TOLERANCE: Volts = Volts(0.025);
+
+/// Interval at which the sequencer is polled for a valid VID during power up
+const VID_POLL_MS: u64 = 25;
+
+/// Time allowed for the sequencer to present a valid VID during power up
+const VID_TIMEOUT_MS: u64 = 150;
+
+/// VDDCORE voltage below which the rail is considered off after power down
+const VDDCORE_OFF_THRESHOLD: Volts = Volts(0.1);
+
+/// Interval at which the sequencer is polled during power down
+const POWER_DOWN_POLL_MS: u64 = 5;
+
+/// Time allowed for the sequencer to change state during power down. It does
+/// so as soon as it has acted on what it saw, so this only has to cover a few
+/// of our polls.
+const POWER_DOWN_STATE_TIMEOUT_MS: u64 = 50;
+
+/// Time allowed for a rail to be disabled and drop its power good once the
+/// sequencer has moved on to it. This is mostly the time taken for the rail's
+/// output capacitance to discharge below its power good threshold.
+fn rail_power_down_timeout_ms(rail: TofinoPowerRailId) -> u64 {
+    match rail {
+        // VDDCORE supplies by far the most current, and so has by far the
+        // most output capacitance to discharge.
+        TofinoPowerRailId::VddCore => 200,
+        TofinoPowerRailId::Vdd18
+        | TofinoPowerRailId::VddPcie
+        | TofinoPowerRailId::Vddt
+        | TofinoPowerRailId::Vdda15
+        | TofinoPowerRailId::Vdda18 => 50,
+    }
+}
+
+/// Steps of the Tofino power down sequence, as recorded in the ringbuf
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum PowerDownStep {
+    /// The sequencer has left A0, so the VID is no longer valid
+    LeftA0,
+    /// The given rail is disabled and no longer reports power good
+    RailDisabled(TofinoPowerRailId),
+    /// The sequencer has reached A2
+    A2,
+}
 
 pub(crate) struct Tofino {
     pub policy: TofinoSequencerPolicy,
@@ -13,6 +69,15 @@ pub(crate) struct Tofino {
     pub abort_reported: bool,
     pub ready_for_power_up: bool,
     pub pcie_link_up: bool,
+    pub rail_telemetry: TofinoRailTelemetry,
+    pub fault_log: RingLog<TofinoFault, TOFINO_FAULT_LOG_LEN>,
+    pub event_log: RingLog<TofinoEvent, TOFINO_EVENT_LOG_LEN>,
+    /// Sequencer state seen at the previous tick, so that changes to it can be
+    /// recorded in the event log
+    last_state: Option<TofinoSeqState>,
+    /// Set when Tofino has been shut down because of a fault, keeping it in
+    /// A2 until the fault is cleared
+    pub fault_latched: bool,
 }
 
 impl Tofino {
@@ -27,27 +92,41 @@ impl Tofino {
             abort_reported: false,
             ready_for_power_up: false,
             pcie_link_up: false,
+            rail_telemetry: TofinoRailTelemetry::new(i2c_task),
+            fault_log: RingLog::default(),
+            event_log: RingLog::default(),
+            last_state: None,
+            fault_latched: false,
         }
     }
 
     pub fn apply_vid(&mut self, vid: Tofino2Vid) -> Result<(), SeqError> {
-        use userlib::units::Volts;
-
-        let value = Volts(match vid {
-            Tofino2Vid::V0P922 => 0.922,
-            Tofino2Vid::V0P893 => 0.893,
-            Tofino2Vid::V0P867 => 0.867,
-            Tofino2Vid::V0P847 => 0.847,
-            Tofino2Vid::V0P831 => 0.831,
-            Tofino2Vid::V0P815 => 0.815,
-            Tofino2Vid::V0P790 => 0.790,
-            Tofino2Vid::V0P759 => 0.759,
-        });
+        let value = drv_sidecar_seq_api::vid_to_volts(vid);
         self.vddcore
             .set_vout(value)
             .map_err(|_| SeqError::SetVddCoreVoutFailed)?;
 
         ringbuf_entry!(Trace::SetVddCoreVout(value));
+        self.record_event(TofinoEventKind::Vid(vid as u8));
+
+        // Read back the output voltage to make sure the regulator actually
+        // applied the VID, rather than clamping or ignoring it, before we
+        // let the sequencer continue bringing up Tofino.
+        userlib::hl::sleep_for(VDDCORE_SETTLE_MS);
+        let actual = self
+            .vddcore
+            .read_vout()
+            .map_err(|_| SeqError::SetVddCoreVoutFailed)?;
+        let delta = actual.0 - value.0;
+        if delta > VDDCORE_VOUT_TOLERANCE.0 || delta < -VDDCORE_VOUT_TOLERANCE.0
+        {
+            ringbuf_entry!(Trace::VddCoreVoutMismatch {
+                commanded: value,
+                actual
+            });
+            return Err(SeqError::VddCoreMismatch);
+        }
+
         Ok(())
     }
 
@@ -77,18 +156,39 @@ impl Tofino {
             == 0xf)
     }
 
+    /// Powers Tofino up. If this fails partway, Tofino is powered back down,
+    /// so that it's left in A2 rather than some intermediate state.
     pub fn power_up(&mut self) -> Result<(), SeqError> {
+        let result = self.try_power_up();
+        if let Err(e) = result {
+            ringbuf_entry!(Trace::TofinoPowerUpFailed(e));
+            self.record_event(TofinoEventKind::PowerUpFailed);
+            if let Err(e) = self.power_down() {
+                ringbuf_entry!(Trace::TofinoSequencerError(e));
+            }
+        }
+        result
+    }
+
+    fn try_power_up(&mut self) -> Result<(), SeqError> {
         ringbuf_entry!(Trace::TofinoPowerUp);
+        self.record_event(TofinoEventKind::PowerUp);
 
         // Initiate the power up sequence.
         self.abort_reported = false;
         self.sequencer.set_enable(true)?;
 
         // Wait for the VID to become valid, retrying if needed.
-        for i in 1..4 {
+        let deadline = sys_get_timer().now + VID_TIMEOUT_MS;
+        loop {
             // Sleep first since there is a delay between the sequencer
             // receiving the EN bit and the VID being valid.
-            hl::sleep_for(i * 25);
+            hl::sleep_for(VID_POLL_MS);
+
+            // If the sequencer has given up, the VID will never be valid.
+            if let Some(abort) = self.sequencer.status()?.abort {
+                return Err(SeqError::from(abort.error));
+            }
 
             let maybe_vid = self.sequencer.vid().map_err(|e| {
                 if let FpgaError::InvalidValue = e {
@@ -254,13 +354,16 @@ impl Tofino {
 
                 return Ok(());
             }
-        }
 
-        Err(SeqError::SequencerTimeout)
+            if sys_get_timer().now >= deadline {
+                return Err(SeqError::SequencerTimeout);
+            }
+        }
     }
 
     pub fn power_down(&mut self) -> Result<(), SeqError> {
         ringbuf_entry!(Trace::TofinoPowerDown);
+        self.record_event(TofinoEventKind::PowerDown);
         self.set_pcie_present(false)?;
         self.sequencer.set_pcie_reset(TofinoPcieReset::Asserted)?;
         // The deassertion of presence implicitly gates the ability for the
@@ -269,7 +372,155 @@ impl Tofino {
 
         self.sequencer
             .set_enable(false)
-            .map_err(|_| SeqError::SequencerError)
+            .map_err(|_| SeqError::SequencerError)?;
+
+        // The sequencer tears down the PDN in the reverse of the order in
+        // which it brought it up, waiting for each rail to drop before
+        // disabling the next. Follow along, so that a rail which fails to
+        // turn off is caught here rather than back-powering its neighbors
+        // during the next power up.
+        self.await_power_down_step(
+            PowerDownStep::LeftA0,
+            POWER_DOWN_STATE_TIMEOUT_MS,
+            |seq| {
+                let state = seq.status()?.state;
+                Ok(state != TofinoSeqState::A0
+                    && state != TofinoSeqState::InPowerUp)
+            },
+        )?;
+
+        // `power_rails()` is indexed in power up order.
+        let rails = self.sequencer.power_rails()?;
+        for i in (0..rails.len()).rev() {
+            let id = rails[i].id;
+            self.await_power_down_step(
+                PowerDownStep::RailDisabled(id),
+                rail_power_down_timeout_ms(id),
+                |seq| {
+                    let rail = seq.power_rails()?[i];
+                    Ok(rail.status == PowerRailStatus::Disabled
+                        && !rail.pins.enable
+                        && !rail.pins.good)
+                },
+            )?;
+        }
+
+        self.await_power_down_step(
+            PowerDownStep::A2,
+            POWER_DOWN_STATE_TIMEOUT_MS,
+            |seq| Ok(seq.status()?.state == TofinoSeqState::A2),
+        )?;
+
+        // The sequencer only sees the rails' enable and power good pins, so
+        // confirm over PMBus that VDDCORE has actually collapsed. If the
+        // regulator doesn't answer at all, it has lost power along with the
+        // rest of the PDN, which is just as good.
+        if let Ok(vout) = self.vddcore.read_vout() {
+            if vout.0 > VDDCORE_OFF_THRESHOLD.0 {
+                ringbuf_entry!(Trace::VddCoreStillPowered(vout));
+                return Err(SeqError::VddCoreStillPowered);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Polls the sequencer until `done` returns `true`, recording `step` once
+    /// it does. Returns `SeqError::PowerDownTimeout` if this takes longer than
+    /// `timeout_ms`.
+    fn await_power_down_step(
+        &self,
+        step: PowerDownStep,
+        timeout_ms: u64,
+        done: impl Fn(&Sequencer) -> Result<bool, SeqError>,
+    ) -> Result<(), SeqError> {
+        for _ in 0..timeout_ms.div_ceil(POWER_DOWN_POLL_MS) {
+            if done(&self.sequencer)? {
+                ringbuf_entry!(Trace::TofinoPowerDownStep(step));
+                return Ok(());
+            }
+            hl::sleep_for(POWER_DOWN_POLL_MS);
+        }
+
+        ringbuf_entry!(Trace::TofinoPowerDownTimeout(step));
+        Err(SeqError::PowerDownTimeout)
+    }
+
+    /// Checks for an abort by the sequencer or an output fault reported by one
+    /// of the regulators, shutting Tofino down if either is found.
+    pub fn check_faults(&mut self) -> Result<(), SeqError> {
+        let status = self.sequencer.status()?;
+        if let Some(abort) = &status.abort {
+            if !self.abort_reported {
+                self.handle_abort(abort)?;
+            }
+        }
+
+        self.rail_telemetry.update();
+
+        let powered = matches!(
+            status.state,
+            TofinoSeqState::A0 | TofinoSeqState::InPowerUp
+        );
+        if powered && !self.fault_latched {
+            if let Some(rail) = self.rail_telemetry.faulted_rail() {
+                self.protective_shutdown(TofinoFaultSource::Rail(rail))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Clears a latched fault, allowing Tofino to be powered up again.
+    pub fn clear_fault(&mut self) {
+        self.fault_latched = false;
+        self.rail_telemetry.clear_faults();
+        self.record_event(TofinoEventKind::FaultCleared);
+    }
+
+    fn handle_abort(&mut self, abort: &TofinoSeqAbort) -> Result<(), SeqError> {
+        self.abort_reported = true;
+        let reported = self.report_abort(abort);
+
+        // A software abort is the result of us powering Tofino down, rather
+        // than a fault.
+        if abort.error != TofinoSeqError::SoftwareAbort {
+            self.protective_shutdown(TofinoFaultSource::Sequencer)?;
+        }
+
+        reported
+    }
+
+    /// Records a fault in the fault log and powers Tofino down, latching it off
+    /// until the fault is cleared.
+    fn protective_shutdown(
+        &mut self,
+        source: TofinoFaultSource,
+    ) -> Result<(), SeqError> {
+        ringbuf_entry!(Trace::TofinoProtectiveShutdown(source));
+        self.fault_latched = true;
+        self.record_event(TofinoEventKind::Fault(source));
+
+        // Take the snapshot first, since powering down changes what the
+        // sequencer reports. Failing to read it shouldn't prevent shutdown.
+        let abort = self.sequencer.status().ok().and_then(|s| s.abort);
+        self.fault_log.record(TofinoFault {
+            timestamp: sys_get_timer().now,
+            source,
+            seq_error: abort.map_or(0, |a| a.error as u8),
+            seq_error_state: abort.map_or(0, |a| a.state as u8),
+            seq_error_step: abort.map_or(0, |a| a.step as u8),
+            rail_status: self.rail_telemetry.status_words(),
+        });
+
+        self.power_down()
+    }
+
+    fn record_event(&mut self, kind: TofinoEventKind) {
+        self.event_log.record(TofinoEvent {
+            timestamp: sys_get_timer().now,
+            kind,
+        });
     }
 
     pub fn report_abort(
@@ -320,6 +571,14 @@ impl Tofino {
             .abort
             .map_or(TofinoSeqError::None, |abort| abort.error);
 
+        if self.last_state != Some(status.state) {
+            self.last_state = Some(status.state);
+            self.record_event(TofinoEventKind::StateChange {
+                state: status.state as u8,
+                step: status.step as u8,
+            });
+        }
+
         // Determine the link up/down state of the PCIe link. This is only valid
         // in A0 as otherwise the debug port won't properly respond.
         self.pcie_link_up = if status.state == TofinoSeqState::A0 {
@@ -329,10 +588,7 @@ impl Tofino {
         };
 
         match &status.abort {
-            Some(abort) if !self.abort_reported => {
-                self.abort_reported = true;
-                self.report_abort(abort)?;
-            }
+            Some(abort) if !self.abort_reported => self.handle_abort(abort)?,
             _ => {
                 ringbuf_entry!(Trace::TofinoSequencerTick(
                     self.policy,
@@ -367,7 +623,9 @@ impl Tofino {
                 TofinoSequencerPolicy::LatchOffOnFault,
                 TofinoSeqState::A2,
                 TofinoSeqError::None,
-            ) if self.ready_for_power_up => self.power_up(),
+            ) if self.ready_for_power_up && !self.fault_latched => {
+                self.power_up()
+            }
 
             // RestartOnFault not yet implemented because we do not yet know how
             // this should behave. And we probably still want to see/debug if a
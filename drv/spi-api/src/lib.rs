@@ -35,6 +35,28 @@ pub enum SpiError {
     /// Server restarted
     #[idol(server_death)]
     TaskRestarted = 4,
+
+    /// The controller is locked, so its configuration can't be changed, or
+    /// another task is holding CS asserted on the same mux option, or has
+    /// pinned the mux to a different option
+    Busy = 5,
+
+    /// The caller's lock timed out and was released by the server
+    LockExpired = 6,
+
+    /// The transfer was aborted before it completed
+    Aborted = 7,
+
+    /// The CRC received from the device didn't match the data
+    CrcError = 8,
+
+    /// Received bytes were lost because the server couldn't take them fast
+    /// enough, either as a host or in target mode
+    Overrun = 9,
+
+    /// The result of an asynchronous exchange was left uncollected for so
+    /// long that the server threw it away
+    ResultExpired = 10,
 }
 
 impl From<idol_runtime::ServerDeath> for SpiError {
@@ -43,11 +65,22 @@ impl From<idol_runtime::ServerDeath> for SpiError {
     }
 }
 
-impl From<SpiError> for GwSpiError {
-    fn from(value: SpiError) -> Self {
+/// The management gateway protocol's `SpiError` only has the variants that
+/// predate the rest, so the others are handed back for the caller to report
+/// some other way.
+impl TryFrom<SpiError> for GwSpiError {
+    type Error = SpiError;
+
+    fn try_from(value: SpiError) -> Result<Self, Self::Error> {
         match value {
-            SpiError::BadTransferSize => Self::BadTransferSize,
-            SpiError::TaskRestarted => Self::TaskRestarted,
+            SpiError::BadTransferSize => Ok(Self::BadTransferSize),
+            SpiError::TaskRestarted => Ok(Self::TaskRestarted),
+            SpiError::Busy
+            | SpiError::LockExpired
+            | SpiError::Aborted
+            | SpiError::CrcError
+            | SpiError::Overrun
+            | SpiError::ResultExpired => Err(value),
         }
     }
 }
@@ -61,6 +94,54 @@ pub enum CsState {
     Asserted = 1,
 }
 
+/// Divider applied to the SPI kernel clock to produce SCK, for use with
+/// `Spi::set_clock_divider`. The values match the hardware's MBR field.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, zerocopy::AsBytes, FromPrimitive,
+)]
+#[repr(u8)]
+pub enum ClockDivider {
+    Div2 = 0,
+    Div4 = 1,
+    Div8 = 2,
+    Div16 = 3,
+    Div32 = 4,
+    Div64 = 5,
+    Div128 = 6,
+    Div256 = 7,
+}
+
+/// Counters for one device, as returned by `Spi::get_stats`. These start from
+/// zero whenever the server restarts.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Eq,
+    PartialEq,
+    zerocopy::AsBytes,
+    zerocopy::FromBytes,
+)]
+#[repr(C)]
+pub struct SpiDeviceStats {
+    /// Bytes moved by completed transfers, counting both directions once
+    /// (i.e. an exchange of 4 bytes counts as 4)
+    pub bytes: u64,
+    /// Completed transfers
+    pub transfers: u32,
+    /// Transfers that failed for any reason
+    pub errors: u32,
+    /// RX FIFO overruns detected while talking to this device
+    pub overruns: u32,
+    /// Requests for this device refused with `SpiError::Busy` because another
+    /// task was holding CS on the same mux option
+    pub lock_contentions: u32,
+    /// Times the controller had to switch mux options to reach this device
+    pub mux_switches: u32,
+    _pad: u32,
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 pub struct ControllerLock<'a, S: SpiServer>(&'a S);
@@ -131,14 +212,14 @@ impl SpiServer for Spi {
         src: &[u8],
         dest: &mut [u8],
     ) -> Result<(), SpiError> {
-        Spi::exchange(self, device_index, src, dest)
+        Spi::exchange(self, device_index, false, src, dest)
     }
     fn write(&self, device_index: u8, src: &[u8]) -> Result<(), SpiError> {
-        Spi::write(self, device_index, src)
+        Spi::write(self, device_index, false, src)
     }
 
     fn read(&self, device_index: u8, dest: &mut [u8]) -> Result<(), SpiError> {
-        Spi::read(self, device_index, dest)
+        Spi::read(self, device_index, false, dest)
     }
 
     fn lock(
@@ -146,7 +227,9 @@ impl SpiServer for Spi {
         device_index: u8,
         cs_state: CsState,
     ) -> Result<(), idol_runtime::ServerDeath> {
-        Spi::lock(self, device_index, cs_state)
+        // A timeout of 0 holds the lock until it's released, unless the
+        // server has a default timeout configured.
+        Spi::lock(self, device_index, cs_state, 0)
     }
 
     fn release(&self) -> Result<(), idol_runtime::ServerDeath> {
@@ -250,5 +333,124 @@ impl<S: SpiServer> SpiDevice<S> {
     }
 }
 
+impl SpiDevice<Spi> {
+    /// Variant of `lock` that the server will forcibly release if your task
+    /// goes `timeout_ms` without calling it. Each transfer, or calling this
+    /// again, restarts the timer.
+    ///
+    /// If the lock expires, CS is deasserted and the next `read`, `write`, or
+    /// `exchange` from your task will fail with `SpiError::LockExpired`.
+    ///
+    /// This is only available for remote SPI servers, since a lock on an
+    /// embedded `SpiServerCore` can't starve anyone else.
+    pub fn lock_with_timeout(
+        &self,
+        assert_cs: CsState,
+        timeout_ms: u32,
+    ) -> Result<(), idol_runtime::ServerDeath> {
+        self.server.lock(self.device_index, assert_cs, timeout_ms)
+    }
+
+    /// Variant of `exchange` that leaves CS asserted afterwards, so that the
+    /// next transfer to this device continues the same transaction.
+    ///
+    /// This is a lighter-weight alternative to `lock`: the server keeps
+    /// servicing other tasks in between, but refuses transfers to other
+    /// devices on the same mux option with `SpiError::Busy` until the
+    /// transaction is finished by a plain `read`, `write`, or `exchange` (or
+    /// until this task restarts). If the transfer fails, CS is deasserted and
+    /// the transaction is over.
+    ///
+    /// Other devices may be addressed on other mux options in the meantime,
+    /// which switches this device's mux option away; only use this if your
+    /// device tolerates that. Taking a `lock` on the same mux option,
+    /// including by your own task, ends the transaction.
+    pub fn exchange_keep_cs(
+        &self,
+        source: &[u8],
+        sink: &mut [u8],
+    ) -> Result<(), SpiError> {
+        self.server.exchange(self.device_index, true, source, sink)
+    }
+
+    /// Writes `command` followed by `source` under a single CS assertion,
+    /// putting whatever the device sends back after the command into `sink`.
+    /// This avoids copying a command and its payload into one buffer, or
+    /// holding the lock across several transfers.
+    ///
+    /// For a half-duplex device, `sink` is read after both are written.
+    pub fn command_exchange(
+        &self,
+        command: &[u8],
+        source: &[u8],
+        sink: &mut [u8],
+    ) -> Result<(), SpiError> {
+        self.server.command_exchange(
+            self.device_index,
+            false,
+            command,
+            source,
+            sink,
+        )
+    }
+
+    /// Starts exchanging `len` bytes with the device, sending `source` and
+    /// then idle bytes, and returns without waiting. The server posts
+    /// `notification_mask` to us when it's done, after which
+    /// `collect_result` gets the bytes received.
+    ///
+    /// This needs the SPI server's `async-exchange` feature, and `len` is
+    /// limited by the controller's `async_buffer_size`. Until the result is
+    /// collected, other transfers on this controller fail with
+    /// `SpiError::Busy`.
+    pub fn start_exchange(
+        &self,
+        source: &[u8],
+        len: u32,
+        notification_mask: u32,
+    ) -> Result<(), SpiError> {
+        self.server.start_exchange(
+            self.device_index,
+            false,
+            len,
+            notification_mask,
+            source,
+        )
+    }
+
+    /// Finishes an exchange begun with `start_exchange`, putting what was
+    /// received into `sink`, and returns its length. Collect promptly: a
+    /// result that keeps other clients waiting for long is thrown away, and
+    /// this then fails with `SpiError::ResultExpired`.
+    pub fn collect_result(&self, sink: &mut [u8]) -> Result<u32, SpiError> {
+        self.server.collect_result(sink)
+    }
+
+    /// Asserts CS while you hold the lock, waiting out the device's CS setup
+    /// time, for devices that need CS pulsed between commands. CS then stays
+    /// asserted across transfers until `deassert_cs`.
+    pub fn assert_cs(&self) -> Result<(), SpiError> {
+        self.server.assert_cs()
+    }
+
+    /// Deasserts CS while you hold the lock, after waiting out the device's
+    /// CS hold time. The lock is kept.
+    pub fn deassert_cs(&self) -> Result<(), SpiError> {
+        self.server.deassert_cs()
+    }
+
+    /// Variant of `write` that leaves CS asserted afterwards; see
+    /// `exchange_keep_cs`.
+    pub fn write_keep_cs(&self, source: &[u8]) -> Result<(), SpiError> {
+        self.server.write(self.device_index, true, source)
+    }
+
+    /// Variant of `read` that leaves CS asserted afterwards; see
+    /// `exchange_keep_cs`.
+    pub fn read_keep_cs(&self, dest: &mut [u8]) -> Result<(), SpiError> {
+        self.server.read(self.device_index, true, dest)
+    }
+}
+
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
 include!(concat!(env!("OUT_DIR"), "/spi_devices.rs"));
@@ -17,7 +17,7 @@ use serde::{Deserialize, Serialize};
 use attest_data::messages::RecvSprotError as AttestDataSprotError;
 use gateway_messages::{
     RotError, RotWatchdogError as GwRotWatchdogError, SpError,
-    SprocketsError as GwSprocketsErr,
+    SpiError as GwSpiError, SprocketsError as GwSprocketsErr,
     SprotProtocolError as GwSprotProtocolError,
     WatchdogError as GwWatchdogError,
 };
@@ -47,7 +47,10 @@ impl From<SprotError> for SpError {
     fn from(value: SprotError) -> Self {
         match value {
             SprotError::Protocol(e) => Self::Sprot(e.into()),
-            SprotError::Spi(e) => Self::Spi(e.into()),
+            SprotError::Spi(e) => match GwSpiError::try_from(e) {
+                Ok(e) => Self::Spi(e),
+                Err(e) => Self::Sprot(spi_protocol_error(e).into()),
+            },
             SprotError::Update(e) => Self::Update(e.into()),
             SprotError::Sprockets(e) => Self::Sprockets(e.into()),
             SprotError::Watchdog(e) => Self::Watchdog(e.into()),
@@ -59,7 +62,10 @@ impl From<SprotError> for RotError {
     fn from(value: SprotError) -> Self {
         match value {
             SprotError::Protocol(e) => Self::Sprot(e.into()),
-            SprotError::Spi(e) => Self::Spi(e.into()),
+            SprotError::Spi(e) => match GwSpiError::try_from(e) {
+                Ok(e) => Self::Spi(e),
+                Err(e) => Self::Sprot(spi_protocol_error(e).into()),
+            },
             SprotError::Update(e) => Self::Update(e.into()),
             SprotError::Sprockets(e) => Self::Sprockets(e.into()),
             SprotError::Watchdog(e) => Self::Watchdog(e.into()),
@@ -73,6 +79,32 @@ impl From<idol_runtime::ServerDeath> for SprotError {
     }
 }
 
+/// Picks the protocol error to report for an SPI error which the management
+/// gateway and attestation protocols have no variant for.
+fn spi_protocol_error(e: SpiError) -> SprotProtocolError {
+    match e {
+        // Both mean the exchange with the RoT was corrupted, as the protocol
+        // errors do.
+        SpiError::Overrun => SprotProtocolError::FlowError,
+        SpiError::CrcError => SprotProtocolError::InvalidCrc,
+        // Only the standalone SPI server returns this to sprot, when another
+        // task has CS asserted or the mux pinned, so we couldn't assert ours.
+        SpiError::Busy => SprotProtocolError::CannotAssertCSn,
+        // Sprot never takes a lock with a timeout or gives the SPI core an
+        // abort notification, so these can't happen; if they somehow do, say
+        // so rather than claim something else went wrong.
+        SpiError::LockExpired | SpiError::Aborted => {
+            SprotProtocolError::UnexpectedResponse
+        }
+        // Sprot doesn't use asynchronous exchanges either.
+        SpiError::ResultExpired => SprotProtocolError::UnexpectedResponse,
+        // These have counterparts everywhere, and aren't passed here.
+        SpiError::BadTransferSize | SpiError::TaskRestarted => {
+            SprotProtocolError::UnexpectedResponse
+        }
+    }
+}
+
 /// Sprot protocol specific errors
 #[derive(
     Copy,
@@ -348,6 +380,15 @@ impl From<AttestOrSprotError> for AttestDataSprotError {
                 SprotError::Spi(e1) => match e1 {
                     SpiError::BadTransferSize => Self::SpiBadTransferSize,
                     SpiError::TaskRestarted => Self::SpiTaskRestarted,
+                    SpiError::Busy
+                    | SpiError::LockExpired
+                    | SpiError::Aborted
+                    | SpiError::CrcError
+                    | SpiError::Overrun
+                    | SpiError::ResultExpired => AttestOrSprotError::Sprot(
+                        SprotError::Protocol(spi_protocol_error(e1)),
+                    )
+                    .into(),
                 },
                 // We should never return these but it's safer to return an
                 // enum just in case these come up
@@ -48,6 +48,86 @@ pub struct Ethernet {
     mdio_timer_irq_mask: u32,
 }
 
+/// Divider from the AHB clock (HCLK) to the MDIO clock (MDC), i.e. the `CR`
+/// field of `ETH_MACMDIOAR`.
+///
+/// The resulting MDC frequency is HCLK divided by the variant's number, so at
+/// our usual ~200 MHz HCLK, `Div102` gives an MDC of about 1.96 MHz.  IEEE
+/// 802.3 limits MDC to 2.5 MHz, and the reference manual pairs each of the
+/// `Div16` through `Div124` dividers with an HCLK range that keeps it there;
+/// the `Div4` through `Div18` dividers will exceed that limit at our clock
+/// speed, and should only be used with PHYs that are known to tolerate a
+/// faster MDC.
+///
+/// The same divider is used as the prescaler for the MDIO timer, so that
+/// `smi_timer_wait` counts in MDC periods (assuming the timer is clocked at
+/// roughly HCLK).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MdcDivider {
+    /// For HCLK of 20-35 MHz
+    Div16,
+    /// For HCLK of 35-60 MHz
+    Div26,
+    /// For HCLK of 60-100 MHz
+    Div42,
+    /// For HCLK of 100-150 MHz
+    Div62,
+    /// For HCLK of 150-250 MHz
+    Div102,
+    /// For HCLK of 250-300 MHz
+    Div124,
+    Div4,
+    Div6,
+    Div8,
+    Div10,
+    Div12,
+    Div14,
+    Div18,
+}
+
+impl MdcDivider {
+    /// The conservative default, suitable for any PHY at ~200 MHz HCLK
+    pub const DEFAULT: Self = Self::Div102;
+
+    /// Returns the value of the `CR` field that selects this divider
+    fn cr_bits(self) -> u8 {
+        match self {
+            Self::Div42 => 0b0000,
+            Self::Div62 => 0b0001,
+            Self::Div16 => 0b0010,
+            Self::Div26 => 0b0011,
+            Self::Div102 => 0b0100,
+            Self::Div124 => 0b0101,
+            Self::Div4 => 0b1000,
+            Self::Div6 => 0b1001,
+            Self::Div8 => 0b1010,
+            Self::Div10 => 0b1011,
+            Self::Div12 => 0b1100,
+            Self::Div14 => 0b1101,
+            Self::Div18 => 0b1111,
+        }
+    }
+
+    /// Returns the ratio of HCLK to MDC
+    pub fn divisor(self) -> u16 {
+        match self {
+            Self::Div4 => 4,
+            Self::Div6 => 6,
+            Self::Div8 => 8,
+            Self::Div10 => 10,
+            Self::Div12 => 12,
+            Self::Div14 => 14,
+            Self::Div16 => 16,
+            Self::Div18 => 18,
+            Self::Div26 => 26,
+            Self::Div42 => 42,
+            Self::Div62 => 62,
+            Self::Div102 => 102,
+            Self::Div124 => 124,
+        }
+    }
+}
+
 /// As the name implies, this spins until a predicate becomes true, in a crappy
 /// way.
 ///
@@ -73,6 +153,9 @@ impl Ethernet {
     ///
     /// We might want to fold these operations into `new` in the future, but for
     /// now, you need to do them separately.
+    ///
+    /// `mdc_divider` selects the MDIO clock speed; see [`MdcDivider`].
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         mac: &'static device::ethernet_mac::RegisterBlock,
         mtl: &'static device::ethernet_mtl::RegisterBlock,
@@ -81,6 +164,7 @@ impl Ethernet {
         rx_ring: crate::ring::RxRing,
         mdio_timer: &'static device::tim16::RegisterBlock,
         mdio_timer_irq_mask: u32,
+        mdc_divider: MdcDivider,
     ) -> Self {
         // The DMA register block contains the soft-reset for the entire system.
         // We need to do this soft-reset even straight out of chip reset,
@@ -93,11 +177,9 @@ impl Ethernet {
 
         // Okay, we have a freshly reset Ethernet controller.
 
-        // Configure the MDIO clock divider. TODO: this divider is currently
-        // hardcoded assuming a ~200MHz AHB frequency.
-        const MDIOAR_CR_DIVIDE_BY_102: u8 = 0b0100;
+        // Configure the MDIO clock divider.
         mac.macmdioar
-            .write(|w| unsafe { w.cr().bits(MDIOAR_CR_DIVIDE_BY_102) });
+            .write(|w| unsafe { w.cr().bits(mdc_divider.cr_bits()) });
         // Program the DMA bus interface parameters. Early versions of the
         // reference manual contained burst length control bits here, but they
         // appear to have been defeatured in later editions, so we'll just do
@@ -247,9 +329,12 @@ impl Ethernet {
             w
         });
         // Configure the timer's prescaler to use the same factor we chose for
-        // MDIO, above. TODO: this may need to be scaled as the reference clocks
-        // may not be the same.
-        mdio_timer.psc.write(|w| w.psc().bits(102));
+        // MDIO, above. The timer divides by `psc + 1`, which gives us a little
+        // extra padding. TODO: this may need to be scaled as the reference
+        // clocks may not be the same.
+        mdio_timer
+            .psc
+            .write(|w| w.psc().bits(mdc_divider.divisor()));
 
         Self {
             mac,
@@ -0,0 +1,421 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Pin-level side of the STM32H7 SPI server: asserting CS, and switching the
+//! controller between mux options.
+//!
+//! This is kept apart from `drv-stm32h7-spi-server-core`, and only needs the
+//! GPIO types, so that it can be tested on the host against a [`PinSys`] that
+//! records what it's asked to do.
+
+#![cfg_attr(not(test), no_std)]
+
+use drv_stm32xx_gpio_common::{
+    Alternate, Mode, OutputType, PinSet, Port, Pull, Speed,
+};
+
+/// The GPIO operations that the SPI server needs from the system, for driving
+/// CS and mux pins.
+pub trait PinSys {
+    /// Sets the pins in `set` high and those in `reset` low.
+    fn gpio_set_reset(&self, port: Port, set: u16, reset: u16);
+
+    #[allow(clippy::too_many_arguments)]
+    fn gpio_configure(
+        &self,
+        port: Port,
+        pins: u16,
+        mode: Mode,
+        output_type: OutputType,
+        speed: Speed,
+        pull: Pull,
+        af: Alternate,
+    );
+
+    fn gpio_set(&self, pinset: PinSet) {
+        self.gpio_set_reset(pinset.port, pinset.pin_mask, 0);
+    }
+
+    fn gpio_reset(&self, pinset: PinSet) {
+        self.gpio_set_reset(pinset.port, 0, pinset.pin_mask);
+    }
+
+    fn gpio_configure_output(
+        &self,
+        pinset: PinSet,
+        output_type: OutputType,
+        speed: Speed,
+        pull: Pull,
+    ) {
+        self.gpio_configure(
+            pinset.port,
+            pinset.pin_mask,
+            Mode::Output,
+            output_type,
+            speed,
+            pull,
+            Alternate::AF0, // doesn't matter
+        );
+    }
+
+    fn gpio_configure_input(&self, pinset: PinSet, pull: Pull) {
+        self.gpio_configure(
+            pinset.port,
+            pinset.pin_mask,
+            Mode::Input,
+            OutputType::PushPull, // doesn't matter
+            Speed::High,          // doesn't matter
+            pull,
+            Alternate::AF0, // doesn't matter
+        );
+    }
+}
+
+#[cfg(target_os = "none")]
+impl PinSys for drv_stm32xx_sys_api::Sys {
+    fn gpio_set_reset(&self, port: Port, set: u16, reset: u16) {
+        drv_stm32xx_sys_api::Sys::gpio_set_reset(self, port, set, reset)
+    }
+
+    fn gpio_configure(
+        &self,
+        port: Port,
+        pins: u16,
+        mode: Mode,
+        output_type: OutputType,
+        speed: Speed,
+        pull: Pull,
+        af: Alternate,
+    ) {
+        drv_stm32xx_sys_api::Sys::gpio_configure(
+            self,
+            port,
+            pins,
+            mode,
+            output_type,
+            speed,
+            pull,
+            af,
+        )
+    }
+}
+
+/// A routing of the SPI controller onto pins.
+#[derive(Copy, Clone, Debug)]
+pub struct SpiMuxOption {
+    /// A list of config changes to apply to activate the output pins of this
+    /// mux option. This is a list because some mux options are spread across
+    /// multiple ports, or (in at least one case) the pins in the same port
+    /// require different AF numbers to work.
+    ///
+    /// To disable the mux, we'll force these pins to SCK's idle level for the
+    /// device last used, which is low for SPI modes 0/1 and high for 2/3.
+    pub outputs: &'static [(PinSet, Alternate)],
+    /// A list of config changes to apply to activate the input pins of this mux
+    /// option. This is _not_ a list because there's only one such pin, CIPO.
+    /// Mux options used only by half-duplex devices, which receive on COPI,
+    /// may leave this out.
+    ///
+    /// To disable the mux, we'll switch this pin to HiZ.
+    pub input: Option<(PinSet, Alternate)>,
+    /// Pins beyond COPI and SCK that must be switched to their alternate
+    /// function along with the outputs, e.g. IO2/IO3 for a quad-capable flash.
+    /// These are kept separate from `outputs` so that we can still check that
+    /// the outputs are exactly one COPI and one SCK.
+    ///
+    /// To disable the mux, we'll drive these pins high, which is the inactive
+    /// state for WP# and HOLD#.
+    pub extra_pins: &'static [(PinSet, Alternate)],
+    /// Swap data lines?
+    pub swap_data: bool,
+}
+
+/// Asserts (resets) the CS pins in `cs`.
+pub fn assert_cs(cs: &[PinSet], gpio: &impl PinSys) {
+    for &pin in cs {
+        gpio.gpio_reset(pin);
+    }
+}
+
+/// Deasserts (sets) the CS pins in `cs`.
+pub fn deassert_cs(cs: &[PinSet], gpio: &impl PinSys) {
+    for &pin in cs {
+        gpio.gpio_set(pin);
+    }
+}
+
+/// Returns a mux option's pins to GPIO. `sck_idle_high` is the idle level of
+/// SCK in the clock mode of the device last used, so that SCK stays where that
+/// device expects it rather than making an edge as the pins switch over.
+pub fn deactivate_mux_option(
+    opt: &SpiMuxOption,
+    sck_idle_high: bool,
+    gpio: &impl PinSys,
+) {
+    // Drive all output pins to SCK's idle level. The outputs don't say which
+    // pin is SCK, but COPI's level doesn't matter while CS is deasserted.
+    for &(pins, _af) in opt.outputs {
+        if sck_idle_high {
+            gpio.gpio_set(pins);
+        } else {
+            gpio.gpio_reset(pins);
+        }
+        gpio.gpio_configure_output(
+            pins,
+            OutputType::PushPull,
+            Speed::Low,
+            Pull::None,
+        );
+    }
+    // Drive extra pins high, which is the inactive state for WP# and HOLD#.
+    for &(pins, _af) in opt.extra_pins {
+        gpio.gpio_set(pins);
+        gpio.gpio_configure_output(
+            pins,
+            OutputType::PushPull,
+            Speed::Low,
+            Pull::None,
+        );
+    }
+    // Switch input pin away from SPI peripheral to a GPIO input, which makes it
+    // Hi-Z.
+    if let Some((pin, _af)) = opt.input {
+        gpio.gpio_configure_input(pin, Pull::None);
+    }
+}
+
+/// Switches `opt`'s pins to the SPI peripheral.
+pub fn route_mux_option(opt: &SpiMuxOption, gpio: &impl PinSys) {
+    // Switch all outputs to the SPI peripheral.
+    for &(pins, af) in opt.outputs {
+        gpio.gpio_configure(
+            pins.port,
+            pins.pin_mask,
+            Mode::Alternate,
+            OutputType::PushPull,
+            Speed::Low,
+            Pull::None,
+            af,
+        );
+    }
+    // Any extra pins (e.g. quad data lines) go along with the outputs.
+    for &(pins, af) in opt.extra_pins {
+        gpio.gpio_configure(
+            pins.port,
+            pins.pin_mask,
+            Mode::Alternate,
+            OutputType::PushPull,
+            Speed::Low,
+            Pull::None,
+            af,
+        );
+    }
+    // And the input too, if there is one.
+    if let Some((pin, af)) = opt.input {
+        gpio.gpio_configure(
+            pin.port,
+            pin.pin_mask,
+            Mode::Alternate,
+            OutputType::PushPull, // doesn't matter
+            Speed::High,          // doesn't matter
+            Pull::None,
+            af,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::vec::Vec;
+
+    /// A GPIO-level side effect, as seen by `RecordingSys`.
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    enum Event {
+        SetReset {
+            port: Port,
+            set: u16,
+            reset: u16,
+        },
+        Configure {
+            port: Port,
+            pins: u16,
+            mode: Mode,
+            af: Alternate,
+        },
+    }
+
+    /// `PinSys` that records the GPIO operations it's asked to do instead of
+    /// doing them.
+    #[derive(Default)]
+    struct RecordingSys {
+        events: RefCell<Vec<Event>>,
+    }
+
+    impl RecordingSys {
+        fn take(&self) -> Vec<Event> {
+            self.events.take()
+        }
+    }
+
+    impl PinSys for RecordingSys {
+        fn gpio_set_reset(&self, port: Port, set: u16, reset: u16) {
+            self.events
+                .borrow_mut()
+                .push(Event::SetReset { port, set, reset });
+        }
+
+        fn gpio_configure(
+            &self,
+            port: Port,
+            pins: u16,
+            mode: Mode,
+            _output_type: OutputType,
+            _speed: Speed,
+            _pull: Pull,
+            af: Alternate,
+        ) {
+            self.events.borrow_mut().push(Event::Configure {
+                port,
+                pins,
+                mode,
+                af,
+            });
+        }
+    }
+
+    const MUX_A: SpiMuxOption = SpiMuxOption {
+        outputs: &[(Port::A.pins([5, 7]), Alternate::AF5)],
+        input: Some((Port::A.pin(6), Alternate::AF5)),
+        extra_pins: &[],
+        swap_data: false,
+    };
+
+    const MUX_B: SpiMuxOption = SpiMuxOption {
+        outputs: &[(Port::B.pins([3, 5]), Alternate::AF6)],
+        input: None,
+        extra_pins: &[(Port::B.pins([6, 7]), Alternate::AF6)],
+        swap_data: false,
+    };
+
+    #[test]
+    fn cs_assert_and_deassert() {
+        let sys = RecordingSys::default();
+        let cs = [Port::C.pin(4)];
+
+        assert_cs(&cs, &sys);
+        assert_eq!(
+            sys.take(),
+            [Event::SetReset {
+                port: Port::C,
+                set: 0,
+                reset: 1 << 4,
+            }]
+        );
+
+        deassert_cs(&cs, &sys);
+        assert_eq!(
+            sys.take(),
+            [Event::SetReset {
+                port: Port::C,
+                set: 1 << 4,
+                reset: 0,
+            }]
+        );
+    }
+
+    #[test]
+    fn deactivate_parks_sck_at_idle_level() {
+        for (idle_high, set, reset) in
+            [(false, 0, 0b1010_0000), (true, 0b1010_0000, 0)]
+        {
+            let sys = RecordingSys::default();
+            deactivate_mux_option(&MUX_A, idle_high, &sys);
+            assert_eq!(
+                sys.take(),
+                [
+                    Event::SetReset {
+                        port: Port::A,
+                        set,
+                        reset,
+                    },
+                    Event::Configure {
+                        port: Port::A,
+                        pins: 0b1010_0000,
+                        mode: Mode::Output,
+                        af: Alternate::AF0,
+                    },
+                    Event::Configure {
+                        port: Port::A,
+                        pins: 1 << 6,
+                        mode: Mode::Input,
+                        af: Alternate::AF0,
+                    },
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn mux_switch() {
+        let sys = RecordingSys::default();
+        deactivate_mux_option(&MUX_A, false, &sys);
+        route_mux_option(&MUX_B, &sys);
+        assert_eq!(
+            sys.take(),
+            [
+                // Old option's outputs parked low...
+                Event::SetReset {
+                    port: Port::A,
+                    set: 0,
+                    reset: 0b1010_0000,
+                },
+                Event::Configure {
+                    port: Port::A,
+                    pins: 0b1010_0000,
+                    mode: Mode::Output,
+                    af: Alternate::AF0,
+                },
+                // ...and its input made Hi-Z.
+                Event::Configure {
+                    port: Port::A,
+                    pins: 1 << 6,
+                    mode: Mode::Input,
+                    af: Alternate::AF0,
+                },
+                // New option's outputs and extra pins go to the peripheral.
+                Event::Configure {
+                    port: Port::B,
+                    pins: 0b0010_1000,
+                    mode: Mode::Alternate,
+                    af: Alternate::AF6,
+                },
+                Event::Configure {
+                    port: Port::B,
+                    pins: 0b1100_0000,
+                    mode: Mode::Alternate,
+                    af: Alternate::AF6,
+                },
+            ]
+        );
+
+        // Switching back parks the extra pins high, the inactive state for
+        // WP# and HOLD#.
+        deactivate_mux_option(&MUX_B, false, &sys);
+        let events = sys.take();
+        assert!(events.contains(&Event::SetReset {
+            port: Port::B,
+            set: 0b1100_0000,
+            reset: 0,
+        }));
+        assert!(!events.iter().any(|e| matches!(
+            e,
+            Event::Configure {
+                mode: Mode::Input,
+                ..
+            }
+        )));
+    }
+}
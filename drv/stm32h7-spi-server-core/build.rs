@@ -6,8 +6,10 @@ use anyhow::{anyhow, bail, Result};
 use build_spi::*;
 use indexmap::IndexMap;
 use quote::ToTokens;
+use serde::Deserialize;
 use std::collections::BTreeMap;
 use std::io::Write;
+use std::ops::Range;
 
 fn main() -> Result<()> {
     build_util::expose_target_board();
@@ -37,7 +39,23 @@ fn main() -> Result<()> {
 
     let global_config = build_util::config::<SpiGlobalConfig>()?;
     check_spi_config(&global_config.spi, &spi)?;
-    generate_spi_config(&global_config.spi, &spi)?;
+    let dma_region = check_dma_config(
+        &global_config.spi,
+        &spi,
+        &full_task_config.uses,
+        std::env::var("CARGO_FEATURE_DMA").is_ok(),
+    )?;
+    check_target_config(
+        &global_config.spi,
+        &spi,
+        std::env::var("CARGO_FEATURE_TARGET").is_ok(),
+    )?;
+    check_async_config(
+        &global_config.spi,
+        &spi,
+        std::env::var("CARGO_FEATURE_ASYNC_EXCHANGE").is_ok(),
+    )?;
+    generate_spi_config(&global_config.spi, &spi, dma_region)?;
 
     Ok(())
 }
@@ -57,6 +75,7 @@ fn main() -> Result<()> {
 fn generate_spi_config(
     config: &BTreeMap<String, SpiConfig>,
     global_config: &str,
+    dma_region: Option<Range<u32>>,
 ) -> Result<()> {
     let config = config.get(global_config).ok_or_else(|| {
         anyhow!("reference to undefined spi config {}", global_config)
@@ -68,6 +87,14 @@ fn generate_spi_config(
     let mut out = std::fs::File::create(&dest_path)?;
 
     writeln!(out, "{}", config.to_token_stream())?;
+    if let Some(r) = dma_region {
+        writeln!(
+            out,
+            "/// Bounds of the memory that the `spi_dma` section is placed in\n\
+             const DMA_REGION: core::ops::Range<u32> = {:#x}..{:#x};",
+            r.start, r.end
+        )?;
+    }
 
     drop(out);
 
@@ -123,20 +150,117 @@ fn check_spi_config(
         ));
     }
 
+    if let Some(size) = config.lease_buffer_size {
+        if size == 0 || size > 256 {
+            return Err(anyhow!(
+                "lease_buffer_size is {size}, but must be 1-256 bytes"
+            ));
+        }
+    }
+
+    let min_div = config.min_clock_divider.unwrap_or(ClockDivider::DIV2);
+    let max_div = config.max_clock_divider.unwrap_or(ClockDivider::DIV256);
+    if min_div > max_div {
+        return Err(anyhow!(
+            "min_clock_divider {min_div:?} is larger than \
+             max_clock_divider {max_div:?}"
+        ));
+    }
+
     for mux in config.mux_options.values() {
         for out in &mux.outputs {
             check_afpinset(out)?;
         }
-        check_afpin(&mux.input)?;
+        if let Some(input) = &mux.input {
+            check_afpin(input)?;
+        }
+        for extra in &mux.extra_pins {
+            check_afpinset(extra)?;
+        }
     }
 
     for (devname, dev) in &config.devices {
-        if !config.mux_options.contains_key(&dev.mux) {
+        let Some(mux) = config.mux_options.get(&dev.mux) else {
             return Err(anyhow!(
                 "device {} names undefined mux {}",
                 devname,
                 dev.mux
             ));
+        };
+        if mux.input.is_none() && dev.comm_mode != CommMode::HalfDuplex {
+            return Err(anyhow!(
+                "device {} is full-duplex, but mux {} has no input pin",
+                devname,
+                dev.mux
+            ));
+        }
+
+        if dev.clock_divider < min_div || dev.clock_divider > max_div {
+            return Err(anyhow!(
+                "device {} uses clock divider {:?}, but this controller only \
+                 allows {:?} thru {:?}",
+                devname,
+                dev.clock_divider,
+                min_div,
+                max_div
+            ));
+        }
+
+        if dev.inter_frame_gap_cycles > 15 {
+            return Err(anyhow!(
+                "device {} inter_frame_gap_cycles is {}, but must be 0-15",
+                devname,
+                dev.inter_frame_gap_cycles
+            ));
+        }
+
+        if ![8, 16, 32].contains(&dev.frame_size) {
+            return Err(anyhow!(
+                "device {} frame_size is {}, but must be 8, 16, or 32 bits",
+                devname,
+                dev.frame_size
+            ));
+        }
+        if dev.frame_size != 8 && dev.comm_mode == CommMode::HalfDuplex {
+            return Err(anyhow!(
+                "device {} is half-duplex, which only supports 8-bit frames",
+                devname
+            ));
+        }
+
+        // The CRC is always 8 bits, and the reference manual requires the CRC
+        // length to be compatible with the frame size.
+        if dev.crc_poly.is_some() && dev.frame_size != 8 {
+            return Err(anyhow!(
+                "device {} uses a CRC, which only supports 8-bit frames",
+                devname
+            ));
+        }
+
+        if dev.bit_order == BitOrder::LsbFirst && dev.frame_size != 8 {
+            return Err(anyhow!(
+                "device {} is LSB-first, which only supports 8-bit frames",
+                devname
+            ));
+        }
+
+        // TI frame format fixes these, so other values would be ignored.
+        if dev.protocol == SpiProtocol::Ti
+            && (dev.spi_mode != 0 || dev.bit_order != BitOrder::MsbFirst)
+        {
+            return Err(anyhow!(
+                "device {} uses TI frame format, which requires spi_mode 0 \
+                 and MSB-first bit order",
+                devname
+            ));
+        }
+
+        if dev.spi_mode > 3 {
+            return Err(anyhow!(
+                "device {} spi_mode is {}, but must be 0-3",
+                devname,
+                dev.spi_mode
+            ));
         }
 
         for pin in &dev.cs {
@@ -147,6 +271,162 @@ fn check_spi_config(
     Ok(())
 }
 
+/// The memory that one of the task's sections is placed in
+#[derive(Deserialize)]
+struct SectionRegion {
+    address: u32,
+    size: u32,
+    dma: bool,
+}
+
+/// Checks the `dma` section of the SPI config, returning the bounds of the
+/// memory that the bounce buffers are placed in if DMA is enabled.
+fn check_dma_config(
+    config: &BTreeMap<String, SpiConfig>,
+    global_config: &str,
+    uses: &[String],
+    feature_enabled: bool,
+) -> Result<Option<Range<u32>>> {
+    let config = config.get(global_config).ok_or_else(|| {
+        anyhow!("reference to undefined spi config {}", global_config)
+    })?;
+
+    let dma = match (&config.dma, feature_enabled) {
+        (Some(dma), true) => dma,
+        (None, false) => return Ok(None),
+        (Some(_), false) => {
+            bail!("spi config has a dma section, but the 'dma' feature is off")
+        }
+        (None, true) => {
+            bail!("'dma' feature is enabled, but the spi config has no dma")
+        }
+    };
+
+    if dmamux_requests(config.controller).is_none() {
+        bail!("DMA is not supported on spi{}", config.controller);
+    }
+    if dma.controller != 1 && dma.controller != 2 {
+        bail!(
+            "bad DMA controller {}, valid values are 1 and 2",
+            dma.controller
+        );
+    }
+    if dma.tx_stream > 7 || dma.rx_stream > 7 {
+        bail!("DMA streams are numbered 0-7");
+    }
+    if dma.tx_stream == dma.rx_stream {
+        bail!("DMA tx_stream and rx_stream must be different");
+    }
+    let size = dma.buffer_size();
+    if size == 0 || size > usize::from(u16::MAX) {
+        bail!("DMA buffer_size is {size}, but must be 1-65535 bytes");
+    }
+
+    for p in [format!("dma{}", dma.controller), "dmamux1".to_string()] {
+        if !uses.contains(&p) {
+            bail!("DMA requires the task to use peripheral '{p}'");
+        }
+    }
+
+    // The bounce buffers must be somewhere the DMA can reach, and which the
+    // kernel maps uncached, since nothing cleans or invalidates the D-cache
+    // around a transfer. Memories marked `dma = true` satisfy both.
+    let regions = build_util::task_section_regions::<SectionRegion>()?;
+    let Some(region) = regions.get("spi_dma") else {
+        bail!(
+            "DMA requires the task to map its 'spi_dma' section, e.g. \
+             sections = {{spi_dma = \"sram1\"}}"
+        );
+    };
+    if !region.dma {
+        bail!("the 'spi_dma' section must be in a memory with dma = true");
+    }
+
+    Ok(Some(region.address..region.address + region.size))
+}
+
+fn check_target_config(
+    config: &BTreeMap<String, SpiConfig>,
+    global_config: &str,
+    feature_enabled: bool,
+) -> Result<()> {
+    let config = config.get(global_config).ok_or_else(|| {
+        anyhow!("reference to undefined spi config {}", global_config)
+    })?;
+
+    let target = match (&config.target, feature_enabled) {
+        (Some(target), true) => target,
+        (None, false) => return Ok(()),
+        (Some(_), false) => {
+            bail!(
+                "spi config has a target section, but the 'target' feature \
+                 is off"
+            )
+        }
+        (None, true) => {
+            bail!(
+                "'target' feature is enabled, but the spi config has no target"
+            )
+        }
+    };
+
+    let Some(mux) = config.mux_options.get(&target.mux) else {
+        bail!("target names undefined mux {}", target.mux);
+    };
+    if mux.input.is_none() {
+        bail!("target mux {} needs an input pin for CIPO", target.mux);
+    }
+    if mux.extra_pins.is_empty() {
+        bail!(
+            "target mux {} must route the SS pin to the peripheral in its \
+             extra_pins",
+            target.mux
+        );
+    }
+    if target.spi_mode > 3 {
+        bail!("target spi_mode is {}, but must be 0-3", target.spi_mode);
+    }
+    let size = target.buffer_size();
+    if size == 0 || size > 4096 {
+        bail!("target buffer_size is {size}, but must be 1-4096 bytes");
+    }
+
+    Ok(())
+}
+
+fn check_async_config(
+    config: &BTreeMap<String, SpiConfig>,
+    global_config: &str,
+    feature_enabled: bool,
+) -> Result<()> {
+    let config = config.get(global_config).ok_or_else(|| {
+        anyhow!("reference to undefined spi config {}", global_config)
+    })?;
+
+    let size = match (config.async_buffer_size, feature_enabled) {
+        (Some(size), true) => size,
+        (None, false) => return Ok(()),
+        (Some(_), false) => {
+            bail!(
+                "spi config has an async_buffer_size, but the \
+                 'async-exchange' feature is off"
+            )
+        }
+        (None, true) => {
+            bail!(
+                "'async-exchange' feature is enabled, but the spi config has \
+                 no async_buffer_size"
+            )
+        }
+    };
+    // The whole buffer is moved in one go, so it has to fit in TSIZE.
+    if size == 0 || size > usize::from(u16::MAX) {
+        bail!("async_buffer_size is {size}, but must be 1-65535 bytes");
+    }
+
+    Ok(())
+}
+
 fn check_afpinset(config: &AfPinSetConfig) -> Result<()> {
     for &pin in &config.pins {
         if pin > 15 {
@@ -29,10 +29,49 @@ use stm32h7::stm32h753 as device;
 use userlib::*;
 
 use drv_stm32h7_spi as spi_core;
+use drv_stm32h7_spi_pins::{
+    assert_cs, deactivate_mux_option, deassert_cs, route_mux_option, PinSys,
+    SpiMuxOption,
+};
 use drv_stm32xx_sys_api as sys_api;
+#[cfg(feature = "dma")]
+use spi_core::dma::{Direction, DmaStream};
 use sys_api::PinSet;
 
-use core::{cell::Cell, convert::Infallible};
+use core::{
+    cell::Cell,
+    convert::Infallible,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// The clock, reset, and GPIO operations that the `SpiServerCore` needs from
+/// the system: bringing up its peripheral, and driving CS and mux pins.
+///
+/// Servers use the `sys_api::Sys` impl. The GPIO half lives in
+/// `drv-stm32h7-spi-pins`, so that the pin-level side effects of a transfer (CS
+/// assert/deassert, mux switches) can be tested on the host against a stub
+/// that records them.
+pub trait SpiSys: PinSys + Clone {
+    fn enable_clock(&self, peripheral: sys_api::Peripheral);
+    fn enter_reset(&self, peripheral: sys_api::Peripheral);
+    fn leave_reset(&self, peripheral: sys_api::Peripheral);
+}
+
+impl SpiSys for sys_api::Sys {
+    fn enable_clock(&self, peripheral: sys_api::Peripheral) {
+        sys_api::Sys::enable_clock(self, peripheral)
+    }
+
+    fn enter_reset(&self, peripheral: sys_api::Peripheral) {
+        sys_api::Sys::enter_reset(self, peripheral)
+    }
+
+    fn leave_reset(&self, peripheral: sys_api::Peripheral) {
+        sys_api::Sys::leave_reset(self, peripheral)
+    }
+}
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -52,28 +91,292 @@ use core::{cell::Cell, convert::Infallible};
 /// You probably want to use `declare_spi_core!`, which creates both the SPI
 /// core and the interior-mutable static storage associated with it.
 #[derive(Clone)]
-pub struct SpiServerCore {
+pub struct SpiServerCore<S = sys_api::Sys> {
     spi: spi_core::Spi,
-    sys: sys_api::Sys,
+    sys: S,
     irq_mask: u32,
+    abort_mask: u32,
     lock_holder: &'static Cell<Option<LockState>>, // used by Idol server
     current_mux_index: &'static Cell<usize>,
+    swap_override: &'static Cell<SwapOverride>,
+    /// Indexed by device.
+    clock_overrides: &'static [Cell<ClockOverride>],
+    /// Indexed by device.
+    #[cfg(feature = "stats")]
+    stats: &'static [Cell<SpiDeviceStats>],
+    #[cfg(feature = "dma")]
+    dma: DmaState,
+    #[cfg(feature = "target")]
+    target: TargetState,
+    #[cfg(feature = "async-exchange")]
+    async_state: AsyncState,
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Copy, Clone, PartialEq, counters::Count)]
 enum Trace {
-    Start(#[count(children)] SpiOperation, (u16, u16)),
+    Start(#[count(children)] SpiOperation, (u32, u32)),
+    /// Only recorded with the `spi-byte-trace` feature
     Tx(u8),
+    /// Only recorded with the `spi-byte-trace` feature
     Rx(u8),
     WaitISR(u32),
+    SwapOverride {
+        mux_index: u8,
+        swap: bool,
+    },
+    TransferFailed(TransferError),
+    /// The RX FIFO overflowed during a transfer to this device; `status` is
+    /// the SR value at the time.
+    Overrun {
+        device_index: u8,
+        status: u32,
+    },
+    /// Time (in kernel ticks) from the start of a transfer to its successful
+    /// completion, not including IPC overhead.
+    Duration(u64),
+    Loopback {
+        mux_index: u8,
+        passed: bool,
+    },
+    SelfTest {
+        received: u8,
+        crc: u8,
+        passed: bool,
+    },
+    ClockOverride {
+        device_index: u8,
+        divider: u8,
+    },
+    MuxSwitch {
+        from: u8,
+        to: u8,
+    },
+    /// Armed target mode with this many bytes to send.
+    TargetPrepared(u32),
+    /// The host finished a target-mode transfer.
+    TargetDone {
+        clocked: u32,
+        underrun: bool,
+    },
     #[count(skip)]
     None,
 }
 
 counted_ringbuf!(Trace, 64, Trace::None);
 
+/// Whether to record every byte sent and received as a `Tx`/`Rx` trace entry.
+/// This is useful when debugging a single transfer, but it pushes everything
+/// else out of the ringbuf, so it's off unless the `spi-byte-trace` feature is
+/// enabled.
+const BYTE_TRACE: bool = cfg!(feature = "spi-byte-trace");
+
+/// The most frames the peripheral can be told to move at once, through either
+/// TSIZE or TSER.
+const MAX_TSIZE: u32 = u16::MAX as u32;
+
+/// CPU cycles per microsecond, for the busy-waits that implement CS setup and
+/// hold times. Our H7 boards run the CPU at 400 MHz; if one runs slower, the
+/// delays just come out longer than configured, which is the safe direction.
+const CPU_CYCLES_PER_US: u32 = 400;
+
+/// What the controller is doing right now, mirrored into RAM so that it can
+/// be read out with Humility (`humility readvar SPI_TRANSFER_STATUS`) while
+/// the server is blocked in a transfer, or from a dump after the fact.
+///
+/// IPC to the server is in-order, so it can't answer questions about the
+/// transfer it's in the middle of; this is the only way to tell a server
+/// that's stuck on a device from one that's idle.
+struct TransferStatus {
+    /// Set at the commit point of a transfer and cleared when it ends,
+    /// successfully or not.
+    in_transfer: AtomicBool,
+    /// Index of the device being (or most recently) transferred with.
+    device_index: AtomicU8,
+    /// Number of bytes moved so far. For full-duplex devices this counts
+    /// received bytes; for half-duplex devices it counts both phases.
+    progress: AtomicU32,
+    /// Total number of bytes the transfer will move.
+    len: AtomicU32,
+}
+
+impl TransferStatus {
+    fn start(&self, device_index: u8, len: u32) {
+        self.device_index.store(device_index, Ordering::Relaxed);
+        self.len.store(len, Ordering::Relaxed);
+        self.progress.store(0, Ordering::Relaxed);
+        self.in_transfer.store(true, Ordering::Relaxed);
+    }
+
+    fn set_progress(&self, progress: u32) {
+        self.progress.store(progress, Ordering::Relaxed);
+    }
+
+    fn finish(&self) {
+        self.in_transfer.store(false, Ordering::Relaxed);
+    }
+
+    fn device_index(&self) -> usize {
+        usize::from(self.device_index.load(Ordering::Relaxed))
+    }
+}
+
+#[used]
+static SPI_TRANSFER_STATUS: TransferStatus = TransferStatus {
+    in_transfer: AtomicBool::new(false),
+    device_index: AtomicU8::new(0),
+    progress: AtomicU32::new(0),
+    len: AtomicU32::new(0),
+};
+
+/// DMA streams and their request lines, generated from the `dma` section of
+/// the SPI config.
+#[cfg(feature = "dma")]
+struct DmaConfig {
+    controller: u8,
+    tx_stream: usize,
+    rx_stream: usize,
+    tx_request: u8,
+    rx_request: u8,
+    /// Transfers shorter than this are moved by the CPU.
+    threshold: usize,
+}
+
+/// The streams used for DMA transfers, and the bounce buffers they move data
+/// through. Client leases can't be handed to the DMA directly, and our stack
+/// is in DTCM, which the DMA can't reach, so the buffers live in the
+/// `spi_dma` section. The build script requires that section to be in a
+/// `dma = true` memory, which the kernel maps uncached, so no cache
+/// maintenance is needed around a transfer.
+#[cfg(feature = "dma")]
+#[derive(Clone)]
+struct DmaState {
+    tx: DmaStream,
+    rx: DmaStream,
+    tx_buf: &'static [Cell<u8>; DMA_BUFFER_SIZE],
+    rx_buf: &'static [Cell<u8>; DMA_BUFFER_SIZE],
+}
+
+#[cfg(feature = "dma")]
+impl DmaState {
+    /// Claims the bounce buffers and streams. Can only be called once.
+    fn claim() -> Self {
+        let (tx_buf, rx_buf) = mutable_statics::mutable_statics! {
+            #[link_section = ".spi_dma"]
+            static mut DMA_TX_BUF: [Cell<u8>; DMA_BUFFER_SIZE] =
+                [|| Cell::new(0); _];
+            #[link_section = ".spi_dma"]
+            static mut DMA_RX_BUF: [Cell<u8>; DMA_BUFFER_SIZE] =
+                [|| Cell::new(0); _];
+        };
+
+        // If the linker didn't honor the section, the buffers could be in
+        // DTCM, which the DMA can't reach, or in cached memory, where it would
+        // move stale data, so catch that up front.
+        for buf in [&*tx_buf, &*rx_buf] {
+            let start = buf.as_ptr() as u32;
+            let end = start + DMA_BUFFER_SIZE as u32;
+            assert!(DMA_REGION.start <= start && end <= DMA_REGION.end);
+        }
+
+        Self {
+            tx: DmaStream::new(DMA_CONFIG.controller, DMA_CONFIG.tx_stream),
+            rx: DmaStream::new(DMA_CONFIG.controller, DMA_CONFIG.rx_stream),
+            tx_buf,
+            rx_buf,
+        }
+    }
+}
+
+/// How to behave as a target, generated from the `target` section of the SPI
+/// config.
+#[cfg(feature = "target")]
+struct TargetConfig {
+    mux_index: usize,
+    cpol: device::spi1::cfg2::CPOL_A,
+    cpha: device::spi1::cfg2::CPHA_A,
+    underrun_byte: u8,
+}
+
+/// Progress through the bytes prepared for a target-mode transfer.
+#[cfg(feature = "target")]
+#[derive(Copy, Clone)]
+struct TargetTx {
+    /// Bytes prepared in `TargetState::tx_buf`.
+    len: usize,
+    /// Bytes of those already loaded into the TX FIFO.
+    pos: usize,
+}
+
+/// Target-mode state. The bytes to send are copied out of the client's lease
+/// when the transfer is prepared, since the host may start clocking before
+/// the client gets around to waiting for it.
+#[cfg(feature = "target")]
+#[derive(Clone)]
+struct TargetState {
+    tx_buf: &'static [Cell<u8>; TARGET_BUFFER_SIZE],
+    /// Set while the peripheral is armed as a target.
+    armed: &'static Cell<Option<TargetTx>>,
+}
+
+#[cfg(feature = "target")]
+impl TargetState {
+    /// Claims the target-mode statics. Can only be called once.
+    fn claim() -> Self {
+        let (tx_buf, armed) = mutable_statics::mutable_statics! {
+            static mut TARGET_TX_BUF: [Cell<u8>; TARGET_BUFFER_SIZE] =
+                [|| Cell::new(0); _];
+            static mut TARGET_ARMED: [Cell<Option<TargetTx>>; 1] =
+                [|| Cell::new(None); _];
+        };
+        Self {
+            tx_buf,
+            armed: &armed[0],
+        }
+    }
+}
+
+/// An exchange started by [`SpiServerCore::async_start`]. The data moves
+/// through `AsyncState::buf` in place: each byte received replaces the one
+/// sent at the same position, which has always gone out by then.
+#[cfg(feature = "async-exchange")]
+#[derive(Copy, Clone)]
+struct AsyncTransfer {
+    device_index: usize,
+    len: usize,
+    tx_pos: usize,
+    rx_pos: usize,
+    keep_cs_asserted: bool,
+    start: u64,
+    /// Set once the transfer is over, until it's collected.
+    result: Option<Result<(), TransferError>>,
+}
+
+#[cfg(feature = "async-exchange")]
+#[derive(Clone)]
+struct AsyncState {
+    buf: &'static [Cell<u8>; ASYNC_BUFFER_SIZE],
+    transfer: &'static Cell<Option<AsyncTransfer>>,
+}
+
+#[cfg(feature = "async-exchange")]
+impl AsyncState {
+    /// Claims the async buffer and state. Can only be called once.
+    fn claim() -> Self {
+        let (buf, transfer) = mutable_statics::mutable_statics! {
+            static mut ASYNC_BUF: [Cell<u8>; ASYNC_BUFFER_SIZE] =
+                [|| Cell::new(0); _];
+            static mut ASYNC_TRANSFER: [Cell<Option<AsyncTransfer>>; 1] =
+                [|| Cell::new(None); _];
+        };
+        Self {
+            buf,
+            transfer: &transfer[0],
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct LockState {
     task: TaskId,
@@ -92,11 +395,140 @@ pub enum TransferError {
     ///
     /// This is almost certainly a programming error on the client side.
     BadDevice = 2,
+
+    /// The transfer was cut short by an abort notification.
+    Aborted = 3,
+
+    /// The CRC received from the device didn't match.
+    CrcError = 4,
+
+    /// The client's lease stopped working partway through the transfer, which
+    /// means the client has died; there's no one left to reply to.
+    ClientGone = 5,
+
+    /// The controller is armed as a target, or running an asynchronous
+    /// exchange, so it can't start another transfer until that one is
+    /// finished.
+    Busy = 6,
+
+    /// There's no asynchronous exchange to collect the result of.
+    NotStarted = 7,
+
+    /// The RX FIFO overflowed, so received data was lost. We pace TX to
+    /// prevent this, so it means we were held off the CPU for too long.
+    Overrun = 8,
+}
+
+/// Errors returned by [`SpiServerCore::target_prepare`] and
+/// [`SpiServerCore::target_wait_transfer`].
+#[cfg(feature = "target")]
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum TargetError {
+    /// The data to send doesn't fit in the target buffer.
+    BadTransferSize,
+
+    /// The controller is locked, or already armed as a target.
+    Busy,
+
+    /// There's no prepared transfer to wait for.
+    NotPrepared,
+
+    /// The host sent bytes faster than we could take them out of the RX
+    /// FIFO, so some were lost.
+    Overrun,
+
+    /// The wait was cut short by an abort notification.
+    Aborted,
+
+    /// The client's lease stopped working, which means the client has died.
+    ClientGone,
 }
 
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub struct LockError(());
 
+/// Errors returned by [`SpiServerCore::set_data_line_swap`].
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum SwapError {
+    /// Attempt to configure mux option N when there is no mux option N.
+    BadMuxIndex,
+
+    /// The controller is locked, so a transaction may be in progress.
+    Busy,
+}
+
+/// Errors returned by [`SpiServerCore::select_mux`].
+#[cfg(feature = "pin-mux")]
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum MuxError {
+    /// Attempt to select mux option N when there is no mux option N.
+    BadMuxIndex,
+
+    /// The controller is in use on another mux option.
+    Busy,
+}
+
+/// Runtime overrides of each mux option's configured `swap_data` setting.
+///
+/// Bit N of `mask` is set if mux option N has been overridden, in which case
+/// bit N of `swap` gives the value to use instead of the configured one.
+#[derive(Copy, Clone, Default)]
+pub struct SwapOverride {
+    mask: u32,
+    swap: u32,
+}
+
+/// Runtime override of a device's configured clock divider, set with
+/// [`SpiServerCore::set_clock_divider`].
+#[derive(Copy, Clone, Default)]
+pub struct ClockOverride(Option<device::spi1::cfg1::MBR_A>);
+
+/// Errors returned by [`SpiServerCore::set_clock_divider`].
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum ClockDividerError {
+    /// There is no device N, or the controller is locked to another device.
+    BadDevice,
+
+    /// The divider is outside the controller's configured limits.
+    BadDivider,
+}
+
+/// Errors returned by [`SpiServerCore::loopback_test`] and
+/// [`SpiServerCore::selftest`].
+#[cfg(feature = "diagnostics")]
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum LoopbackError {
+    /// There is no mux option N, or it has no input pin to loop back into.
+    BadMuxIndex,
+
+    /// The controller is locked, so a transaction may be in progress.
+    Busy,
+
+    /// The test was cut short by an abort notification.
+    Aborted,
+}
+
+/// Pattern shifted out by [`SpiServerCore::loopback_test`]. This is no longer
+/// than the smallest FIFO on the H7, so TX can't overrun RX.
+#[cfg(feature = "diagnostics")]
+const LOOPBACK_PATTERN: [u8; 8] =
+    [0x00, 0xFF, 0xA5, 0x5A, 0x01, 0x80, 0x3C, 0xC3];
+
+/// CRC polynomial used by [`SpiServerCore::selftest`] (CRC-8/SMBus).
+#[cfg(feature = "diagnostics")]
+const SELFTEST_CRC_POLY: u8 = 0x07;
+
+impl SwapOverride {
+    fn swap_data(&self, mux_index: usize, opt: &SpiMuxOption) -> bool {
+        let bit = 1 << mux_index;
+        if self.mask & bit != 0 {
+            self.swap & bit != 0
+        } else {
+            opt.swap_data
+        }
+    }
+}
+
 impl From<TransferError> for RequestError<SpiError> {
     fn from(value: TransferError) -> Self {
         match value {
@@ -106,6 +538,51 @@ impl From<TransferError> for RequestError<SpiError> {
             TransferError::BadDevice => {
                 RequestError::Fail(ClientError::BadMessageContents)
             }
+            TransferError::Aborted => RequestError::Runtime(SpiError::Aborted),
+            TransferError::CrcError => {
+                RequestError::Runtime(SpiError::CrcError)
+            }
+            TransferError::ClientGone => {
+                RequestError::Fail(ClientError::WentAway)
+            }
+            TransferError::Busy => RequestError::Runtime(SpiError::Busy),
+            TransferError::NotStarted => {
+                RequestError::Fail(ClientError::BadMessageContents)
+            }
+            TransferError::Overrun => RequestError::Runtime(SpiError::Overrun),
+        }
+    }
+}
+
+#[cfg(feature = "target")]
+impl From<TargetError> for RequestError<SpiError> {
+    fn from(value: TargetError) -> Self {
+        match value {
+            TargetError::BadTransferSize => {
+                RequestError::Runtime(SpiError::BadTransferSize)
+            }
+            TargetError::Busy => RequestError::Runtime(SpiError::Busy),
+            TargetError::NotPrepared => {
+                RequestError::Fail(ClientError::BadMessageContents)
+            }
+            TargetError::Overrun => RequestError::Runtime(SpiError::Overrun),
+            TargetError::Aborted => RequestError::Runtime(SpiError::Aborted),
+            TargetError::ClientGone => {
+                RequestError::Fail(ClientError::WentAway)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "diagnostics")]
+impl From<LoopbackError> for RequestError<SpiError> {
+    fn from(value: LoopbackError) -> Self {
+        match value {
+            LoopbackError::BadMuxIndex => {
+                RequestError::Fail(ClientError::BadMessageContents)
+            }
+            LoopbackError::Busy => RequestError::Runtime(SpiError::Busy),
+            LoopbackError::Aborted => RequestError::Runtime(SpiError::Aborted),
         }
     }
 }
@@ -116,22 +593,88 @@ impl From<LockError> for RequestError<Infallible> {
     }
 }
 
+impl From<ClockDividerError> for RequestError<SpiError> {
+    fn from(_: ClockDividerError) -> Self {
+        // Both of these are client mistakes.
+        RequestError::Fail(ClientError::BadMessageContents)
+    }
+}
+
+impl From<SwapError> for RequestError<SpiError> {
+    fn from(value: SwapError) -> Self {
+        match value {
+            SwapError::BadMuxIndex => {
+                RequestError::Fail(ClientError::BadMessageContents)
+            }
+            SwapError::Busy => RequestError::Runtime(SpiError::Busy),
+        }
+    }
+}
+
+#[cfg(feature = "pin-mux")]
+impl From<MuxError> for RequestError<SpiError> {
+    fn from(value: MuxError) -> Self {
+        match value {
+            MuxError::BadMuxIndex => {
+                RequestError::Fail(ClientError::BadMessageContents)
+            }
+            MuxError::Busy => RequestError::Runtime(SpiError::Busy),
+        }
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
-impl SpiServerCore {
+impl<S: SpiSys> SpiServerCore<S> {
+    /// Takes ownership of the SPI controller.
+    ///
+    /// `irq_mask` is the notification wired to the controller's interrupt, as
+    /// generated from the app config (e.g. `notifications::SPI_IRQ_MASK`); it
+    /// must be exactly one bit. Any notification in `abort_mask` that arrives
+    /// while a transfer is waiting on the controller aborts that transfer; pass
+    /// 0 to disable this.
     pub fn init(
-        sys: sys_api::Sys,
+        sys: S,
         irq_mask: u32,
+        abort_mask: u32,
         lock_holder: &'static Cell<Option<LockState>>, // used by Idol server
         current_mux_index: &'static Cell<usize>,
+        swap_override: &'static Cell<SwapOverride>,
+        clock_overrides: &'static [Cell<ClockOverride>; DEVICE_COUNT],
     ) -> Self {
         check_server_config();
 
+        // A zero mask would make us wait forever for the interrupt, and extra
+        // bits would have us treat unrelated notifications as the controller.
+        assert!(irq_mask.is_power_of_two());
+        assert_eq!(irq_mask & abort_mask, 0);
+
         let registers = unsafe { &*CONFIG.registers };
 
         sys.enable_clock(CONFIG.peripheral);
         sys.enter_reset(CONFIG.peripheral);
         sys.leave_reset(CONFIG.peripheral);
+
+        // The DMA controller may be shared with other tasks' streams, so we
+        // turn its clock on but leave it out of reset. DMAMUX1 shares its
+        // clock.
+        #[cfg(feature = "dma")]
+        let dma = {
+            sys.enable_clock(match DMA_CONFIG.controller {
+                1 => sys_api::Peripheral::Dma1,
+                _ => sys_api::Peripheral::Dma2,
+            });
+            DmaState::claim()
+        };
+        #[cfg(feature = "target")]
+        let target = TargetState::claim();
+        #[cfg(feature = "async-exchange")]
+        let async_state = AsyncState::claim();
+        #[cfg(feature = "stats")]
+        let stats = mutable_statics::mutable_statics! {
+            static mut STATS: [Cell<SpiDeviceStats>; DEVICE_COUNT] =
+                [|| Cell::new(Default::default()); _];
+        };
         let mut spi = spi_core::Spi::from(registers);
 
         // This should correspond to '0' in the standard SPI parlance
@@ -168,11 +711,19 @@ impl SpiServerCore {
         // We deactivate before activate to avoid pin clash if we previously crashed
         // with one of these activated.
         current_mux_index.set(0);
-        for opt in &CONFIG.mux_options[1..] {
-            deactivate_mux_option(opt, &sys);
+        // No device has been used yet, so park each option's SCK high if any
+        // device on it idles high, and low otherwise.
+        for (i, opt) in CONFIG.mux_options.iter().enumerate().skip(1) {
+            let sck_idle_high = CONFIG.devices.iter().any(|d| {
+                d.mux_index == i
+                    && d.cpol == device::spi1::cfg2::CPOL_A::IDLEHIGH
+            });
+            deactivate_mux_option(opt, sck_idle_high, &sys);
         }
+        let opt = &CONFIG.mux_options[current_mux_index.get()];
         activate_mux_option(
-            &CONFIG.mux_options[current_mux_index.get()],
+            opt,
+            swap_override.get().swap_data(current_mux_index.get(), opt),
             &sys,
             &spi,
         );
@@ -181,8 +732,19 @@ impl SpiServerCore {
             spi,
             sys,
             irq_mask,
+            abort_mask,
             lock_holder,
             current_mux_index,
+            swap_override,
+            clock_overrides,
+            #[cfg(feature = "stats")]
+            stats,
+            #[cfg(feature = "dma")]
+            dma,
+            #[cfg(feature = "target")]
+            target,
+            #[cfg(feature = "async-exchange")]
+            async_state,
         }
     }
 
@@ -195,46 +757,164 @@ impl SpiServerCore {
         self.lock_holder.set(None);
     }
 
+    /// Reads from a device into `dest`.
+    ///
+    /// If `keep_cs_asserted` is set, CS is left asserted after a successful
+    /// transfer, so that a later transfer can continue the transaction. It is
+    /// up to the caller to make sure that nothing else is sent on the same
+    /// mux option in the meantime.
     pub fn read<'b, BufWrite: BufWriter<'b>>(
         &self,
         device_index: u8,
         dest: BufWrite,
+        keep_cs_asserted: bool,
     ) -> Result<(), TransferError> {
         self.ready_writey::<&[u8], _>(
             SpiOperation::read,
             device_index,
             None,
             Some(dest),
+            keep_cs_asserted,
         )
     }
 
+    /// Writes `src` to a device; see [`Self::read`] for `keep_cs_asserted`.
     pub fn write<'b, BufRead: BufReader<'b>>(
         &self,
         device_index: u8,
         src: BufRead,
+        keep_cs_asserted: bool,
     ) -> Result<(), TransferError> {
         self.ready_writey::<_, &mut [u8]>(
             SpiOperation::write,
             device_index,
             Some(src),
             None,
+            keep_cs_asserted,
         )
     }
 
+    /// Exchanges bytes with a device; see [`Self::read`] for
+    /// `keep_cs_asserted`.
     pub fn exchange<'b, BufRead: BufReader<'b>, BufWrite: BufWriter<'b>>(
         &self,
         device_index: u8,
         src: BufRead,
         dest: BufWrite,
+        keep_cs_asserted: bool,
     ) -> Result<(), TransferError> {
         self.ready_writey(
             SpiOperation::exchange,
             device_index,
             Some(src),
             Some(dest),
+            keep_cs_asserted,
         )
     }
 
+    /// Writes `command` and then `src` to a device under one CS assertion,
+    /// putting the bytes received after the command into `dest`. (On a
+    /// half-duplex device, `dest` is read once both have been written.) See
+    /// [`Self::read`] for `keep_cs_asserted`.
+    #[cfg(feature = "command-exchange")]
+    pub fn command_exchange<
+        'b,
+        CmdRead: BufReader<'b>,
+        BufRead: BufReader<'b>,
+        BufWrite: BufWriter<'b>,
+    >(
+        &self,
+        device_index: u8,
+        command: CmdRead,
+        src: BufRead,
+        dest: BufWrite,
+        keep_cs_asserted: bool,
+    ) -> Result<(), TransferError> {
+        // A full-duplex device answers the command as it's clocked out, and
+        // that answer isn't wanted.
+        let half_duplex = CONFIG
+            .devices
+            .get(usize::from(device_index))
+            .is_some_and(DeviceDescriptor::is_half_duplex);
+        let skip = if half_duplex {
+            0
+        } else {
+            command.remaining_size()
+        };
+        self.ready_writey(
+            SpiOperation::command_exchange,
+            device_index,
+            Some(ChainReader {
+                first: command,
+                second: src,
+            }),
+            Some(SkipWriter { skip, inner: dest }),
+            keep_cs_asserted,
+        )
+    }
+
+    /// Returns the counters for `device_index`, or `None` if there is no such
+    /// device.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self, device_index: u8) -> Option<SpiDeviceStats> {
+        self.stats.get(usize::from(device_index)).map(Cell::get)
+    }
+
+    /// Counts a transfer to `device_index` that the caller refused because
+    /// another task was holding CS on the same mux option.
+    pub fn record_contention(&self, device_index: u8) {
+        self.update_stats(usize::from(device_index), |s| {
+            s.lock_contentions = s.lock_contentions.wrapping_add(1);
+        });
+    }
+
+    /// Updates the counters for `device_index`, if we're keeping them.
+    fn update_stats(
+        &self,
+        device_index: usize,
+        f: impl FnOnce(&mut SpiDeviceStats),
+    ) {
+        #[cfg(feature = "stats")]
+        if let Some(cell) = self.stats.get(device_index) {
+            let mut stats = cell.get();
+            f(&mut stats);
+            cell.set(stats);
+        }
+        #[cfg(not(feature = "stats"))]
+        let _ = (device_index, f);
+    }
+
+    /// Wraps up the bookkeeping for a successful transfer of `len` bytes
+    /// that began at `start`.
+    fn transfer_done(&self, device_index: usize, len: u32, start: u64) {
+        SPI_TRANSFER_STATUS.finish();
+        self.update_stats(device_index, |s| {
+            s.transfers = s.transfers.wrapping_add(1);
+            s.bytes += u64::from(len);
+        });
+        ringbuf_entry!(Trace::Duration(sys_get_timer().now - start));
+    }
+
+    /// Returns the mux option used by `device_index`, or `None` if there is no
+    /// such device.
+    pub fn device_mux_index(&self, device_index: u8) -> Option<usize> {
+        CONFIG
+            .devices
+            .get(usize::from(device_index))
+            .map(|d| d.mux_index)
+    }
+
+    /// Deasserts CS for `device_index`, e.g. to abandon a transaction left
+    /// open by a `keep_cs_asserted` transfer. Out-of-range devices are
+    /// ignored.
+    pub fn deassert_cs(&self, device_index: u8) {
+        if let Some(device) = CONFIG.devices.get(usize::from(device_index)) {
+            for pin in device.cs {
+                self.sys.gpio_set(*pin);
+            }
+        }
+    }
+
     pub fn lock(
         &self,
         sender: TaskId,
@@ -271,33 +951,817 @@ impl SpiServerCore {
             } else {
                 self.sys.gpio_set(*pin);
             }
-        }
+        }
+
+        self.lock_holder.set(Some(LockState {
+            task: sender,
+            device_index: devidx,
+        }));
+        Ok(())
+    }
+
+    /// Asserts or deasserts CS for the locked device on behalf of the lock
+    /// holder, `sender`, for devices that need CS pulsed between commands.
+    /// Unlike changing CS with `lock`, this observes the device's CS setup
+    /// and hold times, and first gets the mux and clock polarity ready, so
+    /// that the device sees a clean idle SCK while selected.
+    #[cfg(feature = "cs-control")]
+    pub fn set_cs(
+        &self,
+        sender: TaskId,
+        asserted: bool,
+    ) -> Result<(), LockError> {
+        let lockstate = self.lock_holder.get().ok_or(LockError(()))?;
+        // As in `lock`, only the holder can reach us while we're locked.
+        assert!(lockstate.task == sender);
+        let device = &CONFIG.devices[lockstate.device_index];
+        if asserted {
+            self.select_mux_option(device.mux_index);
+            self.spi.set_clock_mode(device.cpol, device.cpha);
+            self.assert_device_cs(device);
+        } else {
+            self.deassert_device_cs(device);
+        }
+        Ok(())
+    }
+
+    pub fn release(&self, sender: TaskId) -> Result<(), LockError> {
+        if let Some(lockstate) = &self.lock_holder.get() {
+            // The fact that we were able to receive this means we
+            // should be locked by the sender...but double check.
+            assert!(lockstate.task == sender);
+
+            let device = &CONFIG.devices[lockstate.device_index];
+
+            for pin in device.cs {
+                // Deassert CS. If it wasn't asserted, this is a no-op.
+                // If it was, this fixes that.
+                self.sys.gpio_set(*pin);
+            }
+
+            self.lock_holder.set(None);
+            Ok(())
+        } else {
+            Err(LockError(()))
+        }
+    }
+
+    /// Returns the number of devices configured on this controller.
+    pub fn device_count(&self) -> u8 {
+        CONFIG.devices.len() as u8
+    }
+
+    /// Returns the number of mux options configured on this controller.
+    pub fn mux_count(&self) -> u8 {
+        CONFIG.mux_options.len() as u8
+    }
+
+    /// Returns the index of the mux option that's currently active.
+    pub fn current_mux_index(&self) -> u8 {
+        self.current_mux_index.get() as u8
+    }
+
+    /// Switches to mux option `mux_index` now, rather than on the next
+    /// transfer to a device behind it. This is refused if the controller is
+    /// busy with something else: a transfer in the background, target mode,
+    /// or a lock on a device behind another option.
+    #[cfg(feature = "pin-mux")]
+    pub fn select_mux(&self, mux_index: u8) -> Result<(), MuxError> {
+        let mux_index = usize::from(mux_index);
+        if mux_index >= CONFIG.mux_options.len() {
+            return Err(MuxError::BadMuxIndex);
+        }
+        if mux_index == self.current_mux_index.get() {
+            return Ok(());
+        }
+        #[cfg(feature = "target")]
+        if self.target.armed.get().is_some() {
+            return Err(MuxError::Busy);
+        }
+        #[cfg(feature = "async-exchange")]
+        if self.async_in_progress() {
+            return Err(MuxError::Busy);
+        }
+        if let Some(lockstate) = self.lock_holder.get() {
+            if CONFIG.devices[lockstate.device_index].mux_index != mux_index {
+                return Err(MuxError::Busy);
+            }
+        }
+        self.select_mux_option(mux_index);
+        Ok(())
+    }
+
+    /// Overrides the configured data line swap for mux option `mux_index`.
+    ///
+    /// The override is applied the next time the mux option is activated, or
+    /// immediately if it is the active option. This is refused while the
+    /// controller is locked, since the lock holder may be partway through a
+    /// multi-part transaction.
+    pub fn set_data_line_swap(
+        &self,
+        mux_index: u8,
+        swap: bool,
+    ) -> Result<(), SwapError> {
+        let mux_index = usize::from(mux_index);
+        if mux_index >= CONFIG.mux_options.len() {
+            return Err(SwapError::BadMuxIndex);
+        }
+        if self.lock_holder.get().is_some() {
+            return Err(SwapError::Busy);
+        }
+
+        ringbuf_entry!(Trace::SwapOverride {
+            mux_index: mux_index as u8,
+            swap,
+        });
+
+        let mut over = self.swap_override.get();
+        let bit = 1 << mux_index;
+        over.mask |= bit;
+        if swap {
+            over.swap |= bit;
+        } else {
+            over.swap &= !bit;
+        }
+        self.swap_override.set(over);
+
+        // The peripheral is disabled between transfers, so we can update the
+        // active option in place.
+        if mux_index == self.current_mux_index.get() {
+            self.spi.set_data_line_swap(swap);
+        }
+        Ok(())
+    }
+
+    /// Overrides the configured clock divider for device `device_index` until
+    /// the server restarts. The divider has to be within the controller's
+    /// `min_clock_divider`/`max_clock_divider` limits, and if the controller
+    /// is locked, only the locked device can be changed.
+    pub fn set_clock_divider(
+        &self,
+        device_index: u8,
+        divider: ClockDivider,
+    ) -> Result<(), ClockDividerError> {
+        let index = usize::from(device_index);
+        let over = self
+            .clock_overrides
+            .get(index)
+            .ok_or(ClockDividerError::BadDevice)?;
+        if let Some(lockstate) = self.lock_holder.get() {
+            if lockstate.device_index != index {
+                return Err(ClockDividerError::BadDevice);
+            }
+        }
+
+        let bits = divider as u8;
+        if bits < CONFIG.min_clock_divider as u8
+            || bits > CONFIG.max_clock_divider as u8
+        {
+            return Err(ClockDividerError::BadDivider);
+        }
+
+        ringbuf_entry!(Trace::ClockOverride {
+            device_index,
+            divider: bits,
+        });
+        over.set(ClockOverride(Some(mbr_for(divider))));
+        Ok(())
+    }
+
+    /// Shifts a known pattern out of mux option `mux_index` and checks that
+    /// the same bytes come back in, returning `true` if they did.
+    ///
+    /// The H7's SPI block has no internal loopback, so this relies on COPI
+    /// being bridged to CIPO (e.g. by a test fixture). No CS is asserted, so
+    /// devices on the mux option should ignore the traffic.
+    #[cfg(feature = "diagnostics")]
+    pub fn loopback_test(&self, mux_index: u8) -> Result<bool, LoopbackError> {
+        let opt = CONFIG
+            .mux_options
+            .get(usize::from(mux_index))
+            .ok_or(LoopbackError::BadMuxIndex)?;
+        if opt.input.is_none() {
+            return Err(LoopbackError::BadMuxIndex);
+        }
+        if self.lock_holder.get().is_some() {
+            return Err(LoopbackError::Busy);
+        }
+        #[cfg(feature = "async-exchange")]
+        if self.async_in_progress() {
+            return Err(LoopbackError::Busy);
+        }
+
+        self.select_mux_option(usize::from(mux_index));
+
+        // Plain full-duplex framing, at the slowest clock we have so that
+        // long fixture wiring doesn't cause false failures.
+        self.spi.set_frame_size(8);
+        self.spi
+            .set_bit_order(device::spi1::cfg2::LSBFRST_A::MSBFIRST);
+        self.spi.set_protocol(device::spi1::cfg2::SP_A::MOTOROLA);
+        self.spi.set_inter_frame_gap(0);
+        self.spi.set_crc(None);
+        self.spi
+            .set_comm_mode(device::spi1::cfg2::COMM_A::FULLDUPLEX);
+        let len = LOOPBACK_PATTERN.len();
+        self.spi
+            .enable(len as u16, device::spi1::cfg1::MBR_A::DIV256);
+        self.spi.start();
+        self.spi.enable_transfer_interrupts();
+        self.spi.clear_eot();
+
+        let mut passed = true;
+        let mut tx_count = 0;
+        let mut rx_count = 0;
+        while rx_count < len {
+            if tx_count < len && self.spi.can_tx_frame() {
+                self.spi.send8(LOOPBACK_PATTERN[tx_count]);
+                tx_count += 1;
+                if tx_count == len {
+                    self.spi.disable_can_tx_interrupt();
+                }
+            } else if self.spi.can_rx_byte() {
+                let b = self.spi.recv8();
+                if BYTE_TRACE {
+                    ringbuf_entry!(Trace::Rx(b));
+                }
+                passed &= b == LOOPBACK_PATTERN[rx_count];
+                rx_count += 1;
+            } else if self.wait_for_irq().is_err() {
+                self.spi.end();
+                return Err(LoopbackError::Aborted);
+            }
+        }
+        while !self.spi.check_eot() {
+            if self.wait_for_irq().is_err() {
+                self.spi.end();
+                return Err(LoopbackError::Aborted);
+            }
+        }
+        self.spi.clear_eot();
+        self.spi.end();
+
+        ringbuf_entry!(Trace::Loopback { mux_index, passed });
+        Ok(passed)
+    }
+
+    /// Checks the controller itself, independent of anything attached to it,
+    /// returning `true` if it passed.
+    ///
+    /// The H7's SPI block has no internal loopback, so we can't check what it
+    /// receives. Instead, we disconnect the active mux option's pins (so
+    /// nothing outside sees the traffic, and no CS is touched), shift out a
+    /// known pattern with the hardware CRC enabled, and check that every frame
+    /// was clocked and the CRC the peripheral computed matches ours. This
+    /// covers the FIFOs, sequencer and CRC unit; `loopback_test` covers the
+    /// pins, given a fixture.
+    #[cfg(feature = "diagnostics")]
+    pub fn selftest(&self) -> Result<bool, LoopbackError> {
+        #[cfg(feature = "target")]
+        if self.target.armed.get().is_some() {
+            return Err(LoopbackError::Busy);
+        }
+        if self.lock_holder.get().is_some() {
+            return Err(LoopbackError::Busy);
+        }
+        #[cfg(feature = "async-exchange")]
+        if self.async_in_progress() {
+            return Err(LoopbackError::Busy);
+        }
+
+        let mux_index = self.current_mux_index.get();
+        let opt = &CONFIG.mux_options[mux_index];
+        deactivate_mux_option(opt, self.spi.sck_idles_high(), &self.sys);
+        let result = self.selftest_run();
+        activate_mux_option(
+            opt,
+            self.swap_override.get().swap_data(mux_index, opt),
+            &self.sys,
+            &self.spi,
+        );
+        result
+    }
+
+    #[cfg(feature = "diagnostics")]
+    fn selftest_run(&self) -> Result<bool, LoopbackError> {
+        self.spi.set_frame_size(8);
+        self.spi
+            .set_bit_order(device::spi1::cfg2::LSBFRST_A::MSBFIRST);
+        self.spi.set_protocol(device::spi1::cfg2::SP_A::MOTOROLA);
+        self.spi.set_inter_frame_gap(0);
+        self.spi.set_crc(Some(SELFTEST_CRC_POLY));
+        self.spi
+            .set_comm_mode(device::spi1::cfg2::COMM_A::FULLDUPLEX);
+        let len = LOOPBACK_PATTERN.len();
+        self.spi
+            .enable(len as u16, device::spi1::cfg1::MBR_A::DIV256);
+        self.spi.start();
+        self.spi.enable_transfer_interrupts();
+        self.spi.clear_eot();
+
+        // Whatever comes in is noise, but there should be one byte of it for
+        // each one sent.
+        let mut tx_count = 0;
+        let mut rx_count = 0;
+        while rx_count < len {
+            if tx_count < len && self.spi.can_tx_frame() {
+                self.spi.send8(LOOPBACK_PATTERN[tx_count]);
+                tx_count += 1;
+                if tx_count == len {
+                    self.spi.disable_can_tx_interrupt();
+                }
+            } else if self.spi.can_rx_byte() {
+                let _ = self.spi.recv8();
+                rx_count += 1;
+            } else if self.wait_for_irq().is_err() {
+                self.spi.end();
+                return Err(LoopbackError::Aborted);
+            }
+        }
+        while !self.spi.check_eot() {
+            if self.wait_for_irq().is_err() {
+                self.spi.end();
+                return Err(LoopbackError::Aborted);
+            }
+        }
+        // The received CRC is noise too, so its mismatch flag is ignored (and
+        // cleared by `end`).
+        let crc = self.spi.read_tx_crc() as u8;
+        self.spi.clear_eot();
+        self.spi.end();
+        self.spi.set_crc(None);
+
+        let passed = crc == crc8(SELFTEST_CRC_POLY, &LOOPBACK_PATTERN);
+        ringbuf_entry!(Trace::SelfTest {
+            received: rx_count as u8,
+            crc,
+            passed,
+        });
+        Ok(passed)
+    }
+
+    /// Starts exchanging `len` bytes with a device without waiting for it to
+    /// finish, so that the caller can get on with other things. `src` is
+    /// copied into a buffer first, and padded out with the device's idle
+    /// byte if it's shorter than `len`. The transfer is then moved along by
+    /// calling `async_service` each time the controller interrupt fires, and
+    /// its result fetched with `async_collect`.
+    ///
+    /// `len` is limited by the configured `async_buffer_size`, and only
+    /// full-duplex devices with 8-bit frames are supported. Until the result
+    /// is collected, other transfers are refused with `TransferError::Busy`.
+    #[cfg(feature = "async-exchange")]
+    pub fn async_start<'b, BufRead: BufReader<'b>>(
+        &self,
+        device_index: u8,
+        mut src: BufRead,
+        len: u32,
+        keep_cs_asserted: bool,
+    ) -> Result<(), TransferError> {
+        let device_index = usize::from(device_index);
+        #[cfg(feature = "target")]
+        if self.target.armed.get().is_some() {
+            return Err(TransferError::Busy);
+        }
+        if self.async_state.transfer.get().is_some() {
+            return Err(TransferError::Busy);
+        }
+        if let Some(lockstate) = &self.lock_holder.get() {
+            if lockstate.device_index != device_index {
+                return Err(TransferError::BadDevice);
+            }
+        }
+        let device = CONFIG
+            .devices
+            .get(device_index)
+            .ok_or(TransferError::BadDevice)?;
+        if device.is_half_duplex() || device.frame_size != 8 {
+            return Err(TransferError::BadDevice);
+        }
+        let len = len as usize;
+        if len == 0 || len > ASYNC_BUFFER_SIZE || src.remaining_size() > len {
+            return Err(TransferError::BadTransferSize);
+        }
+
+        let buf = &self.async_state.buf[..len];
+        for cell in buf {
+            let b = if src.remaining_size() != 0 {
+                src.read().ok_or(TransferError::ClientGone)?
+            } else {
+                device.idle_byte
+            };
+            cell.set(b);
+        }
+
+        // Apply any runtime override of the device's clock divider.
+        let device = &DeviceDescriptor {
+            clock_divider: self.clock_overrides[device_index]
+                .get()
+                .0
+                .unwrap_or(device.clock_divider),
+            ..*device
+        };
+
+        // This is our commit point.
+        ringbuf_entry!(Trace::Start(
+            SpiOperation::start_exchange,
+            (len as u32, len as u32)
+        ));
+        SPI_TRANSFER_STATUS.start(device_index as u8, len as u32);
+        if device.mux_index != self.current_mux_index.get() {
+            self.update_stats(device_index, |s| {
+                s.mux_switches = s.mux_switches.wrapping_add(1);
+            });
+        }
+        self.select_mux_option(device.mux_index);
+        self.spi.set_clock_mode(device.cpol, device.cpha);
+        self.spi.set_frame_size(8);
+        self.spi.set_bit_order(device.bit_order);
+        self.spi.set_protocol(device.protocol);
+        self.spi.set_inter_frame_gap(device.inter_frame_gap_cycles);
+        self.spi.set_crc(device.crc_poly);
+        self.spi.set_comm_mode(device.comm_mode);
+        // The buffer size is checked to fit in TSIZE at build time, so this
+        // never leaves anything for `top_up_reload`.
+        self.enable_chunked(len as u32, device.clock_divider);
+        self.spi.start();
+        self.spi.enable_transfer_interrupts();
+        self.spi.clear_eot();
+        if self.lock_holder.get().is_none() {
+            self.assert_device_cs(device);
+        }
+
+        self.async_state.transfer.set(Some(AsyncTransfer {
+            device_index,
+            len,
+            tx_pos: 0,
+            rx_pos: 0,
+            keep_cs_asserted,
+            start: sys_get_timer().now,
+            result: None,
+        }));
+        self.async_service();
+        Ok(())
+    }
+
+    /// Returns `true` if an asynchronous exchange is running, or finished
+    /// but not yet collected.
+    #[cfg(feature = "async-exchange")]
+    pub fn async_in_progress(&self) -> bool {
+        self.async_state.transfer.get().is_some()
+    }
+
+    /// Returns `true` if an asynchronous exchange is running, i.e. is still
+    /// using the controller.
+    #[cfg(feature = "async-exchange")]
+    pub fn async_running(&self) -> bool {
+        self.async_state
+            .transfer
+            .get()
+            .is_some_and(|t| t.result.is_none())
+    }
+
+    /// Moves the asynchronous exchange along, as far as the FIFOs allow. Call
+    /// this when the controller interrupt fires; this re-enables it as
+    /// needed. Returns `Some` with whether the exchange succeeded once it's
+    /// over, and `None` otherwise.
+    #[cfg(feature = "async-exchange")]
+    pub fn async_service(&self) -> Option<bool> {
+        let mut t = self.async_state.transfer.get()?;
+        if t.result.is_some() {
+            return None;
+        }
+        let device = &CONFIG.devices[t.device_index];
+        let buf = &self.async_state.buf[..t.len];
+
+        // As in `ready_writey`, never let more bytes be in flight than the RX
+        // FIFO can hold.
+        while t.tx_pos < t.len
+            && t.tx_pos - t.rx_pos < FIFO_DEPTH
+            && self.spi.can_tx_frame()
+        {
+            self.spi.send8(buf[t.tx_pos].get());
+            t.tx_pos += 1;
+        }
+        if t.tx_pos == t.len || t.tx_pos - t.rx_pos == FIFO_DEPTH {
+            self.spi.disable_can_tx_interrupt();
+        }
+
+        while self.spi.can_rx_byte() {
+            if t.rx_pos >= t.len {
+                panic!();
+            }
+            buf[t.rx_pos].set(self.spi.recv8());
+            t.rx_pos += 1;
+            if t.tx_pos < t.len {
+                self.spi.enable_can_tx_interrupt();
+            }
+        }
+        SPI_TRANSFER_STATUS.set_progress(t.rx_pos as u32);
+
+        let cs_override = self.lock_holder.get().is_some();
+        if self.spi.check_overrun() {
+            ringbuf_entry!(Trace::Overrun {
+                device_index: t.device_index as u8,
+                status: self.spi.read_status(),
+            });
+            t.result = Some(Err(self.fail_transfer(
+                device,
+                cs_override,
+                TransferError::Overrun,
+            )));
+            self.async_state.transfer.set(Some(t));
+            return Some(false);
+        }
+
+        // The end of the transfer may not have been flagged yet, even with all
+        // the data in, in which case the next interrupt will be for it.
+        if t.rx_pos < t.len || !self.spi.check_eot() {
+            self.async_state.transfer.set(Some(t));
+            sys_irq_control(self.irq_mask, true);
+            return None;
+        }
+
+        let result = if device.crc_poly.is_some() && self.spi.check_crc_error()
+        {
+            Err(self.fail_transfer(
+                device,
+                cs_override,
+                TransferError::CrcError,
+            ))
+        } else {
+            self.spi.clear_eot();
+            self.spi.end();
+            if !cs_override && !t.keep_cs_asserted {
+                self.deassert_device_cs(device);
+            }
+            self.transfer_done(t.device_index, t.len as u32, t.start);
+            Ok(())
+        };
+        t.result = Some(result);
+        self.async_state.transfer.set(Some(t));
+        Some(result.is_ok())
+    }
+
+    /// Cuts short a running asynchronous exchange, e.g. because an abort
+    /// notification arrived. Returns `true` if there was one to stop.
+    #[cfg(feature = "async-exchange")]
+    pub fn async_abort(&self) -> bool {
+        let Some(mut t) = self.async_state.transfer.get() else {
+            return false;
+        };
+        if t.result.is_some() {
+            return false;
+        }
+        let device = &CONFIG.devices[t.device_index];
+        let cs_override = self.lock_holder.get().is_some();
+        t.result = Some(Err(self.fail_transfer(
+            device,
+            cs_override,
+            TransferError::Aborted,
+        )));
+        self.async_state.transfer.set(Some(t));
+        true
+    }
+
+    /// Blocks until the running asynchronous exchange (if any) is over, for
+    /// when the caller needs the controller back right away. Returns whether
+    /// it succeeded, or `None` if there wasn't one running.
+    #[cfg(feature = "async-exchange")]
+    pub fn async_wait(&self) -> Option<bool> {
+        let t = self.async_state.transfer.get()?;
+        if t.result.is_some() {
+            return None;
+        }
+        loop {
+            let bits = sys_recv_notification(self.irq_mask | self.abort_mask);
+            if bits & self.abort_mask != 0 {
+                self.async_abort();
+                return Some(false);
+            }
+            if let Some(ok) = self.async_service() {
+                return Some(ok);
+            }
+        }
+    }
+
+    /// Fetches the result of an asynchronous exchange, writing the bytes
+    /// received into `dest` (which may be shorter than the exchange), and
+    /// returns its length. If the exchange is still running, returns
+    /// `TransferError::Busy`.
+    #[cfg(feature = "async-exchange")]
+    pub fn async_collect<'b, BufWrite: BufWriter<'b>>(
+        &self,
+        mut dest: BufWrite,
+    ) -> Result<u32, TransferError> {
+        let t = self
+            .async_state
+            .transfer
+            .get()
+            .ok_or(TransferError::NotStarted)?;
+        let result = t.result.ok_or(TransferError::Busy)?;
+        self.async_state.transfer.set(None);
+        result?;
+        for cell in &self.async_state.buf[..t.len] {
+            if dest.write(cell.get()).is_err() {
+                if dest.remaining_size() != 0 {
+                    return Err(TransferError::ClientGone);
+                }
+                break;
+            }
+        }
+        Ok(t.len as u32)
+    }
+
+    /// Throws away a finished asynchronous exchange whose result nobody is
+    /// going to collect, e.g. because the task that started it restarted.
+    /// Running exchanges are left alone.
+    #[cfg(feature = "async-exchange")]
+    pub fn async_discard(&self) {
+        if let Some(t) = self.async_state.transfer.get() {
+            if t.result.is_some() {
+                self.async_state.transfer.set(None);
+            }
+        }
+    }
+
+    /// Arms the controller as a target (slave) for a transfer driven by
+    /// someone else, such as a host CPU or FPGA, sending the bytes in `src`.
+    /// If the host clocks out more than that, it gets the configured underrun
+    /// byte.
+    ///
+    /// Only the first FIFO's worth of `src` is loaded here; the rest is fed in
+    /// by `target_wait_transfer`, as is taking the bytes the host sends. So,
+    /// for anything longer than the FIFO, call that before the host starts.
+    /// Until then, the controller can't be used as a host.
+    #[cfg(feature = "target")]
+    pub fn target_prepare<'b, BufRead: BufReader<'b>>(
+        &self,
+        mut src: BufRead,
+    ) -> Result<(), TargetError> {
+        if self.lock_holder.get().is_some() || self.target.armed.get().is_some()
+        {
+            return Err(TargetError::Busy);
+        }
+        #[cfg(feature = "async-exchange")]
+        if self.async_in_progress() {
+            return Err(TargetError::Busy);
+        }
+        let len = src.remaining_size();
+        if len > TARGET_BUFFER_SIZE {
+            return Err(TargetError::BadTransferSize);
+        }
+        for cell in &self.target.tx_buf[..len] {
+            cell.set(src.read().ok_or(TargetError::ClientGone)?);
+        }
+
+        self.select_mux_option(TARGET_CONFIG.mux_index);
+        self.spi.set_target_mode(true);
+        self.spi
+            .set_clock_mode(TARGET_CONFIG.cpol, TARGET_CONFIG.cpha);
+        self.spi.set_frame_size(8);
+        self.spi
+            .set_bit_order(device::spi1::cfg2::LSBFRST_A::MSBFIRST);
+        self.spi.set_protocol(device::spi1::cfg2::SP_A::MOTOROLA);
+        self.spi.set_inter_frame_gap(0);
+        self.spi.set_crc(None);
+        self.spi
+            .set_comm_mode(device::spi1::cfg2::COMM_A::FULLDUPLEX);
+        self.spi.set_underrun_data(TARGET_CONFIG.underrun_byte);
+        // A TSIZE of 0 means the transfer lasts as long as the host wants.
+        // The divider is ignored, since the host provides the clock.
+        self.spi.enable(0, device::spi1::cfg1::MBR_A::DIV2);
+
+        let mut tx = TargetTx { len, pos: 0 };
+        self.load_target_fifo(&mut tx);
+        self.spi.enable_transfer_interrupts();
+        if tx.pos == tx.len {
+            self.spi.disable_can_tx_interrupt();
+        }
+        self.target.armed.set(Some(tx));
+        ringbuf_entry!(Trace::TargetPrepared(len as u32));
+        Ok(())
+    }
+
+    /// Waits for the host to finish the transfer armed by `target_prepare`,
+    /// which we learn from a notification in `cs_mask` (e.g. from an EXTI
+    /// interrupt on the rising edge of SS). Bytes the host sends are written
+    /// to `dest`, with any beyond its end discarded.
+    ///
+    /// Returns the number of bytes the host clocked; if that's more than were
+    /// prepared, the excess were underrun bytes. Either way, the controller
+    /// goes back to being a host.
+    #[cfg(feature = "target")]
+    pub fn target_wait_transfer<'b, BufWrite: BufWriter<'b>>(
+        &self,
+        dest: BufWrite,
+        cs_mask: u32,
+    ) -> Result<u32, TargetError> {
+        let Some(mut tx) = self.target.armed.get() else {
+            return Err(TargetError::NotPrepared);
+        };
+        let mut rx = Some(dest);
+        let mut clocked = 0u32;
+        let mut cs_released = false;
+        loop {
+            self.load_target_fifo(&mut tx);
+            if tx.pos == tx.len {
+                self.spi.disable_can_tx_interrupt();
+            }
+
+            while self.spi.can_rx_byte() {
+                let b = self.spi.recv8();
+                if BYTE_TRACE {
+                    ringbuf_entry!(Trace::Rx(b));
+                }
+                clocked = clocked.saturating_add(1);
+                if let Some(rx_writer) = &mut rx {
+                    if rx_writer.write(b).is_err() {
+                        if rx_writer.remaining_size() != 0 {
+                            self.target_stop();
+                            return Err(TargetError::ClientGone);
+                        }
+                        rx = None;
+                    }
+                }
+            }
+
+            // Unlike as a host, we can't hold off the other end when we fall
+            // behind, so this is an error rather than a bug.
+            if self.spi.check_overrun() {
+                self.target_stop();
+                return Err(TargetError::Overrun);
+            }
 
-        self.lock_holder.set(Some(LockState {
-            task: sender,
-            device_index: devidx,
-        }));
-        Ok(())
-    }
+            // Once SS has been released, we've just drained the last of the
+            // bytes, and we're done.
+            if cs_released {
+                break;
+            }
 
-    pub fn release(&self, sender: TaskId) -> Result<(), LockError> {
-        if let Some(lockstate) = &self.lock_holder.get() {
-            // The fact that we were able to receive this means we
-            // should be locked by the sender...but double check.
-            assert!(lockstate.task == sender);
+            sys_irq_control(self.irq_mask, true);
+            let bits = sys_recv_notification(
+                self.irq_mask | cs_mask | self.abort_mask,
+            );
+            if bits & self.abort_mask != 0 {
+                self.target_stop();
+                return Err(TargetError::Aborted);
+            }
+            cs_released = bits & cs_mask != 0;
+        }
 
-            let device = &CONFIG.devices[lockstate.device_index];
+        let underrun = self.spi.check_underrun();
+        self.target_stop();
+        ringbuf_entry!(Trace::TargetDone { clocked, underrun });
+        Ok(clocked)
+    }
 
-            for pin in device.cs {
-                // Deassert CS. If it wasn't asserted, this is a no-op.
-                // If it was, this fixes that.
-                self.sys.gpio_set(*pin);
+    /// Tops up the TX FIFO from the prepared target-mode bytes.
+    #[cfg(feature = "target")]
+    fn load_target_fifo(&self, tx: &mut TargetTx) {
+        while tx.pos < tx.len && self.spi.can_tx_frame() {
+            let b = self.target.tx_buf[tx.pos].get();
+            if BYTE_TRACE {
+                ringbuf_entry!(Trace::Tx(b));
             }
+            self.spi.send8(b);
+            tx.pos += 1;
+        }
+        self.target.armed.set(Some(*tx));
+    }
 
-            self.lock_holder.set(None);
-            Ok(())
-        } else {
-            Err(LockError(()))
+    /// Disarms target mode, returning the controller to the host role.
+    #[cfg(feature = "target")]
+    fn target_stop(&self) {
+        self.spi.end();
+        self.spi.set_target_mode(false);
+        self.target.armed.set(None);
+    }
+
+    /// Switches the pins over to mux option `mux_index`, if it isn't already
+    /// active.
+    fn select_mux_option(&self, mux_index: usize) {
+        let current_mux_index = self.current_mux_index.get();
+        if mux_index != current_mux_index {
+            ringbuf_entry!(Trace::MuxSwitch {
+                from: current_mux_index as u8,
+                to: mux_index as u8,
+            });
+            deactivate_mux_option(
+                &CONFIG.mux_options[current_mux_index],
+                self.spi.sck_idles_high(),
+                &self.sys,
+            );
+            let opt = &CONFIG.mux_options[mux_index];
+            activate_mux_option(
+                opt,
+                self.swap_override.get().swap_data(mux_index, opt),
+                &self.sys,
+                &self.spi,
+            );
+            // Remember this for later to avoid unnecessary
+            // switching.
+            self.current_mux_index.set(mux_index);
         }
     }
 
@@ -307,9 +1771,20 @@ impl SpiServerCore {
         device_index: u8,
         mut tx: Option<BufRead>,
         mut rx: Option<BufWrite>,
+        keep_cs_asserted: bool,
     ) -> Result<(), TransferError> {
         let device_index = usize::from(device_index);
 
+        // The peripheral can't be a host while it's waiting to be a target.
+        #[cfg(feature = "target")]
+        if self.target.armed.get().is_some() {
+            return Err(TransferError::Busy);
+        }
+        #[cfg(feature = "async-exchange")]
+        if self.async_in_progress() {
+            return Err(TransferError::Busy);
+        }
+
         // If we are locked, check that the caller isn't mistakenly
         // addressing the wrong device.
         if let Some(lockstate) = &self.lock_holder.get() {
@@ -333,14 +1808,15 @@ impl SpiServerCore {
 
         // Get the required transfer lengths in the src and dest directions.
         //
-        // Sizes that overflow a u16 are invalid and we reject them
-        let src_len: u16 = tx
+        // Transfers longer than TSIZE can express are fed to the peripheral in
+        // chunks (see `enable_chunked`), so the only limit is our counters.
+        let src_len: u32 = tx
             .as_ref()
             .map(|tx| tx.remaining_size())
             .unwrap_or(0)
             .try_into()
             .map_err(|_| TransferError::BadTransferSize)?;
-        let dest_len: u16 = rx
+        let dest_len: u32 = rx
             .as_ref()
             .map(|rx| rx.remaining_size())
             .unwrap_or(0)
@@ -348,41 +1824,102 @@ impl SpiServerCore {
             .map_err(|_| TransferError::BadTransferSize)?;
         let overall_len = src_len.max(dest_len);
 
+        // Apply any runtime override of the device's clock divider.
+        let device = &DeviceDescriptor {
+            clock_divider: self.clock_overrides[device_index]
+                .get()
+                .0
+                .unwrap_or(device.clock_divider),
+            ..*device
+        };
+
         // Zero-byte SPI transactions don't make sense and we'll
         // decline them.
         if overall_len == 0 {
             return Err(TransferError::BadTransferSize);
         }
 
+        // With wider frames, the peripheral can't send a partial frame, so
+        // the caller has to give us whole ones.
+        let frame_bytes = device.frame_bytes();
+        if overall_len % frame_bytes != 0 {
+            return Err(TransferError::BadTransferSize);
+        }
+
         // We have a reasonable-looking request containing reasonable-looking
         // lease(s). This is our commit point.
         ringbuf_entry!(Trace::Start(op, (src_len, dest_len)));
+        // Half-duplex devices move the two directions one after the other,
+        // rather than overlapping them.
+        let status_len = if device.is_half_duplex() {
+            src_len.saturating_add(dest_len)
+        } else {
+            overall_len
+        };
+        SPI_TRANSFER_STATUS.start(device_index as u8, status_len);
+        let start = sys_get_timer().now;
 
         // Switch the mux to the requested port.
-        let current_mux_index = self.current_mux_index.get();
-        if device.mux_index != current_mux_index {
-            deactivate_mux_option(
-                &CONFIG.mux_options[current_mux_index],
-                &self.sys,
+        if device.mux_index != self.current_mux_index.get() {
+            self.update_stats(device_index, |s| {
+                s.mux_switches = s.mux_switches.wrapping_add(1);
+            });
+        }
+        self.select_mux_option(device.mux_index);
+
+        // Apply per-device framing while the peripheral is still disabled.
+        // Changing CPOL moves SCK's idle level, so this has to happen before
+        // we assert CS below.
+        self.spi.set_clock_mode(device.cpol, device.cpha);
+        self.spi.set_frame_size(device.frame_size);
+        self.spi.set_bit_order(device.bit_order);
+        self.spi.set_protocol(device.protocol);
+        self.spi.set_inter_frame_gap(device.inter_frame_gap_cycles);
+        self.spi.set_crc(device.crc_poly);
+
+        // Half-duplex devices share one data line between directions, so they
+        // can't use the full-duplex engine below.
+        self.spi.set_comm_mode(device.comm_mode);
+        if device.is_half_duplex() {
+            let result = self.half_duplex_transfer(
+                device,
+                tx,
+                rx,
+                src_len,
+                dest_len,
+                keep_cs_asserted,
             );
-            activate_mux_option(
-                &CONFIG.mux_options[device.mux_index],
-                &self.sys,
-                &self.spi,
+            if result.is_ok() {
+                self.transfer_done(device_index, status_len, start);
+            }
+            return result;
+        }
+
+        // Large transfers go through DMA, unless the device wants a CRC: that
+        // would be appended to each DMA chunk, rather than to the transfer.
+        // The streams also move single bytes, so wider frames stay on the
+        // interrupt-driven path.
+        #[cfg(feature = "dma")]
+        if overall_len as usize >= DMA_CONFIG.threshold
+            && device.crc_poly.is_none()
+            && device.frame_size == 8
+        {
+            let result = self.dma_transfer(
+                device,
+                tx,
+                rx,
+                overall_len,
+                keep_cs_asserted,
             );
-            // Remember this for later to avoid unnecessary
-            // switching.
-            self.current_mux_index.set(device.mux_index);
+            if result.is_ok() {
+                self.transfer_done(device_index, status_len, start);
+            }
+            return result;
         }
 
-        // Make sure SPI is on.
-        //
-        // Due to driver limitations we will only move up to 64kiB
-        // per transaction. It would be worth lifting this
-        // limitation, maybe. Doing so would require managing data
-        // in 64kiB chunks (because the peripheral is 16-bit) and
-        // using the "reload" facility on the peripheral.
-        self.spi.enable(overall_len, device.clock_divider);
+        // Make sure SPI is on. TSIZE counts frames, not bytes.
+        let mut reload = self
+            .enable_chunked(overall_len / frame_bytes, device.clock_divider);
 
         // Load transfer count and start the state machine. At this
         // point we _have_ to move the specified number of bytes
@@ -409,9 +1946,7 @@ impl SpiServerCore {
         // We're doing this! Check if we need to control CS.
         let cs_override = self.lock_holder.get().is_some();
         if !cs_override {
-            for pin in device.cs {
-                self.sys.gpio_reset(*pin);
-            }
+            self.assert_device_cs(device);
         }
 
         // We use this to exert backpressure on the TX state machine as the RX
@@ -420,8 +1955,9 @@ impl SpiServerCore {
         // could read the configured FIFO size out of the block, but that does
         // not appear to be possible.
         //
-        // See reference manual table 409 for details.
-        let mut tx_permits = FIFO_DEPTH;
+        // See reference manual table 409 for details. The FIFO holds bytes,
+        // and wider frames take up several of them.
+        let mut tx_permits = FIFO_DEPTH / frame_bytes as usize;
 
         // Track number of bytes sent and received. Sent bytes will lead
         // received bytes. Received bytes indicate overall progress and
@@ -434,6 +1970,8 @@ impl SpiServerCore {
         // missing, because we have to pull bytes from the FIFO to avoid overrun
         // conditions.
         while rx_count < overall_len {
+            self.top_up_reload(&mut reload);
+
             // At the end of this loop we're going to sleep if there's no
             // obvious work to be done. Sleeping is not free, so, we only do it
             // if this flag is set. (It defaults to set, we'll clear it if work
@@ -449,29 +1987,49 @@ impl SpiServerCore {
                 && tx_permits > 0
                 && self.spi.can_tx_frame()
             {
-                // The next byte to TX will come from the caller, if we haven't
-                // run off the end of their lease, or the fixed padding byte if
-                // we have.
-                let byte = if let Some(txbuf) = &mut tx {
-                    // TODO: lint is buggy in 2024-04-04 toolchain, retest later
-                    #[allow(clippy::manual_unwrap_or_default)]
-                    if let Some(b) = txbuf.read() {
-                        b
+                // Gather one frame's worth of bytes. Each will come from the
+                // caller, if we haven't run off the end of their lease, or
+                // the device's padding byte if we have.
+                let mut frame = [0u8; 4];
+                for slot in &mut frame[..frame_bytes as usize] {
+                    let byte = if let Some(txbuf) = &mut tx {
+                        if let Some(b) = txbuf.read() {
+                            b
+                        } else if txbuf.remaining_size() != 0 {
+                            // The lease has bytes left but we couldn't read
+                            // them, so the client is gone. Don't keep
+                            // clocking the bus on its behalf.
+                            return Err(self.fail_transfer(
+                                device,
+                                false,
+                                TransferError::ClientGone,
+                            ));
+                        } else {
+                            // We've hit the end of the lease. Stop checking.
+                            tx = None;
+                            device.idle_byte
+                        }
                     } else {
-                        // We've hit the end of the lease. Stop checking.
-                        tx = None;
-                        0
+                        device.idle_byte
+                    };
+
+                    if BYTE_TRACE {
+                        ringbuf_entry!(Trace::Tx(byte));
                     }
-                } else {
-                    0
-                };
+                    *slot = byte;
+                }
 
-                ringbuf_entry!(Trace::Tx(byte));
-                self.spi.send8(byte);
-                tx_count += 1;
+                match frame_bytes {
+                    1 => self.spi.send8(frame[0]),
+                    2 => self
+                        .spi
+                        .send16(u16::from_be_bytes([frame[0], frame[1]])),
+                    _ => self.spi.send32(u32::from_be_bytes(frame)),
+                }
+                tx_count += frame_bytes;
 
-                // Consume one TX permit to make sure we don't overrun the RX
-                // fifo.
+                // Consume one TX permit per frame to make sure we don't
+                // overrun the RX fifo.
                 tx_permits -= 1;
 
                 if tx_permits == 0 || tx_count == overall_len {
@@ -488,8 +2046,14 @@ impl SpiServerCore {
                 // decision.
             }
 
-            // Drain bytes from the RX FIFO.
-            while self.spi.can_rx_byte() {
+            // Drain frames from the RX FIFO. With 8-bit frames we can take
+            // bytes as soon as they land; wider frames have to wait for the
+            // whole frame.
+            while if frame_bytes == 1 {
+                self.spi.can_rx_byte()
+            } else {
+                self.spi.can_rx_frame()
+            } {
                 // We didn't check rx_count < overall_len above because, if we
                 // got to that point, it would mean the SPI hardware gave us
                 // more bytes than we sent. This would be bad. And so, we'll
@@ -498,20 +2062,42 @@ impl SpiServerCore {
                     panic!();
                 }
 
-                // Pull byte from RX FIFO.
-                let b = self.spi.recv8();
-                ringbuf_entry!(Trace::Rx(b));
-                rx_count += 1;
+                // Pull a frame from the RX FIFO.
+                let frame = match frame_bytes {
+                    1 => [self.spi.recv8(), 0, 0, 0],
+                    2 => {
+                        let [a, b] = self.spi.recv16().to_be_bytes();
+                        [a, b, 0, 0]
+                    }
+                    _ => self.spi.recv32().to_be_bytes(),
+                };
+                rx_count += frame_bytes;
+                SPI_TRANSFER_STATUS.set_progress(rx_count);
 
-                // Allow another byte to be inserted in the TX FIFO.
+                // Allow another frame to be inserted in the TX FIFO.
                 tx_permits += 1;
 
-                // Deposit the byte if we're still within the bounds of the
-                // caller's incoming lease.
-                if let Some(rx_reader) = &mut rx {
-                    if rx_reader.write(b).is_err() {
-                        // We're off the end. Stop checking.
-                        rx = None;
+                for &b in &frame[..frame_bytes as usize] {
+                    if BYTE_TRACE {
+                        ringbuf_entry!(Trace::Rx(b));
+                    }
+
+                    // Deposit the byte if we're still within the bounds of
+                    // the caller's incoming lease.
+                    if let Some(rx_reader) = &mut rx {
+                        if rx_reader.write(b).is_err() {
+                            if rx_reader.remaining_size() != 0 {
+                                // As with TX, a failure short of the end of
+                                // the lease means the client is gone.
+                                return Err(self.fail_transfer(
+                                    device,
+                                    false,
+                                    TransferError::ClientGone,
+                                ));
+                            }
+                            // We're off the end. Stop checking.
+                            rx = None;
+                        }
                     }
                 }
 
@@ -530,17 +2116,9 @@ impl SpiServerCore {
             }
 
             if should_sleep {
-                ringbuf_entry!(Trace::WaitISR(self.spi.read_status()));
-
-                if self.spi.check_overrun() {
-                    panic!();
+                if let Err(e) = self.wait_for_irq() {
+                    return Err(self.fail_transfer(device, cs_override, e));
                 }
-
-                // Allow the controller interrupt to post to our
-                // notification set.
-                sys_irq_control(self.irq_mask, true);
-                // Wait for our notification set to get, well, set.
-                sys_recv_notification(self.irq_mask);
             }
         }
 
@@ -549,68 +2127,445 @@ impl SpiServerCore {
         if !self.spi.check_eot() {
             panic!();
         }
+
+        // With the data done, the peripheral has also exchanged CRCs, if this
+        // device uses them.
+        if device.crc_poly.is_some() && self.spi.check_crc_error() {
+            return Err(self.fail_transfer(
+                device,
+                cs_override,
+                TransferError::CrcError,
+            ));
+        }
         self.spi.clear_eot();
 
         // Wrap up the transfer and restore things to a reasonable
         // state.
         self.spi.end();
 
-        // Deassert (set) CS, if we asserted it in the first place.
+        // Deassert (set) CS, if we asserted it in the first place and the
+        // caller doesn't want to continue the transaction.
+        if !cs_override && !keep_cs_asserted {
+            self.deassert_device_cs(device);
+        }
+
+        self.transfer_done(device_index, status_len, start);
+        Ok(())
+    }
+
+    /// Moves bytes to and from a half-duplex device. Unlike full-duplex
+    /// transfers, the two directions happen one after the other: first we
+    /// write all of `tx` (if any), then turn the data line around and read
+    /// into `rx` (if any). CS stays asserted across both phases.
+    ///
+    /// `src_len` and `dest_len` must be the remaining sizes of `tx` and `rx`,
+    /// as checked by `ready_writey`, and `keep_cs_asserted` is as passed to
+    /// it.
+    fn half_duplex_transfer<
+        'b,
+        BufRead: BufReader<'b>,
+        BufWrite: BufWriter<'b>,
+    >(
+        &self,
+        device: &DeviceDescriptor,
+        tx: Option<BufRead>,
+        rx: Option<BufWrite>,
+        src_len: u32,
+        dest_len: u32,
+        keep_cs_asserted: bool,
+    ) -> Result<(), TransferError> {
+        let cs_override = self.lock_holder.get().is_some();
+        if !cs_override {
+            self.assert_device_cs(device);
+        }
+
+        // A zero transfer size means "go forever" to the peripheral, so skip
+        // any empty phase.
+        if let Some(mut tx) = tx.filter(|_| src_len != 0) {
+            self.spi.set_half_duplex_direction(true);
+            let mut reload = self.enable_chunked(src_len, device.clock_divider);
+            self.spi.start();
+            self.spi.enable_transfer_interrupts();
+            self.spi.clear_eot();
+
+            let mut tx_count = 0;
+            while tx_count < src_len {
+                self.top_up_reload(&mut reload);
+                if self.spi.can_tx_frame() {
+                    // The lease holds exactly `src_len` bytes, so failing to
+                    // read one means the client is gone.
+                    let Some(byte) = tx.read() else {
+                        return Err(self.fail_transfer(
+                            device,
+                            false,
+                            TransferError::ClientGone,
+                        ));
+                    };
+                    if BYTE_TRACE {
+                        ringbuf_entry!(Trace::Tx(byte));
+                    }
+                    self.spi.send8(byte);
+                    tx_count += 1;
+                    SPI_TRANSFER_STATUS.set_progress(tx_count);
+                } else if let Err(e) = self.wait_for_irq() {
+                    return Err(self.fail_transfer(device, cs_override, e));
+                }
+            }
+            self.spi.disable_can_tx_interrupt();
+            if let Err(e) = self.finish_phase(device) {
+                return Err(self.fail_transfer(device, cs_override, e));
+            }
+        }
+
+        if let Some(mut rx) = rx.filter(|_| dest_len != 0) {
+            self.spi.set_half_duplex_direction(false);
+            let mut reload =
+                self.enable_chunked(dest_len, device.clock_divider);
+            self.spi.start();
+            self.spi.enable_transfer_interrupts();
+            self.spi.clear_eot();
+
+            let mut rx_count = 0;
+            while rx_count < dest_len {
+                self.top_up_reload(&mut reload);
+                if self.spi.can_rx_byte() {
+                    let b = self.spi.recv8();
+                    if BYTE_TRACE {
+                        ringbuf_entry!(Trace::Rx(b));
+                    }
+                    rx_count += 1;
+                    SPI_TRANSFER_STATUS.set_progress(src_len + rx_count);
+                    // As above, the lease is exactly `dest_len` bytes long.
+                    if rx.write(b).is_err() {
+                        return Err(self.fail_transfer(
+                            device,
+                            false,
+                            TransferError::ClientGone,
+                        ));
+                    }
+                } else if let Err(e) = self.wait_for_irq() {
+                    return Err(self.fail_transfer(device, cs_override, e));
+                }
+            }
+            if let Err(e) = self.finish_phase(device) {
+                return Err(self.fail_transfer(device, cs_override, e));
+            }
+        }
+
+        if !cs_override && !keep_cs_asserted {
+            self.deassert_device_cs(device);
+        }
+        Ok(())
+    }
+
+    /// Moves bytes to and from a full-duplex device using the DMA streams, one
+    /// bounce buffer's worth at a time. The task sleeps while each chunk is
+    /// on the wire, waking only for the end-of-transfer interrupt.
+    ///
+    /// The peripheral is stopped between chunks while we copy to and from the
+    /// caller's leases, so SCK pauses there; CS stays asserted throughout.
+    /// `overall_len` and `keep_cs_asserted` are as in `ready_writey`.
+    #[cfg(feature = "dma")]
+    fn dma_transfer<'b, BufRead: BufReader<'b>, BufWrite: BufWriter<'b>>(
+        &self,
+        device: &DeviceDescriptor,
+        mut tx: Option<BufRead>,
+        mut rx: Option<BufWrite>,
+        overall_len: u32,
+        keep_cs_asserted: bool,
+    ) -> Result<(), TransferError> {
+        let dma = &self.dma;
+
+        let cs_override = self.lock_holder.get().is_some();
+        if !cs_override {
+            self.assert_device_cs(device);
+        }
+
+        let mut done = 0;
+        while done < overall_len {
+            let chunk = (overall_len - done).min(DMA_BUFFER_SIZE as u32);
+            let len = chunk as usize;
+
+            // Fill the TX buffer from the caller's lease, padding with the
+            // idle byte once it runs out, as in the CPU-driven path.
+            for cell in &dma.tx_buf[..len] {
+                let byte = if let Some(txbuf) = &mut tx {
+                    if let Some(b) = txbuf.read() {
+                        b
+                    } else if txbuf.remaining_size() != 0 {
+                        return Err(self.fail_transfer(
+                            device,
+                            false,
+                            TransferError::ClientGone,
+                        ));
+                    } else {
+                        tx = None;
+                        device.idle_byte
+                    }
+                } else {
+                    device.idle_byte
+                };
+                if BYTE_TRACE {
+                    ringbuf_entry!(Trace::Tx(byte));
+                }
+                cell.set(byte);
+            }
+
+            // This is the order the reference manual asks for: RX requests
+            // on, streams on, TX requests on, then the peripheral.
+            dma.rx.configure(
+                DMA_CONFIG.rx_request,
+                Direction::PeripheralToMemory,
+                self.spi.rxdr_address(),
+                dma.rx_buf.as_ptr() as u32,
+                chunk as u16,
+            );
+            dma.tx.configure(
+                DMA_CONFIG.tx_request,
+                Direction::MemoryToPeripheral,
+                self.spi.txdr_address(),
+                dma.tx_buf.as_ptr() as u32,
+                chunk as u16,
+            );
+            self.spi.set_dma_requests(false, true);
+            dma.rx.enable();
+            dma.tx.enable();
+            self.spi.set_dma_requests(true, true);
+            self.spi.enable(chunk as u16, device.clock_divider);
+            self.spi.start();
+            self.spi.enable_eot_interrupt();
+
+            while !self.spi.check_eot() {
+                if let Err(e) = self.wait_for_irq() {
+                    let err = self.fail_transfer(device, cs_override, e);
+                    self.stop_dma();
+                    return Err(err);
+                }
+            }
+            // EOT means the last frame has been received, but the RX stream
+            // may still be pulling it out of the FIFO.
+            while !dma.rx.is_complete() {
+                // The buffers are checked at startup, so a bus error here
+                // means something is badly wrong.
+                if dma.rx.has_error() {
+                    panic!();
+                }
+            }
+            self.spi.clear_eot();
+            self.spi.end();
+            self.stop_dma();
+
+            // Hand what we received to the caller, if they want it.
+            for cell in &dma.rx_buf[..len] {
+                let b = cell.get();
+                if BYTE_TRACE {
+                    ringbuf_entry!(Trace::Rx(b));
+                }
+                if let Some(rx_writer) = &mut rx {
+                    if rx_writer.write(b).is_err() {
+                        if rx_writer.remaining_size() != 0 {
+                            return Err(self.fail_transfer(
+                                device,
+                                false,
+                                TransferError::ClientGone,
+                            ));
+                        }
+                        rx = None;
+                    }
+                }
+            }
+
+            done += chunk;
+            SPI_TRANSFER_STATUS.set_progress(done);
+        }
+
+        if !cs_override && !keep_cs_asserted {
+            self.deassert_device_cs(device);
+        }
+        Ok(())
+    }
+
+    /// Shuts off both DMA streams and the peripheral's DMA requests, so that
+    /// the next transfer can use either path. The peripheral must be
+    /// disabled.
+    #[cfg(feature = "dma")]
+    fn stop_dma(&self) {
+        self.dma.tx.disable();
+        self.dma.rx.disable();
+        self.spi.set_dma_requests(false, false);
+    }
+
+    /// Enables the peripheral for a transfer of `len` frames. TSIZE only
+    /// holds 16 bits, so anything past the first 64 KiB is fed in through the
+    /// reload register (TSER) as the transfer runs; this returns the number
+    /// of frames still to be handed to `top_up_reload`.
+    fn enable_chunked(&self, len: u32, div: device::spi1::cfg1::MBR_A) -> u32 {
+        let first = len.min(MAX_TSIZE);
+        self.spi.enable(first as u16, div);
+        self.spi.clear_reload();
+
+        let mut pending = len - first;
+        if pending != 0 {
+            let next = pending.min(MAX_TSIZE);
+            self.spi.set_reload(next as u16);
+            pending -= next;
+        }
+        pending
+    }
+
+    /// Once the peripheral has taken the last reload count, gives it the next
+    /// chunk of the `pending` frames. This has to be called more often than
+    /// every 64 KiB of progress, which the transfer loops do easily.
+    fn top_up_reload(&self, pending: &mut u32) {
+        if *pending != 0 && self.spi.check_reload() {
+            self.spi.clear_reload();
+            let next = (*pending).min(MAX_TSIZE);
+            self.spi.set_reload(next as u16);
+            *pending -= next;
+        }
+    }
+
+    /// Waits for the end of a half-duplex phase and shuts the peripheral
+    /// down, so that the direction can be changed.
+    fn finish_phase(
+        &self,
+        device: &DeviceDescriptor,
+    ) -> Result<(), TransferError> {
+        while !self.spi.check_eot() {
+            self.wait_for_irq()?;
+        }
+        if device.crc_poly.is_some() && self.spi.check_crc_error() {
+            return Err(TransferError::CrcError);
+        }
+        self.spi.clear_eot();
+        self.spi.end();
+        Ok(())
+    }
+
+    /// Sleeps until the controller interrupt fires, or returns
+    /// `TransferError::Aborted` if an abort notification arrives first. If the
+    /// RX FIFO has overflowed, returns `TransferError::Overrun` instead of
+    /// sleeping, since the transfer can't be finished.
+    fn wait_for_irq(&self) -> Result<(), TransferError> {
+        let status = self.spi.read_status();
+        ringbuf_entry!(Trace::WaitISR(status));
+
+        if self.spi.check_overrun() {
+            ringbuf_entry!(Trace::Overrun {
+                device_index: SPI_TRANSFER_STATUS.device_index() as u8,
+                status,
+            });
+            return Err(TransferError::Overrun);
+        }
+
+        // Allow the controller interrupt to post to our notification set.
+        sys_irq_control(self.irq_mask, true);
+        // Wait for our notification set to get, well, set.
+        let bits = sys_recv_notification(self.irq_mask | self.abort_mask);
+        if bits & self.abort_mask != 0 {
+            return Err(TransferError::Aborted);
+        }
+        Ok(())
+    }
+
+    /// Asserts (resets) CS for `device`, then waits out its CS setup time so
+    /// that the first SCK edge doesn't come too soon after.
+    fn assert_device_cs(&self, device: &DeviceDescriptor) {
+        assert_cs(device.cs, &self.sys);
+        delay_us(device.cs_setup_us);
+    }
+
+    /// Waits out `device`'s CS hold time after the last SCK edge, then
+    /// deasserts (sets) its CS.
+    fn deassert_device_cs(&self, device: &DeviceDescriptor) {
+        delay_us(device.cs_hold_us);
+        deassert_cs(device.cs, &self.sys);
+    }
+
+    /// Abandons the transfer in progress because of `err`, leaving the
+    /// controller ready for the next one. Returns `err` for convenience.
+    fn fail_transfer(
+        &self,
+        device: &DeviceDescriptor,
+        cs_override: bool,
+        err: TransferError,
+    ) -> TransferError {
+        ringbuf_entry!(Trace::TransferFailed(err));
+        SPI_TRANSFER_STATUS.finish();
+        self.update_stats(SPI_TRANSFER_STATUS.device_index(), |s| {
+            s.errors = s.errors.wrapping_add(1);
+            if err == TransferError::Overrun {
+                s.overruns = s.overruns.wrapping_add(1);
+            }
+        });
+
+        // Disabling the peripheral stops the clock and discards whatever is
+        // left in the FIFOs, and `end` also clears the error flags (including
+        // overrun), so the controller is ready for the next transfer.
+        self.spi.end();
+
+        // As with a completed transfer, CS is left alone if the caller holds
+        // the lock and is managing it. (Callers pass `cs_override: false` if
+        // the client has died, since then nobody is managing it.)
         if !cs_override {
             for pin in device.cs {
                 self.sys.gpio_set(*pin);
             }
         }
+        err
+    }
+}
 
-        Ok(())
+/// Busy-waits for at least `us` microseconds. CS timing is far shorter than
+/// a timer tick, so we can't sleep for it.
+fn delay_us(us: u16) {
+    if us != 0 {
+        cortex_m::asm::delay(u32::from(us) * CPU_CYCLES_PER_US);
     }
 }
 
-fn deactivate_mux_option(opt: &SpiMuxOption, gpio: &sys_api::Sys) {
-    // Drive all output pins low.
-    for &(pins, _af) in opt.outputs {
-        gpio.gpio_reset(pins);
-        gpio.gpio_configure_output(
-            pins,
-            sys_api::OutputType::PushPull,
-            sys_api::Speed::Low,
-            sys_api::Pull::None,
-        );
+/// Computes an 8-bit CRC the way the SPI block does with CRCSIZE = 8 bits and
+/// TCRCINI clear: MSB first, starting from zero, with no final XOR.
+#[cfg(feature = "diagnostics")]
+fn crc8(poly: u8, data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &b in data {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ poly
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn mbr_for(divider: ClockDivider) -> device::spi1::cfg1::MBR_A {
+    use device::spi1::cfg1::MBR_A;
+    match divider {
+        ClockDivider::Div2 => MBR_A::DIV2,
+        ClockDivider::Div4 => MBR_A::DIV4,
+        ClockDivider::Div8 => MBR_A::DIV8,
+        ClockDivider::Div16 => MBR_A::DIV16,
+        ClockDivider::Div32 => MBR_A::DIV32,
+        ClockDivider::Div64 => MBR_A::DIV64,
+        ClockDivider::Div128 => MBR_A::DIV128,
+        ClockDivider::Div256 => MBR_A::DIV256,
     }
-    // Switch input pin away from SPI peripheral to a GPIO input, which makes it
-    // Hi-Z.
-    gpio.gpio_configure_input(opt.input.0, sys_api::Pull::None);
 }
 
 fn activate_mux_option(
     opt: &SpiMuxOption,
-    gpio: &sys_api::Sys,
+    swap_data: bool,
+    gpio: &impl SpiSys,
     spi: &spi_core::Spi,
 ) {
-    // Apply the data line swap if requested.
-    spi.set_data_line_swap(opt.swap_data);
-    // Switch all outputs to the SPI peripheral.
-    for &(pins, af) in opt.outputs {
-        gpio.gpio_configure(
-            pins.port,
-            pins.pin_mask,
-            sys_api::Mode::Alternate,
-            sys_api::OutputType::PushPull,
-            sys_api::Speed::Low,
-            sys_api::Pull::None,
-            af,
-        );
-    }
-    // And the input too.
-    gpio.gpio_configure(
-        opt.input.0.port,
-        opt.input.0.pin_mask,
-        sys_api::Mode::Alternate,
-        sys_api::OutputType::PushPull, // doesn't matter
-        sys_api::Speed::High,          // doesn't matter
-        sys_api::Pull::None,
-        opt.input.1,
-    );
+    // Apply the data line swap if requested. This is usually the option's
+    // configured `swap_data`, unless it has been overridden at runtime.
+    spi.set_data_line_swap(swap_data);
+    route_mux_option(opt, gpio);
 }
 
 //////////////////////////////////////////////////////////////////////////////
@@ -637,27 +2592,12 @@ struct ServerConfig {
     /// We keep track of a fixed set of devices per SPI controller, which each
     /// have an associated routing (from `mux_options`) and CS pin.
     devices: &'static [DeviceDescriptor],
-}
-
-/// A routing of the SPI controller onto pins.
-#[derive(Copy, Clone, Debug)]
-struct SpiMuxOption {
-    /// A list of config changes to apply to activate the output pins of this
-    /// mux option. This is a list because some mux options are spread across
-    /// multiple ports, or (in at least one case) the pins in the same port
-    /// require different AF numbers to work.
-    ///
-    /// To disable the mux, we'll force these pins low. This is correct for SPI
-    /// mode 0/1 but not mode 2/3; fortunately we currently don't support mode
-    /// 2/3, so we can simplify.
-    outputs: &'static [(PinSet, sys_api::Alternate)],
-    /// A list of config changes to apply to activate the input pins of this mux
-    /// option. This is _not_ a list because there's only one such pin, CIPO.
-    ///
-    /// To disable the mux, we'll switch this pin to HiZ.
-    input: (PinSet, sys_api::Alternate),
-    /// Swap data lines?
-    swap_data: bool,
+    /// Fastest SCK (smallest divider) that any device on this controller may
+    /// use, checked at startup.
+    min_clock_divider: device::spi1::cfg1::MBR_A,
+    /// Slowest SCK (largest divider) that any device on this controller may
+    /// use, checked at startup.
+    max_clock_divider: device::spi1::cfg1::MBR_A,
 }
 
 /// Information about one device attached to the SPI controller.
@@ -673,6 +2613,50 @@ struct DeviceDescriptor {
     /// Clock divider to apply while speaking with this device. Yes, this says
     /// spi1 no matter which SPI block we're in charge of.
     clock_divider: device::spi1::cfg1::MBR_A,
+    /// Byte to shift out when the caller has no (more) data to send, e.g.
+    /// during a `read`. Some parts, such as SPI NOR flash, expect 0xFF here.
+    idle_byte: u8,
+    /// Full- or half-duplex. Yes, this also says spi1.
+    comm_mode: device::spi1::cfg2::COMM_A,
+    /// Idle SCK cycles inserted by the peripheral between frames, for slow
+    /// devices. 0 sends frames back-to-back.
+    inter_frame_gap_cycles: u8,
+    /// If set, the peripheral appends an 8-bit CRC using this polynomial to
+    /// each transfer, and checks the one the device sends back. The CRC bytes
+    /// are not counted in (or visible to) the caller's buffers.
+    crc_poly: Option<u8>,
+    /// Clock polarity and phase, which together make up the device's SPI
+    /// mode. These also say spi1.
+    cpol: device::spi1::cfg2::CPOL_A,
+    cpha: device::spi1::cfg2::CPHA_A,
+    /// Time to wait between asserting CS and starting the clock, and between
+    /// the end of the clock and deasserting CS, in microseconds. These only
+    /// apply when the server drives CS itself, not while a lock holder has
+    /// overridden it.
+    cs_setup_us: u16,
+    cs_hold_us: u16,
+    /// Bits per frame: 8, 16 or 32. Wider frames are packed from (and
+    /// unpacked into) the caller's buffers most significant byte first, and
+    /// transfers must be a whole number of frames long.
+    frame_size: u8,
+    /// Bit order within each frame. LSB-first is only allowed with 8-bit
+    /// frames, since our packing of wider frames assumes MSB-first.
+    bit_order: device::spi1::cfg2::LSBFRST_A,
+    /// Motorola (ordinary SPI) or TI frame format. In TI format, the frame
+    /// sync comes from the peripheral's own SS pin, and `cpol`, `cpha` and
+    /// `bit_order` must be left at their defaults.
+    protocol: device::spi1::cfg2::SP_A,
+}
+
+impl DeviceDescriptor {
+    fn is_half_duplex(&self) -> bool {
+        self.comm_mode == device::spi1::cfg2::COMM_A::HALFDUPLEX
+    }
+
+    /// Bytes of the caller's buffers moved per frame.
+    fn frame_bytes(&self) -> u32 {
+        u32::from(self.frame_size / 8)
+    }
 }
 
 /// Any impl of ServerConfig for Server has to pass these tests at startup.
@@ -685,8 +2669,12 @@ fn check_server_config() {
 
     assert!(!CONFIG.registers.is_null()); // let's start off easy.
 
-    // Mux options must be provided.
+    // The lease buffer lives on the server's stack, so keep it within reason.
+    assert!(LEASE_BUFFER_SIZE > 0 && LEASE_BUFFER_SIZE <= 256);
+
+    // Mux options must be provided, and must fit in a `SwapOverride` mask.
     assert!(!CONFIG.mux_options.is_empty());
+    assert!(CONFIG.mux_options.len() <= 32);
     for muxopt in CONFIG.mux_options {
         // Each mux option must contain at least one output config record.
         assert!(!muxopt.outputs.is_empty());
@@ -696,8 +2684,10 @@ fn check_server_config() {
             assert!(pinset.pin_mask != 0);
             // If this is the same port as the input pin, it must not _include_
             // the input pin.
-            if pinset.port == muxopt.input.0.port {
-                assert!(pinset.pin_mask & muxopt.input.0.pin_mask == 0);
+            if let Some((input, _af)) = muxopt.input {
+                if pinset.port == input.port {
+                    assert!(pinset.pin_mask & input.pin_mask == 0);
+                }
             }
             // We're counting how many total pins are controlled here.
             total_pins += pinset.pin_mask.count_ones();
@@ -705,16 +2695,66 @@ fn check_server_config() {
         // There should be two affected output pins (COPI, SCK). This check
         // prevents people from being clever and trying to mux SPI to two
         // locations simultaneously, which Does Not Work. It also catches
-        // mistakenly including CIPO in the outputs set.
+        // mistakenly including CIPO in the outputs set. Any other pins the
+        // option needs belong in `extra_pins`.
         assert!(total_pins == 2);
-        // There should be exactly one pin in the input set.
-        assert!(muxopt.input.0.pin_mask.count_ones() == 1);
+        // There should be exactly one pin in the input set, if any.
+        if let Some((input, _af)) = muxopt.input {
+            assert!(input.pin_mask.count_ones() == 1);
+        }
+        // Extra pins must not overlap with the outputs or the input, so that
+        // they can't be used to sneak a second routing past the check above.
+        for (extra, _af) in muxopt.extra_pins {
+            assert!(extra.pin_mask != 0);
+            for (pinset, _af) in muxopt.outputs {
+                if pinset.port == extra.port {
+                    assert!(pinset.pin_mask & extra.pin_mask == 0);
+                }
+            }
+            if let Some((input, _af)) = muxopt.input {
+                if input.port == extra.port {
+                    assert!(input.pin_mask & extra.pin_mask == 0);
+                }
+            }
+        }
     }
-    // At least one device must be defined.
-    assert!(!CONFIG.devices.is_empty());
+    // At least one device must be defined, unless the controller is only
+    // used as a target, and device indices are a `u8`.
+    assert!(cfg!(feature = "target") || !CONFIG.devices.is_empty());
+    assert!(CONFIG.devices.len() <= usize::from(u8::MAX));
     for dev in CONFIG.devices {
         // Mux index must be valid.
         assert!(dev.mux_index < CONFIG.mux_options.len());
+        // Only half-duplex devices can do without an input pin.
+        assert!(
+            dev.is_half_duplex()
+                || CONFIG.mux_options[dev.mux_index].input.is_some()
+        );
+
+        // The clock divider must be within the controller's allowed range,
+        // so that a typo can't clock a device faster than it's rated for.
+        // MBR_A's discriminants increase with the divider.
+        assert!(dev.clock_divider as u8 >= CONFIG.min_clock_divider as u8);
+        assert!(dev.clock_divider as u8 <= CONFIG.max_clock_divider as u8);
+
+        // MIDI is a 4-bit field.
+        assert!(dev.inter_frame_gap_cycles <= 15);
+
+        // We only pack whole bytes into frames, and the half-duplex engine
+        // only speaks bytes.
+        assert!(matches!(dev.frame_size, 8 | 16 | 32));
+        assert!(!dev.is_half_duplex() || dev.frame_size == 8);
+        assert!(
+            dev.bit_order == device::spi1::cfg2::LSBFRST_A::MSBFIRST
+                || dev.frame_size == 8
+        );
+        assert!(
+            dev.protocol == device::spi1::cfg2::SP_A::MOTOROLA
+                || (dev.cpol == device::spi1::cfg2::CPOL_A::IDLELOW
+                    && dev.cpha == device::spi1::cfg2::CPHA_A::FIRSTEDGE
+                    && dev.bit_order
+                        == device::spi1::cfg2::LSBFRST_A::MSBFIRST)
+        );
 
         for pin in dev.cs {
             // A CS pin must designate _exactly one_ pin in its mask.
@@ -725,42 +2765,45 @@ fn check_server_config() {
 
 ////////////////////////////////////////////////////////////////////////////////
 
-impl SpiServer for SpiServerCore {
+/// Converts a transfer error for a caller using the `SpiServerCore` directly.
+fn local_transfer_error(e: TransferError) -> SpiError {
+    match e {
+        // If the SPI server was in a remote task, this case would return a
+        // reply-fault; therefore, panicking the task when the SPI driver is
+        // local to that task is appropriate.
+        TransferError::BadDevice => panic!(),
+        TransferError::BadTransferSize => SpiError::BadTransferSize,
+        TransferError::Aborted => SpiError::Aborted,
+        TransferError::CrcError => SpiError::CrcError,
+        // Local buffers can't go away underneath us.
+        TransferError::ClientGone => panic!(),
+        TransferError::Busy => SpiError::Busy,
+        // Only the asynchronous exchanges, which aren't used locally, return
+        // this.
+        TransferError::NotStarted => panic!(),
+        TransferError::Overrun => SpiError::Overrun,
+    }
+}
+
+impl<S: SpiSys> SpiServer for SpiServerCore<S> {
     fn exchange(
         &self,
         device_index: u8,
         src: &[u8],
         dest: &mut [u8],
     ) -> Result<(), SpiError> {
-        SpiServerCore::exchange(self, device_index, src, dest).map_err(|e| {
-            match e {
-                // If the SPI server was in a remote task, this case would
-                // return a reply-fault; therefore, panicking the task when the
-                // SPI driver is local to that task is appropriate.
-                TransferError::BadDevice => panic!(),
-                TransferError::BadTransferSize => SpiError::BadTransferSize,
-            }
-        })
+        SpiServerCore::exchange(self, device_index, src, dest, false)
+            .map_err(local_transfer_error)
     }
 
     fn write(&self, device_index: u8, src: &[u8]) -> Result<(), SpiError> {
-        SpiServerCore::write(self, device_index, src).map_err(|e| match e {
-            // If the SPI server was in a remote task, this case would
-            // return a reply-fault; therefore, panicking the task when the
-            // SPI driver is local to that task is appropriate.
-            TransferError::BadDevice => panic!(),
-            TransferError::BadTransferSize => SpiError::BadTransferSize,
-        })
+        SpiServerCore::write(self, device_index, src, false)
+            .map_err(local_transfer_error)
     }
 
     fn read(&self, device_index: u8, dest: &mut [u8]) -> Result<(), SpiError> {
-        SpiServerCore::read(self, device_index, dest).map_err(|e| match e {
-            // If the SPI server was in a remote task, this case would
-            // return a reply-fault; therefore, panicking the task when the
-            // SPI driver is local to that task is appropriate.
-            TransferError::BadDevice => panic!(),
-            TransferError::BadTransferSize => SpiError::BadTransferSize,
-        })
+        SpiServerCore::read(self, device_index, dest, false)
+            .map_err(local_transfer_error)
     }
 
     fn lock(
@@ -783,24 +2826,91 @@ impl SpiServer for SpiServerCore {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Reads all of `first` and then all of `second`, so that they can be sent as
+/// one transfer.
+#[cfg(feature = "command-exchange")]
+struct ChainReader<A, B> {
+    first: A,
+    second: B,
+}
+
+#[cfg(feature = "command-exchange")]
+impl<'b, A: BufReader<'b>, B: BufReader<'b>> BufReader<'b>
+    for ChainReader<A, B>
+{
+    fn remaining_size(&self) -> usize {
+        self.first.remaining_size() + self.second.remaining_size()
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        // If `first` can't be read despite having bytes left, we return
+        // `None` with a nonzero remaining size, which the transfer engines
+        // take to mean that the client is gone.
+        if self.first.remaining_size() != 0 {
+            self.first.read()
+        } else {
+            self.second.read()
+        }
+    }
+}
+
+/// Throws away the first `skip` bytes written, passing the rest to `inner`.
+#[cfg(feature = "command-exchange")]
+struct SkipWriter<W> {
+    skip: usize,
+    inner: W,
+}
+
+#[cfg(feature = "command-exchange")]
+impl<'b, W: BufWriter<'b>> BufWriter<'b> for SkipWriter<W> {
+    fn remaining_size(&self) -> usize {
+        self.skip + self.inner.remaining_size()
+    }
+
+    fn write(&mut self, val: u8) -> Result<(), ()> {
+        if self.skip != 0 {
+            self.skip -= 1;
+            Ok(())
+        } else {
+            self.inner.write(val)
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub use drv_spi_api::SpiDeviceStats;
 pub use mutable_statics::mutable_statics as __mutable_statics_reexport;
 
 #[macro_export]
 macro_rules! declare_spi_core {
-    ($sys:expr, $irq_mask:expr) => {{
-        let (lock_holder, current_mux_index) =
+    ($sys:expr, $irq_mask:expr) => {
+        $crate::declare_spi_core!($sys, $irq_mask, 0)
+    };
+    ($sys:expr, $irq_mask:expr, $abort_mask:expr) => {{
+        let (lock_holder, current_mux_index, swap_override, clock_overrides) =
             $crate::__mutable_statics_reexport!(
                 static mut LOCK_HOLDER: [core::cell::Cell<
                     Option<$crate::LockState>,
                 >; 1] = [|| core::cell::Cell::new(None); _];
                 static mut MUX_INDEX: [core::cell::Cell<usize>; 1] =
                     [|| core::cell::Cell::new(0); _];
+                static mut SWAP_OVERRIDE: [core::cell::Cell<
+                    $crate::SwapOverride,
+                >; 1] = [|| core::cell::Cell::new(Default::default()); _];
+                static mut CLOCK_OVERRIDES: [core::cell::Cell<
+                    $crate::ClockOverride,
+                >; $crate::DEVICE_COUNT] =
+                    [|| core::cell::Cell::new(Default::default()); _];
             );
         $crate::SpiServerCore::init(
             $sys,
             $irq_mask,
+            $abort_mask,
             &lock_holder[0],
             &current_mux_index[0],
+            &swap_override[0],
+            clock_overrides,
         )
     }}
 }
@@ -808,3 +2918,6 @@ macro_rules! declare_spi_core {
 ////////////////////////////////////////////////////////////////////////////////
 
 include!(concat!(env!("OUT_DIR"), "/spi_config.rs"));
+
+/// Number of devices on this controller, for sizing per-device state.
+pub const DEVICE_COUNT: usize = CONFIG.devices.len();
@@ -6,6 +6,69 @@
 //!
 //! This is a thin wrapper around `stm32h7-spi-server-core`, which can be used
 //! both in this task and embedded into other tasks.
+//!
+//! # Aborting transfers
+//!
+//! A transfer that is stuck waiting on the controller can be cut short by
+//! posting this task's `spi-abort` notification, e.g. from a watchdog task. The
+//! caller then gets `SpiError::Aborted`. This isn't an IPC operation, because
+//! the caller is blocked in the transfer and the server won't receive another
+//! message until it finishes.
+//!
+//! # Holding CS between transfers
+//!
+//! A transfer with `keep_cs_asserted` set leaves CS asserted when it finishes,
+//! so the caller's next transfer to that device continues the transaction.
+//! Unlike a lock, this doesn't stop the server from handling other tasks in the
+//! meantime; instead, transfers to other devices on the same mux option are
+//! refused with `SpiError::Busy` until the holder finishes with a normal
+//! transfer. If the holder restarts instead, the hold is dropped the next time
+//! someone else is refused by it.
+//!
+//! # Lock timeouts
+//!
+//! A lock can be given a timeout, either by the holder when it locks or for
+//! every lock on this controller by `lock_timeout_ms` in the SPI config. If the
+//! holder then makes no calls to the server for that long, we assume it has
+//! hung: the lock is released (deasserting CS), and the holder's next transfer
+//! fails with `SpiError::LockExpired`.
+//!
+//! # Target mode
+//!
+//! With the `target` feature and a `target` section in the SPI config, the
+//! controller can also act as an SPI target (slave), for a host CPU or FPGA to
+//! talk to: `slave_prepare` arms it with data to send, and
+//! `slave_wait_transfer` collects what the host sent once it's done. We learn
+//! that the host is done from the rising edge of SS, which needs an EXTI
+//! interrupt on the SS pin delivered to this task's `spi-cs` notification (via
+//! the sys task's `gpio-irqs` config).
+//!
+//! # Pinning the mux
+//!
+//! With the `pin-mux` feature, `pin_mux` keeps the mux on one option until the
+//! caller unpins it, refusing transfers to devices behind other options with
+//! `SpiError::Busy`. Unlike a lock, devices behind the pinned option stay
+//! available to everyone. If the caller restarts, the pin is dropped the next
+//! time someone is refused by it. A lock taken on a device behind another
+//! option doesn't override the pin, so the holder's transfers will be refused
+//! until the pin is released.
+//!
+//! # Asynchronous exchanges
+//!
+//! With the `async-exchange` feature, `start_exchange` returns as soon as an
+//! exchange is under way, and the server posts the caller's chosen notification
+//! once it's over; the caller then fetches what was received with
+//! `collect_result`. Leases only last as long as the call, so the data goes
+//! through a buffer here, sized by `async_buffer_size` in the SPI config.
+//! Only one exchange can be in flight at once, and other transfers are refused
+//! with `SpiError::Busy` until its result is collected. Locking or releasing
+//! the controller waits for a running exchange to finish first.
+//!
+//! So that a client that never collects can't shut everyone else out, an
+//! uncollected result is thrown away when someone else wants the controller,
+//! if its owner has restarted or the result is more than
+//! `ASYNC_COLLECT_TIMEOUT_MS` old. In the latter case, the owner's
+//! `collect_result` fails with `SpiError::ResultExpired`.
 
 #![no_std]
 #![no_main]
@@ -14,8 +77,8 @@ use core::convert::Infallible;
 
 use drv_spi_api::*;
 use idol_runtime::{
-    LeaseBufReader, LeaseBufWriter, Leased, LenLimit, NotificationHandler,
-    RequestError, R, W,
+    LeaseBufReader, LeaseBufWriter, Leased, NotificationHandler, RequestError,
+    R, W,
 };
 use userlib::*;
 
@@ -25,18 +88,52 @@ use drv_stm32xx_sys_api as sys_api;
 task_slot!(SYS, sys);
 
 // This lets us amortize the cost of the borrow syscalls for retrieving data
-// from the caller. It doesn't appear to be useful to make this any larger than
-// the FIFO depth; for simplicity we set:
-const BUFSIZ: usize = 16;
+// from the caller. It's set per controller by `lease_buffer_size` in the SPI
+// config, so that controllers doing bulk transfers can use a larger window.
+const BUFSIZ: usize = drv_stm32h7_spi_server_core::LEASE_BUFFER_SIZE;
+
+// Timeout for locks whose holders don't ask for one, or 0 for none.
+const LOCK_TIMEOUT_MS: u32 = drv_stm32h7_spi_server_core::LOCK_TIMEOUT_MS;
+
+// How long the result of an asynchronous exchange can sit uncollected before
+// we'll throw it away to let someone else use the controller. The owner is
+// notified the moment the exchange finishes, so this only has to cover the
+// owner being scheduled.
+#[cfg(feature = "async-exchange")]
+const ASYNC_COLLECT_TIMEOUT_MS: u64 = 100;
 
 #[export_name = "main"]
 fn main() -> ! {
     let sys = sys_api::Sys::from(SYS.get_task_id());
     let core = drv_stm32h7_spi_server_core::declare_spi_core!(
         sys,
-        notifications::SPI_IRQ_MASK
+        notifications::SPI_IRQ_MASK,
+        notifications::SPI_ABORT_MASK
     );
-    let mut server = ServerImpl { core };
+    #[cfg(feature = "target")]
+    let target_sys = {
+        let sys = sys_api::Sys::from(SYS.get_task_id());
+        sys.gpio_irq_configure(
+            notifications::SPI_CS_MASK,
+            sys_api::Edge::Rising,
+        );
+        sys
+    };
+    let mut server = ServerImpl {
+        core,
+        #[cfg(feature = "target")]
+        target_sys,
+        lock_timeout_ms: 0,
+        lock_deadline: None,
+        expired_holders: TaskSet::new(),
+        cs_hold: None,
+        #[cfg(feature = "async-exchange")]
+        async_client: None,
+        #[cfg(feature = "async-exchange")]
+        expired_results: TaskSet::new(),
+        #[cfg(feature = "pin-mux")]
+        mux_pin: None,
+    };
     let mut incoming = [0u8; INCOMING_SIZE];
     loop {
         idol_runtime::dispatch(&mut incoming, &mut server);
@@ -45,6 +142,283 @@ fn main() -> ! {
 
 struct ServerImpl {
     core: SpiServerCore,
+    /// Used to manage the EXTI interrupt on SS in target mode.
+    #[cfg(feature = "target")]
+    target_sys: sys_api::Sys,
+    /// How long the current lock holder may go without calling us before its
+    /// lock is forcibly released, or 0 if it may hold the lock forever.
+    lock_timeout_ms: u32,
+    /// If the current lock has a timeout, the time at which it will be
+    /// forcibly released unless the holder calls us first.
+    lock_deadline: Option<u64>,
+    /// Tasks whose locks were forcibly released, and which haven't yet been
+    /// told.
+    expired_holders: TaskSet,
+    /// Transaction left open by a `keep_cs_asserted` transfer, if any.
+    cs_hold: Option<CsHold>,
+    /// Task that started the asynchronous exchange in progress, if any.
+    #[cfg(feature = "async-exchange")]
+    async_client: Option<AsyncClient>,
+    /// Tasks whose asynchronous exchange results were thrown away uncollected,
+    /// and which haven't yet been told.
+    #[cfg(feature = "async-exchange")]
+    expired_results: TaskSet,
+    /// Mux option pinned by `pin_mux`, if any.
+    #[cfg(feature = "pin-mux")]
+    mux_pin: Option<MuxPin>,
+}
+
+/// A set of tasks, each recorded at the generation it had when added, so that
+/// a task drops out of the set if it restarts.
+struct TaskSet([Option<Generation>; hubris_num_tasks::NUM_TASKS]);
+
+impl TaskSet {
+    fn new() -> Self {
+        Self([None; hubris_num_tasks::NUM_TASKS])
+    }
+
+    fn insert(&mut self, task: TaskId) {
+        self.0[task.index()] = Some(task.generation());
+    }
+
+    /// Removes `task` from the set, returning whether it was there.
+    fn take(&mut self, task: TaskId) -> bool {
+        self.0[task.index()].take() == Some(task.generation())
+    }
+}
+
+/// A mux option that a task has pinned the mux to.
+#[cfg(feature = "pin-mux")]
+#[derive(Copy, Clone)]
+struct MuxPin {
+    task: TaskId,
+    mux_index: usize,
+}
+
+/// A device whose CS was left asserted, and the task that asked for it.
+#[derive(Copy, Clone, Eq, PartialEq)]
+struct CsHold {
+    task: TaskId,
+    device_index: u8,
+}
+
+/// The caller of `start_exchange`, and what to do when the exchange is over.
+#[cfg(feature = "async-exchange")]
+#[derive(Copy, Clone)]
+struct AsyncClient {
+    task: TaskId,
+    notification_mask: u32,
+    device_index: u8,
+    keep_cs_asserted: bool,
+    /// When the exchange finished, if it has.
+    finished: Option<u64>,
+}
+
+impl ServerImpl {
+    /// Arms (or disarms) the lock timer.
+    fn set_lock_deadline(&mut self, deadline: Option<u64>) {
+        self.lock_deadline = deadline;
+        sys_set_timer(deadline, notifications::TIMER_MASK);
+    }
+
+    /// Pushes back the lock timer, if `sender` holds a lock with a timeout.
+    /// Transfers call this when they finish, so that time spent on the bus
+    /// doesn't count against the holder.
+    fn renew_lock(&mut self, sender: TaskId) {
+        if self.lock_timeout_ms != 0 && self.core.recv_source() == Some(sender)
+        {
+            let now = sys_get_timer().now;
+            self.set_lock_deadline(Some(now + u64::from(self.lock_timeout_ms)));
+        }
+    }
+
+    /// Checks whether `sender` had its lock expire since we last heard from
+    /// it, clearing the record if so.
+    fn take_expired(&mut self, sender: TaskId) -> bool {
+        self.expired_holders.take(sender)
+    }
+
+    /// Checks whether a transfer from `sender` to `device_index` would collide
+    /// with a held CS.
+    fn check_cs_hold(
+        &mut self,
+        sender: TaskId,
+        device_index: u8,
+    ) -> Result<(), SpiError> {
+        let Some(hold) = self.cs_hold else {
+            return Ok(());
+        };
+        if hold.task == sender && hold.device_index == device_index {
+            // Continuing the transaction.
+            return Ok(());
+        }
+        if sys_refresh_task_id(hold.task) != hold.task {
+            // The holder restarted without finishing its transaction.
+            self.drop_cs_hold();
+            return Ok(());
+        }
+        if self.core.device_mux_index(hold.device_index)
+            == self.core.device_mux_index(device_index)
+        {
+            self.core.record_contention(device_index);
+            return Err(SpiError::Busy);
+        }
+        Ok(())
+    }
+
+    /// Checks whether a transfer to `device_index` would move the mux away
+    /// from an option that a task has pinned it to.
+    fn check_mux_pin(&mut self, device_index: u8) -> Result<(), SpiError> {
+        // Bad device indices are left for the core to reject.
+        let Some(mux_index) = self.core.device_mux_index(device_index) else {
+            return Ok(());
+        };
+        if self.pinned_elsewhere(mux_index) {
+            self.core.record_contention(device_index);
+            return Err(SpiError::Busy);
+        }
+        Ok(())
+    }
+
+    /// Checks whether a task has pinned the mux to an option other than
+    /// `mux_index`.
+    fn pinned_elsewhere(&mut self, mux_index: usize) -> bool {
+        #[cfg(feature = "pin-mux")]
+        if let Some(pin) = self.mux_pin {
+            if sys_refresh_task_id(pin.task) == pin.task {
+                return pin.mux_index != mux_index;
+            }
+            // The pinner restarted without unpinning.
+            self.mux_pin = None;
+        }
+        #[cfg(not(feature = "pin-mux"))]
+        let _ = mux_index;
+        false
+    }
+
+    /// Records the outcome of a transfer from `sender` to `device_index`.
+    fn update_cs_hold(
+        &mut self,
+        sender: TaskId,
+        device_index: u8,
+        keep_cs_asserted: bool,
+        succeeded: bool,
+    ) {
+        let this = CsHold {
+            task: sender,
+            device_index,
+        };
+        // While locked, CS belongs to the lock holder, so there's nothing to
+        // record. A failed transfer always deasserts CS.
+        if keep_cs_asserted && succeeded && self.core.recv_source().is_none() {
+            self.cs_hold = Some(this);
+        } else if self.cs_hold == Some(this) {
+            self.cs_hold = None;
+        }
+    }
+
+    /// Tells the caller of `start_exchange` that its exchange is over. If it
+    /// has restarted in the meantime, nobody will collect the result, so it's
+    /// thrown away instead.
+    #[cfg(feature = "async-exchange")]
+    fn finish_async(&mut self, succeeded: bool) {
+        let Some(client) = self.async_client else {
+            return;
+        };
+        self.update_cs_hold(
+            client.task,
+            client.device_index,
+            client.keep_cs_asserted,
+            succeeded,
+        );
+        if sys_refresh_task_id(client.task) == client.task {
+            sys_post(client.task, client.notification_mask);
+            self.async_client = Some(AsyncClient {
+                finished: Some(sys_get_timer().now),
+                ..client
+            });
+        } else {
+            self.core.async_discard();
+            self.async_client = None;
+        }
+    }
+
+    /// Lets any running asynchronous exchange finish, before we do something
+    /// that would change CS under it.
+    fn drain_async(&mut self) {
+        #[cfg(feature = "async-exchange")]
+        if let Some(succeeded) = self.core.async_wait() {
+            self.finish_async(succeeded);
+        }
+    }
+
+    /// Throws away the result of a finished asynchronous exchange that's
+    /// standing in the way of a synchronous operation, if its owner restarted
+    /// without collecting it or has sat on it too long.
+    fn check_async_owner(&mut self) {
+        #[cfg(feature = "async-exchange")]
+        {
+            let Some(client) = self.async_client else {
+                return;
+            };
+            let Some(finished) = client.finished else {
+                // Still running.
+                return;
+            };
+            if sys_refresh_task_id(client.task) == client.task {
+                if sys_get_timer().now < finished + ASYNC_COLLECT_TIMEOUT_MS {
+                    return;
+                }
+                // Tell the owner on its next `collect_result`.
+                self.expired_results.insert(client.task);
+            }
+            self.core.async_discard();
+            self.async_client = None;
+        }
+    }
+
+    /// Releases the lock if its holder has sat on it too long.
+    fn check_lock_deadline(&mut self) {
+        let Some(deadline) = self.lock_deadline else {
+            return;
+        };
+        if sys_get_timer().now < deadline {
+            return;
+        }
+        // Release it on the holder's behalf (which deasserts CS) and remember
+        // to tell them on their next call.
+        if let Some(holder) = self.core.recv_source() {
+            self.drain_async();
+            self.core.release(holder).unwrap_lite();
+            self.expired_holders.insert(holder);
+        }
+        self.set_lock_deadline(None);
+    }
+
+    /// Implements `assert_cs` and `deassert_cs` for the lock holder.
+    #[cfg(feature = "cs-control")]
+    fn set_cs(
+        &mut self,
+        sender: TaskId,
+        asserted: bool,
+    ) -> Result<(), RequestError<SpiError>> {
+        if self.take_expired(sender) {
+            return Err(SpiError::LockExpired.into());
+        }
+        self.drain_async();
+        self.core.set_cs(sender, asserted).map_err(|_| {
+            idol_runtime::ClientError::BadMessageContents.fail()
+        })?;
+        self.renew_lock(sender);
+        Ok(())
+    }
+
+    /// Abandons any held CS, deasserting it.
+    fn drop_cs_hold(&mut self) {
+        if let Some(hold) = self.cs_hold.take() {
+            self.core.deassert_cs(hold.device_index);
+        }
+    }
 }
 
 impl InOrderSpiImpl for ServerImpl {
@@ -53,51 +427,231 @@ impl InOrderSpiImpl for ServerImpl {
     }
 
     fn closed_recv_fail(&mut self) {
-        self.core.closed_recv_fail()
+        self.drain_async();
+        self.core.closed_recv_fail();
+        self.set_lock_deadline(None);
     }
 
     fn read(
         &mut self,
-        _: &RecvMessage,
+        rm: &RecvMessage,
         device_index: u8,
-        dest: LenLimit<Leased<W, [u8]>, 65535>,
+        keep_cs_asserted: bool,
+        dest: Leased<W, [u8]>,
     ) -> Result<(), RequestError<SpiError>> {
-        self.core
-            .read::<LeaseBufWriter<_, BUFSIZ>>(
-                device_index,
-                dest.into_inner().into(),
-            )
-            .map_err(RequestError::from)
+        if self.take_expired(rm.sender) {
+            return Err(SpiError::LockExpired.into());
+        }
+        self.check_async_owner();
+        self.check_cs_hold(rm.sender, device_index)?;
+        self.check_mux_pin(device_index)?;
+        let result = self.core.read::<LeaseBufWriter<_, BUFSIZ>>(
+            device_index,
+            dest.into(),
+            keep_cs_asserted,
+        );
+        self.renew_lock(rm.sender);
+        self.update_cs_hold(
+            rm.sender,
+            device_index,
+            keep_cs_asserted,
+            result.is_ok(),
+        );
+        result.map_err(RequestError::from)
     }
 
     fn write(
         &mut self,
-        _: &RecvMessage,
+        rm: &RecvMessage,
         device_index: u8,
-        src: LenLimit<Leased<R, [u8]>, 65535>,
+        keep_cs_asserted: bool,
+        src: Leased<R, [u8]>,
     ) -> Result<(), RequestError<SpiError>> {
-        self.core
-            .write::<LeaseBufReader<_, BUFSIZ>>(
-                device_index,
-                src.into_inner().into(),
-            )
-            .map_err(RequestError::from)
+        if self.take_expired(rm.sender) {
+            return Err(SpiError::LockExpired.into());
+        }
+        self.check_async_owner();
+        self.check_cs_hold(rm.sender, device_index)?;
+        self.check_mux_pin(device_index)?;
+        let result = self.core.write::<LeaseBufReader<_, BUFSIZ>>(
+            device_index,
+            src.into(),
+            keep_cs_asserted,
+        );
+        self.renew_lock(rm.sender);
+        self.update_cs_hold(
+            rm.sender,
+            device_index,
+            keep_cs_asserted,
+            result.is_ok(),
+        );
+        result.map_err(RequestError::from)
     }
 
     fn exchange(
         &mut self,
-        _: &RecvMessage,
+        rm: &RecvMessage,
         device_index: u8,
-        src: LenLimit<Leased<R, [u8]>, 65535>,
-        dest: LenLimit<Leased<W, [u8]>, 65535>,
+        keep_cs_asserted: bool,
+        src: Leased<R, [u8]>,
+        dest: Leased<W, [u8]>,
     ) -> Result<(), RequestError<SpiError>> {
-        self.core
+        if self.take_expired(rm.sender) {
+            return Err(SpiError::LockExpired.into());
+        }
+        self.check_async_owner();
+        self.check_cs_hold(rm.sender, device_index)?;
+        self.check_mux_pin(device_index)?;
+        let result = self
+            .core
             .exchange::<LeaseBufReader<_, BUFSIZ>, LeaseBufWriter<_, BUFSIZ>>(
                 device_index,
-                src.into_inner().into(),
-                dest.into_inner().into(),
-            )
-            .map_err(RequestError::from)
+                src.into(),
+                dest.into(),
+                keep_cs_asserted,
+            );
+        self.renew_lock(rm.sender);
+        self.update_cs_hold(
+            rm.sender,
+            device_index,
+            keep_cs_asserted,
+            result.is_ok(),
+        );
+        result.map_err(RequestError::from)
+    }
+
+    fn command_exchange(
+        &mut self,
+        rm: &RecvMessage,
+        device_index: u8,
+        keep_cs_asserted: bool,
+        command: Leased<R, [u8]>,
+        src: Leased<R, [u8]>,
+        dest: Leased<W, [u8]>,
+    ) -> Result<(), RequestError<SpiError>> {
+        #[cfg(feature = "command-exchange")]
+        {
+            if self.take_expired(rm.sender) {
+                return Err(SpiError::LockExpired.into());
+            }
+            self.check_async_owner();
+            self.check_cs_hold(rm.sender, device_index)?;
+            self.check_mux_pin(device_index)?;
+            let result = self.core.command_exchange::<
+                LeaseBufReader<_, BUFSIZ>,
+                LeaseBufReader<_, BUFSIZ>,
+                LeaseBufWriter<_, BUFSIZ>,
+            >(
+                device_index,
+                command.into(),
+                src.into(),
+                dest.into(),
+                keep_cs_asserted,
+            );
+            self.renew_lock(rm.sender);
+            self.update_cs_hold(
+                rm.sender,
+                device_index,
+                keep_cs_asserted,
+                result.is_ok(),
+            );
+            result.map_err(RequestError::from)
+        }
+        #[cfg(not(feature = "command-exchange"))]
+        {
+            let _ = (rm, device_index, keep_cs_asserted, command, src, dest);
+            Err(idol_runtime::ClientError::UnknownOperation.fail())
+        }
+    }
+
+    fn start_exchange(
+        &mut self,
+        rm: &RecvMessage,
+        device_index: u8,
+        keep_cs_asserted: bool,
+        len: u32,
+        notification_mask: u32,
+        src: Leased<R, [u8]>,
+    ) -> Result<(), RequestError<SpiError>> {
+        #[cfg(feature = "async-exchange")]
+        {
+            if self.take_expired(rm.sender) {
+                return Err(SpiError::LockExpired.into());
+            }
+            // Starting afresh gives up on any earlier result we threw away.
+            self.expired_results.take(rm.sender);
+            self.check_async_owner();
+            if self.async_client.is_some() {
+                return Err(SpiError::Busy.into());
+            }
+            self.check_cs_hold(rm.sender, device_index)?;
+            self.check_mux_pin(device_index)?;
+            self.core.async_start::<LeaseBufReader<_, BUFSIZ>>(
+                device_index,
+                src.into(),
+                len,
+                keep_cs_asserted,
+            )?;
+            self.async_client = Some(AsyncClient {
+                task: rm.sender,
+                notification_mask,
+                device_index,
+                keep_cs_asserted,
+                finished: None,
+            });
+            self.renew_lock(rm.sender);
+            Ok(())
+        }
+        #[cfg(not(feature = "async-exchange"))]
+        {
+            let _ = (
+                rm,
+                device_index,
+                keep_cs_asserted,
+                len,
+                notification_mask,
+                src,
+            );
+            Err(idol_runtime::ClientError::UnknownOperation.fail())
+        }
+    }
+
+    fn collect_result(
+        &mut self,
+        rm: &RecvMessage,
+        dest: Leased<W, [u8]>,
+    ) -> Result<u32, RequestError<SpiError>> {
+        #[cfg(feature = "async-exchange")]
+        {
+            if self.expired_results.take(rm.sender) {
+                return Err(SpiError::ResultExpired.into());
+            }
+            match self.async_client {
+                Some(client) if client.task == rm.sender => (),
+                // Nothing was started, or someone else started it.
+                _ => {
+                    return Err(
+                        idol_runtime::ClientError::BadMessageContents.fail()
+                    )
+                }
+            }
+            let result = self
+                .core
+                .async_collect::<LeaseBufWriter<_, BUFSIZ>>(dest.into());
+            if !matches!(
+                result,
+                Err(drv_stm32h7_spi_server_core::TransferError::Busy)
+            ) {
+                self.async_client = None;
+                self.renew_lock(rm.sender);
+            }
+            result.map_err(RequestError::from)
+        }
+        #[cfg(not(feature = "async-exchange"))]
+        {
+            let _ = (rm, dest);
+            Err(idol_runtime::ClientError::UnknownOperation.fail())
+        }
     }
 
     fn lock(
@@ -105,30 +659,345 @@ impl InOrderSpiImpl for ServerImpl {
         rm: &RecvMessage,
         devidx: u8,
         cs_state: CsState,
+        timeout_ms: u32,
     ) -> Result<(), RequestError<Infallible>> {
-        self.core
-            .lock(rm.sender, devidx, cs_state)
-            .map_err(|_| idol_runtime::ClientError::BadMessageContents.fail())
+        // Taking a fresh lock acknowledges any earlier expiry.
+        self.take_expired(rm.sender);
+        self.drain_async();
+        // The lock takes over CS on this mux option, so any transaction left
+        // open there (including by the caller) is over.
+        if let Some(hold) = self.cs_hold {
+            if self.core.device_mux_index(hold.device_index)
+                == self.core.device_mux_index(devidx)
+            {
+                if hold.task == rm.sender && hold.device_index == devidx {
+                    // The lock's `cs_state` decides what happens to CS.
+                    self.cs_hold = None;
+                } else {
+                    self.drop_cs_hold();
+                }
+            }
+        }
+        self.core.lock(rm.sender, devidx, cs_state).map_err(|_| {
+            idol_runtime::ClientError::BadMessageContents.fail()
+        })?;
+
+        // Locking again while locked replaces the timeout. Holders that don't
+        // ask for one get the controller's default, if it has one.
+        self.lock_timeout_ms = if timeout_ms != 0 {
+            timeout_ms
+        } else {
+            LOCK_TIMEOUT_MS
+        };
+        if self.lock_timeout_ms != 0 {
+            self.renew_lock(rm.sender);
+        } else {
+            self.set_lock_deadline(None);
+        }
+        Ok(())
+    }
+
+    fn assert_cs(
+        &mut self,
+        rm: &RecvMessage,
+    ) -> Result<(), RequestError<SpiError>> {
+        #[cfg(feature = "cs-control")]
+        {
+            self.set_cs(rm.sender, true)
+        }
+        #[cfg(not(feature = "cs-control"))]
+        {
+            let _ = rm;
+            Err(idol_runtime::ClientError::UnknownOperation.fail())
+        }
+    }
+
+    fn deassert_cs(
+        &mut self,
+        rm: &RecvMessage,
+    ) -> Result<(), RequestError<SpiError>> {
+        #[cfg(feature = "cs-control")]
+        {
+            self.set_cs(rm.sender, false)
+        }
+        #[cfg(not(feature = "cs-control"))]
+        {
+            let _ = rm;
+            Err(idol_runtime::ClientError::UnknownOperation.fail())
+        }
     }
 
     fn release(
         &mut self,
         rm: &RecvMessage,
     ) -> Result<(), RequestError<Infallible>> {
+        // If the lock already expired, the client is just catching up (e.g.
+        // dropping a `ControllerLock`), which isn't an error.
+        if self.take_expired(rm.sender) {
+            return Ok(());
+        }
+        self.drain_async();
+        self.core.release(rm.sender).map_err(|_| {
+            idol_runtime::ClientError::BadMessageContents.fail()
+        })?;
+        self.set_lock_deadline(None);
+        Ok(())
+    }
+
+    fn device_count(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u8, RequestError<Infallible>> {
+        Ok(self.core.device_count())
+    }
+
+    fn mux_count(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u8, RequestError<Infallible>> {
+        Ok(self.core.mux_count())
+    }
+
+    fn current_mux(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u8, RequestError<Infallible>> {
+        Ok(self.core.current_mux_index())
+    }
+
+    fn pin_mux(
+        &mut self,
+        rm: &RecvMessage,
+        mux_index: u8,
+    ) -> Result<(), RequestError<SpiError>> {
+        #[cfg(feature = "pin-mux")]
+        {
+            self.check_async_owner();
+            if let Some(pin) = self.mux_pin {
+                if pin.task != rm.sender
+                    && sys_refresh_task_id(pin.task) == pin.task
+                {
+                    return Err(SpiError::Busy.into());
+                }
+            }
+            self.core.select_mux(mux_index)?;
+            self.mux_pin = Some(MuxPin {
+                task: rm.sender,
+                mux_index: usize::from(mux_index),
+            });
+            Ok(())
+        }
+        #[cfg(not(feature = "pin-mux"))]
+        {
+            let _ = (rm, mux_index);
+            Err(idol_runtime::ClientError::UnknownOperation.fail())
+        }
+    }
+
+    fn unpin_mux(
+        &mut self,
+        rm: &RecvMessage,
+    ) -> Result<(), RequestError<Infallible>> {
+        #[cfg(feature = "pin-mux")]
+        {
+            match self.mux_pin {
+                Some(pin) if pin.task == rm.sender => {
+                    self.mux_pin = None;
+                    Ok(())
+                }
+                _ => Err(idol_runtime::ClientError::BadMessageContents.fail()),
+            }
+        }
+        #[cfg(not(feature = "pin-mux"))]
+        {
+            let _ = rm;
+            Err(idol_runtime::ClientError::UnknownOperation.fail())
+        }
+    }
+
+    fn set_data_line_swap(
+        &mut self,
+        _: &RecvMessage,
+        mux_index: u8,
+        swap: bool,
+    ) -> Result<(), RequestError<SpiError>> {
         self.core
-            .release(rm.sender)
-            .map_err(|_| idol_runtime::ClientError::BadMessageContents.fail())
+            .set_data_line_swap(mux_index, swap)
+            .map_err(RequestError::from)
+    }
+
+    fn set_clock_divider(
+        &mut self,
+        rm: &RecvMessage,
+        device_index: u8,
+        divider: ClockDivider,
+    ) -> Result<(), RequestError<SpiError>> {
+        // Don't change the clock out from under a transaction that another
+        // task is holding open.
+        self.check_cs_hold(rm.sender, device_index)?;
+        self.core
+            .set_clock_divider(device_index, divider)
+            .map_err(RequestError::from)
+    }
+
+    fn get_stats(
+        &mut self,
+        _: &RecvMessage,
+        device_index: u8,
+    ) -> Result<SpiDeviceStats, RequestError<SpiError>> {
+        #[cfg(feature = "stats")]
+        {
+            self.core.stats(device_index).ok_or_else(|| {
+                idol_runtime::ClientError::BadMessageContents.fail()
+            })
+        }
+        #[cfg(not(feature = "stats"))]
+        {
+            let _ = device_index;
+            Err(idol_runtime::ClientError::UnknownOperation.fail())
+        }
+    }
+
+    fn slave_prepare(
+        &mut self,
+        _: &RecvMessage,
+        src: Leased<R, [u8]>,
+    ) -> Result<(), RequestError<SpiError>> {
+        #[cfg(feature = "target")]
+        {
+            self.check_async_owner();
+            // Being a target takes over the whole controller, which would
+            // break a transaction that's being held open.
+            if self.cs_hold.is_some() {
+                return Err(SpiError::Busy.into());
+            }
+            self.core
+                .target_prepare::<LeaseBufReader<_, BUFSIZ>>(src.into())?;
+            // Only listen for SS once we're armed, so that the end of some
+            // earlier transfer can't be mistaken for the end of this one.
+            self.target_sys
+                .gpio_irq_control(
+                    notifications::SPI_CS_MASK,
+                    sys_api::IrqControl::Enable,
+                )
+                .unwrap_lite();
+            Ok(())
+        }
+        #[cfg(not(feature = "target"))]
+        {
+            let _ = src;
+            Err(idol_runtime::ClientError::UnknownOperation.fail())
+        }
+    }
+
+    fn slave_wait_transfer(
+        &mut self,
+        _: &RecvMessage,
+        dest: Leased<W, [u8]>,
+    ) -> Result<u32, RequestError<SpiError>> {
+        #[cfg(feature = "target")]
+        {
+            self.core
+                .target_wait_transfer::<LeaseBufWriter<_, BUFSIZ>>(
+                    dest.into(),
+                    notifications::SPI_CS_MASK,
+                )
+                .map_err(RequestError::from)
+        }
+        #[cfg(not(feature = "target"))]
+        {
+            let _ = dest;
+            Err(idol_runtime::ClientError::UnknownOperation.fail())
+        }
+    }
+
+    fn loopback_test(
+        &mut self,
+        _: &RecvMessage,
+        mux_index: u8,
+    ) -> Result<bool, RequestError<SpiError>> {
+        #[cfg(feature = "diagnostics")]
+        {
+            self.check_async_owner();
+            // Don't clock test traffic past a device whose CS is being held,
+            // or move the mux away from a pinned option.
+            if self.pinned_elsewhere(usize::from(mux_index)) {
+                return Err(SpiError::Busy.into());
+            }
+            if let Some(hold) = self.cs_hold {
+                let held_mux = self.core.device_mux_index(hold.device_index);
+                if held_mux == Some(usize::from(mux_index)) {
+                    return Err(SpiError::Busy.into());
+                }
+            }
+            self.core
+                .loopback_test(mux_index)
+                .map_err(RequestError::from)
+        }
+        #[cfg(not(feature = "diagnostics"))]
+        {
+            let _ = mux_index;
+            Err(idol_runtime::ClientError::UnknownOperation.fail())
+        }
+    }
+
+    fn selftest(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<bool, RequestError<SpiError>> {
+        #[cfg(feature = "diagnostics")]
+        {
+            self.check_async_owner();
+            // Disconnecting the pins would glitch SCK under a device whose CS
+            // is being held.
+            if self.cs_hold.is_some() {
+                return Err(SpiError::Busy.into());
+            }
+            self.core.selftest().map_err(RequestError::from)
+        }
+        #[cfg(not(feature = "diagnostics"))]
+        {
+            Err(idol_runtime::ClientError::UnknownOperation.fail())
+        }
     }
 }
 
 impl NotificationHandler for ServerImpl {
     fn current_notification_mask(&self) -> u32 {
-        // We don't use notifications, don't listen for any.
-        0
+        // The SPI IRQ is handled synchronously within transfers, except for
+        // asynchronous exchanges. We also listen for aborts here, so that one
+        // arriving between transfers is consumed (and ignored) rather than
+        // aborting the next transfer.
+        let mask = notifications::TIMER_MASK | notifications::SPI_ABORT_MASK;
+        #[cfg(feature = "async-exchange")]
+        let mask = if self.core.async_running() {
+            mask | notifications::SPI_IRQ_MASK
+        } else {
+            mask
+        };
+        mask
     }
 
-    fn handle_notification(&mut self, _bits: u32) {
-        unreachable!()
+    fn handle_notification(&mut self, bits: u32) {
+        #[cfg(feature = "async-exchange")]
+        {
+            let outcome = if bits & notifications::SPI_ABORT_MASK != 0
+                && self.core.async_abort()
+            {
+                Some(false)
+            } else if bits & notifications::SPI_IRQ_MASK != 0 {
+                self.core.async_service()
+            } else {
+                None
+            };
+            if let Some(succeeded) = outcome {
+                self.finish_async(succeeded);
+            }
+        }
+
+        if bits & notifications::TIMER_MASK != 0 {
+            self.check_lock_deadline();
+        }
     }
 }
 
@@ -0,0 +1,161 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Just enough of a driver for the DMA1/DMA2 streams to move bytes between
+//! memory and an SPI block's data registers.
+//!
+//! Each stream is routed to its peripheral through DMAMUX1, whose channels
+//! 0-7 feed DMA1 streams 0-7 and channels 8-15 feed DMA2 streams 0-7. We use
+//! the streams in their simplest configuration: one byte per beat, direct
+//! mode (no FIFO), no double buffering, with the DMA as flow controller. We
+//! poll for completion rather than taking the stream interrupts, since the
+//! SPI's own end-of-transfer interrupt tells us when to look.
+//!
+//! The streams can't reach DTCM, so the memory side of a transfer has to live
+//! in one of the AXI/AHB SRAMs.
+
+use crate::device;
+
+/// Direction of a stream's transfer.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum Direction {
+    PeripheralToMemory,
+    MemoryToPeripheral,
+}
+
+// Stream CR bits.
+const CR_EN: u32 = 1 << 0;
+const CR_DIR_M2P: u32 = 0b01 << 6;
+const CR_MINC: u32 = 1 << 10;
+const CR_PL_HIGH: u32 = 0b10 << 16;
+
+// Per-stream flags in LISR/HISR, relative to the stream's offset.
+const FEIF: u32 = 1 << 0;
+const DMEIF: u32 = 1 << 2;
+const TEIF: u32 = 1 << 3;
+const HTIF: u32 = 1 << 4;
+const TCIF: u32 = 1 << 5;
+const ALL_FLAGS: u32 = FEIF | DMEIF | TEIF | HTIF | TCIF;
+
+#[derive(Clone)]
+pub struct DmaStream {
+    dma: &'static device::dma1::RegisterBlock,
+    dmamux: &'static device::dmamux1::RegisterBlock,
+    /// Stream number within `dma`, 0-7.
+    stream: usize,
+    /// DMAMUX1 channel feeding this stream.
+    mux_channel: usize,
+}
+
+impl DmaStream {
+    /// Wraps stream `stream` (0-7) of DMA `controller` (1 or 2).
+    pub fn new(controller: u8, stream: usize) -> Self {
+        assert!(stream < 8);
+        // Safety: the PAC gives us these as raw pointers to MMIO; we only use
+        // the stream we've been given, and the kernel ensures we can reach it.
+        let dma = match controller {
+            1 => unsafe { &*device::DMA1::ptr() },
+            2 => unsafe { &*device::DMA2::ptr() },
+            _ => panic!(),
+        };
+        let dmamux = unsafe { &*device::DMAMUX1::ptr() };
+        let mux_channel = (usize::from(controller) - 1) * 8 + stream;
+        Self {
+            dma,
+            dmamux,
+            stream,
+            mux_channel,
+        }
+    }
+
+    /// Sets the stream up to move `len` bytes between `memory` and the
+    /// peripheral register at `peripheral`, when asked to by DMAMUX request
+    /// line `request`. The stream must be disabled.
+    pub fn configure(
+        &self,
+        request: u8,
+        dir: Direction,
+        peripheral: u32,
+        memory: u32,
+        len: u16,
+    ) {
+        let st = &self.dma.st[self.stream];
+
+        self.dmamux.ccr[self.mux_channel]
+            .write(|w| w.dmareq_id().bits(request));
+
+        // Safety: these are plain addresses and counts, with no reserved bits
+        // that we could set by mistake.
+        st.par.write(|w| unsafe { w.bits(peripheral) });
+        st.m0ar.write(|w| unsafe { w.bits(memory) });
+        st.ndtr.write(|w| unsafe { w.bits(u32::from(len)) });
+        // Direct mode, which is what we get with DMDIS clear.
+        st.fcr.write(|w| unsafe { w.bits(0) });
+
+        let dir = match dir {
+            Direction::PeripheralToMemory => 0,
+            Direction::MemoryToPeripheral => CR_DIR_M2P,
+        };
+        // Safety: PSIZE and MSIZE are left at 0 (bytes), and everything else
+        // we set is a defined bit.
+        st.cr
+            .write(|w| unsafe { w.bits(dir | CR_MINC | CR_PL_HIGH) });
+
+        self.clear_flags();
+    }
+
+    pub fn enable(&self) {
+        // Safety: we're only setting EN on top of what `configure` wrote.
+        self.dma.st[self.stream]
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | CR_EN) });
+    }
+
+    /// Disables the stream, waiting for it to finish any beat in progress as
+    /// the reference manual requires before it's reconfigured.
+    pub fn disable(&self) {
+        let cr = &self.dma.st[self.stream].cr;
+        // Safety: as in `enable`, but clearing EN.
+        cr.modify(|r, w| unsafe { w.bits(r.bits() & !CR_EN) });
+        while cr.read().bits() & CR_EN != 0 {
+            // Spin; this takes at most one bus transfer.
+        }
+        self.clear_flags();
+    }
+
+    /// Checks whether the stream has moved all of its bytes.
+    pub fn is_complete(&self) -> bool {
+        self.flags() & TCIF != 0
+    }
+
+    /// Checks whether the stream has stopped because of a bus error.
+    pub fn has_error(&self) -> bool {
+        self.flags() & (TEIF | DMEIF) != 0
+    }
+
+    /// Offset of this stream's flags within LISR/HISR (and LIFCR/HIFCR).
+    fn flag_shift(&self) -> u32 {
+        [0, 6, 16, 22][self.stream % 4]
+    }
+
+    fn flags(&self) -> u32 {
+        let isr = if self.stream < 4 {
+            self.dma.lisr.read().bits()
+        } else {
+            self.dma.hisr.read().bits()
+        };
+        (isr >> self.flag_shift()) & ALL_FLAGS
+    }
+
+    fn clear_flags(&self) {
+        let bits = ALL_FLAGS << self.flag_shift();
+        // Safety: writing ones to the clear register only clears flags, and we
+        // only touch this stream's.
+        if self.stream < 4 {
+            self.dma.lifcr.write(|w| unsafe { w.bits(bits) });
+        } else {
+            self.dma.hifcr.write(|w| unsafe { w.bits(bits) });
+        }
+    }
+}
@@ -2,7 +2,8 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-//! A driver for the STM32H7 SPI, in host mode.
+//! A driver for the STM32H7 SPI, in host mode, with basic support for target
+//! mode (see `set_target_mode`).
 //!
 //! This is the core logic, separated from the IPC server. The peripheral also
 //! supports I2S, which we haven't bothered implementing because we don't have a
@@ -23,11 +24,20 @@
 //!    role.
 //!
 //! In host role, the SPI needs to have at least `ker_ck` running to do useful
-//! work.
+//! work. In target role, the serial interface domain is clocked by the host's
+//! SCK instead.
 //!
 //! # Automagic CRC generation
 //!
-//! We do not currently support the hardware's automatic CRC features.
+//! We support the hardware's 8-bit CRC, which is appended to (and checked
+//! against) the end of a transfer by the peripheral, outside of the `tsize`
+//! data frames. See `set_crc`.
+//!
+//! # DMA
+//!
+//! The `dma` module drives the DMA1/DMA2 streams, which can be pointed at the
+//! data registers (see `txdr_address` and `rxdr_address`) and triggered by
+//! the SPI with `set_dma_requests`.
 //!
 //! # Why is everything `spi1`
 //!
@@ -46,6 +56,8 @@ use stm32h7::stm32h753 as device;
 #[cfg(not(any(feature = "h743", feature = "h753")))]
 compile_error!("must define `h743` or `h753` feature");
 
+pub mod dma;
+
 #[derive(Clone)]
 pub struct Spi {
     /// Pointer to our register block.
@@ -117,6 +129,24 @@ impl Spi {
         self.reg.cr1.modify(|_, w| w.spe().set_bit());
     }
 
+    /// Sets the number of frames to reload into TSIZE once it runs out
+    /// (TSER), extending the transfer without ending it. Unlike TSIZE, this
+    /// can be written while the transfer is running; the peripheral clears it
+    /// and sets TSERF when it's been consumed.
+    pub fn set_reload(&self, tser: u16) {
+        self.reg.cr2.modify(|_, w| w.tser().bits(tser));
+    }
+
+    /// Checks whether the reload count set with `set_reload` has been moved
+    /// into TSIZE, so that another can be set.
+    pub fn check_reload(&self) -> bool {
+        self.reg.sr.read().tserf().bit()
+    }
+
+    pub fn clear_reload(&self) {
+        self.reg.ifcr.write(|w| w.tserfc().set_bit());
+    }
+
     pub fn start(&self) {
         self.reg.cr1.modify(|_, w| w.cstart().set_bit());
         // Clear EOT flag
@@ -133,6 +163,12 @@ impl Spi {
         sr.rxwne().bit() || sr.rxplvl().bits() != 0
     }
 
+    /// Checks for at least one complete frame in the RX FIFO. Unlike
+    /// `can_rx_byte`, this works for frames wider than 8 bits.
+    pub fn can_rx_frame(&self) -> bool {
+        self.reg.sr.read().rxp().bit()
+    }
+
     pub fn can_tx_frame(&self) -> bool {
         let sr = self.reg.sr.read();
         sr.txp().bit()
@@ -151,6 +187,84 @@ impl Spi {
         self.reg.cfg2.modify(|_, w| w.ioswp().bit(flag));
     }
 
+    /// Changes the communication mode chosen in `initialize`. Like the rest of
+    /// CFG2, this can only be changed while the peripheral is disabled.
+    pub fn set_comm_mode(&self, comm: device::spi1::cfg2::COMM_A) {
+        self.reg.cfg2.modify(|_, w| w.comm().variant(comm));
+    }
+
+    /// Changes the clock polarity and phase (the SPI mode) chosen in
+    /// `initialize`. As with `set_comm_mode`, the peripheral must be disabled.
+    pub fn set_clock_mode(
+        &self,
+        cpol: device::spi1::cfg2::CPOL_A,
+        cpha: device::spi1::cfg2::CPHA_A,
+    ) {
+        self.reg
+            .cfg2
+            .modify(|_, w| w.cpol().variant(cpol).cpha().variant(cpha));
+    }
+
+    /// Returns whether SCK idles high (CPOL = 1) in the current clock mode.
+    pub fn sck_idles_high(&self) -> bool {
+        self.reg.cfg2.read().cpol().bit_is_set()
+    }
+
+    /// Selects Motorola (ordinary SPI) or TI synchronous serial frame format;
+    /// `initialize` leaves it at Motorola. In TI format, the peripheral pulses
+    /// its hardware SS pin before each frame, and ignores the clock mode and
+    /// bit order. As with `set_comm_mode`, the peripheral must be disabled.
+    pub fn set_protocol(&self, sp: device::spi1::cfg2::SP_A) {
+        self.reg.cfg2.modify(|_, w| w.sp().variant(sp));
+    }
+
+    /// Changes the bit order chosen in `initialize`. As with `set_comm_mode`,
+    /// the peripheral must be disabled.
+    pub fn set_bit_order(&self, lsbfrst: device::spi1::cfg2::LSBFRST_A) {
+        self.reg.cfg2.modify(|_, w| w.lsbfrst().variant(lsbfrst));
+    }
+
+    /// Enables the hardware CRC with the given 8-bit polynomial, or disables it
+    /// if `poly` is `None`. The peripheral must be disabled.
+    pub fn set_crc(&self, poly: Option<u8>) {
+        if let Some(poly) = poly {
+            // Safety: CRCPOLY is a plain 32-bit value with no reserved bits.
+            self.reg
+                .crcpoly
+                .write(|w| unsafe { w.bits(u32::from(poly)) });
+            // CRCSIZE is the CRC length minus one, like DSIZE.
+            self.reg
+                .cfg1
+                .modify(|_, w| w.crcen().set_bit().crcsize().bits(8 - 1));
+        } else {
+            self.reg.cfg1.modify(|_, w| w.crcen().clear_bit());
+        }
+    }
+
+    /// Reads the CRC computed over the data sent so far. This is only valid
+    /// while the peripheral is enabled.
+    pub fn read_tx_crc(&self) -> u32 {
+        self.reg.txcrc.read().bits()
+    }
+
+    /// Checks whether the CRC received at the end of the last transfer failed
+    /// to match. This flag is cleared by `end`.
+    pub fn check_crc_error(&self) -> bool {
+        self.reg.sr.read().crce().bit()
+    }
+
+    /// Sets the number of idle SCK cycles (0-15) inserted between frames
+    /// (MIDI). Like `set_comm_mode`, the peripheral must be disabled.
+    pub fn set_inter_frame_gap(&self, cycles: u8) {
+        self.reg.cfg2.modify(|_, w| w.midi().bits(cycles));
+    }
+
+    /// In half-duplex mode, selects whether the shared data line is driven by
+    /// us (`transmit`) or by the device. Ignored in other modes.
+    pub fn set_half_duplex_direction(&self, transmit: bool) {
+        self.reg.cr1.modify(|_, w| w.hddir().bit(transmit));
+    }
+
     /// Stuffs one byte of data into the SPI TX FIFO.
     ///
     /// Preconditions:
@@ -188,6 +302,44 @@ impl Spi {
         }
     }
 
+    /// Stuffs one 16-bit frame into the SPI TX FIFO. As with `send8`, there
+    /// must be room for it, and the frame size must be 16 bits or smaller.
+    pub fn send16(&self, frame: u16) {
+        // See send8 for why we go about it this way.
+
+        // Safety: "Downcast" txdr to a pointer to its sole member, as in send8.
+        let txdr: &vcell::VolatileCell<u32> =
+            unsafe { core::mem::transmute(&self.reg.txdr) };
+        // Little-endian, so the first halfword is the low one.
+        let txdr16 = txdr.as_ptr() as *mut u16;
+
+        // Safety: as in send8, using a halfword access this time.
+        unsafe {
+            txdr16.write_volatile(frame);
+        }
+    }
+
+    /// Pulls one 16-bit frame from the SPI RX FIFO. As with `recv8`, there
+    /// must be one there, and the frame size must be 16 bits or smaller.
+    pub fn recv16(&self) -> u16 {
+        // Safety: "Downcast" rxdr to a pointer to its sole member, as in send8.
+        let rxdr: &vcell::VolatileCell<u32> =
+            unsafe { core::mem::transmute(&self.reg.rxdr) };
+        let rxdr16 = rxdr.as_ptr() as *mut u16;
+
+        // Safety: as in recv8, using a halfword access this time.
+        unsafe { rxdr16.read_volatile() }
+    }
+
+    /// Changes the frame size chosen in `initialize`. Like the rest of CFG1,
+    /// this can only be changed while the peripheral is disabled.
+    pub fn set_frame_size(&self, bits_per_frame: u8) {
+        assert!((4..=32).contains(&bits_per_frame));
+        self.reg
+            .cfg1
+            .modify(|_, w| w.dsize().bits(bits_per_frame - 1));
+    }
+
     pub fn recv32(&self) -> u32 {
         self.reg.rxdr.read().rxdr().bits()
     }
@@ -244,9 +396,39 @@ impl Spi {
                 .set_bit()
                 .tifrec()
                 .set_bit()
+                .crcec()
+                .set_bit()
+                .tserfc()
+                .set_bit()
         });
     }
 
+    /// Turns the DMA requests for TX and RX data on or off. Like the rest of
+    /// CFG1, this can only be changed while the peripheral is disabled; when
+    /// starting a DMA transfer, the reference manual wants RX requests turned
+    /// on before the streams are enabled, and TX requests after.
+    pub fn set_dma_requests(&self, tx: bool, rx: bool) {
+        self.reg
+            .cfg1
+            .modify(|_, w| w.txdmaen().bit(tx).rxdmaen().bit(rx));
+    }
+
+    /// Address of TXDR, for use as a DMA destination.
+    pub fn txdr_address(&self) -> u32 {
+        &self.reg.txdr as *const _ as u32
+    }
+
+    /// Address of RXDR, for use as a DMA source.
+    pub fn rxdr_address(&self) -> u32 {
+        &self.reg.rxdr as *const _ as u32
+    }
+
+    /// Enables only the end-of-transfer interrupt, for transfers where DMA is
+    /// servicing the FIFOs.
+    pub fn enable_eot_interrupt(&self) {
+        self.reg.ier.write(|w| w.eotie().set_bit());
+    }
+
     pub fn enable_transfer_interrupts(&self) {
         self.reg
             .ier
@@ -276,4 +458,39 @@ impl Spi {
     pub fn check_overrun(&self) -> bool {
         self.reg.sr.read().ovr().is_overrun()
     }
+
+    /// Switches between host role (the default set by `initialize`) and
+    /// target role. As a target, the peripheral is clocked by the other end's
+    /// SCK, and only listens while its hardware SS pin is driven low; that
+    /// pin has to be routed to the peripheral, rather than used as a GPIO.
+    /// Like the rest of CFG2, this can only be changed while the peripheral
+    /// is disabled.
+    pub fn set_target_mode(&self, target: bool) {
+        // In host role we manage SS in software, as in `initialize`.
+        self.reg
+            .cfg2
+            .modify(|_, w| w.master().bit(!target).ssm().bit(!target));
+    }
+
+    /// Sets the byte that a target sends if the host clocks out a frame
+    /// before we've put one in the TX FIFO (an underrun). The peripheral must
+    /// be disabled.
+    pub fn set_underrun_data(&self, byte: u8) {
+        // UDRCFG (bits 10:9) = 0 sends the constant in UDRDR, and UDRDET
+        // (bits 12:11) = 0 checks for underrun at the start of each frame.
+        const UDR_MASK: u32 = 0b1111 << 9;
+        // Safety: we're only clearing the underrun fields, for which zero is
+        // a defined setting.
+        self.reg
+            .cfg1
+            .modify(|r, w| unsafe { w.bits(r.bits() & !UDR_MASK) });
+        // Safety: UDRDR is a plain 32-bit value with no reserved bits.
+        self.reg.udrdr.write(|w| unsafe { w.bits(u32::from(byte)) });
+    }
+
+    /// Checks whether a target has underrun since the peripheral was enabled.
+    /// This flag is cleared by `end`.
+    pub fn check_underrun(&self) -> bool {
+        self.reg.sr.read().udr().bit()
+    }
 }
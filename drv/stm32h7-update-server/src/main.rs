@@ -19,6 +19,7 @@ use drv_update_api::UpdateError;
 use idol_runtime::{
     ClientError, Leased, LenLimit, NotificationHandler, RequestError, R,
 };
+use image_header::Image;
 use ringbuf::*;
 use stm32h7::stm32h753 as device;
 use userlib::*;
@@ -455,19 +456,15 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
         // location at the end of the vector table.  The length of the vector
         // table is fixed in hardware, so this should never change.
         const HEADER_OFFSET: u32 = 0x298;
-        let header: ImageHeader = unsafe {
-            core::ptr::read_volatile(
-                (image_start + HEADER_OFFSET) as *const ImageHeader,
-            )
-        };
-        if header.magic != HEADER_MAGIC {
-            return Err(CabooseError::NoImageHeader.into());
-        }
+        // SAFETY: the header lies within the bank2 flash region, which we
+        // have mapped.
+        let image = unsafe { Image::read(image_start, HEADER_OFFSET) }
+            .ok_or(CabooseError::NoImageHeader)?;
 
         // Calculate where the image header implies that the image should end
         //
         // This is a one-past-the-end value.
-        let image_end = image_start + header.total_image_len;
+        let image_end = image_start + image.total_image_len();
 
         // Then, check that value against the BANK2 bounds.
         //
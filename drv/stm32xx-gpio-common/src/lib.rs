@@ -6,7 +6,7 @@
 
 #![no_std]
 
-use userlib::FromPrimitive;
+use num_derive::FromPrimitive;
 use zerocopy::AsBytes;
 
 #[cfg(feature = "server-support")]
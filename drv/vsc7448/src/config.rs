@@ -69,6 +69,50 @@ pub struct PortConfig {
     pub serdes: (PortSerdes, u8),
 }
 
+/// Board-specific SERDES output buffer tuning
+///
+/// The SERDES types have different output buffers, so each variant names the
+/// register fields of one type directly.  Each field is optional; `None`
+/// leaves the SDK-derived default in place.  Values are checked against the
+/// width of their register field when the SERDES is configured, and a tuning
+/// for the wrong SERDES type is rejected, both with `VscError::OutOfRange`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SerdesTuning {
+    Serdes1g {
+        /// Output amplitude (`SERDES1G_OB_CFG.OB_AMP_CTRL`, 4 bits)
+        ob_amp_ctrl: Option<u8>,
+    },
+    Serdes6g {
+        /// Output amplitude (`SERDES6G_OB_CFG1.OB_LEV`, 6 bits)
+        ob_lev: Option<u8>,
+        /// Pre-cursor emphasis (`SERDES6G_OB_CFG.OB_PREC`, 5 bits)
+        ob_prec: Option<u8>,
+        /// Post-cursor emphasis (`SERDES6G_OB_CFG.OB_POST0`, 6 bits)
+        ob_post0: Option<u8>,
+    },
+    Serdes10g {
+        /// Output amplitude (`SD10G65_OB_CFG1.LEV`, 6 bits)
+        lev: Option<u8>,
+        /// Packed FIR coefficients for the output driver, which control both
+        /// pre- and post-cursor emphasis (`SD10G65_OB_CFG2.D_FILTER`, 24 bits)
+        d_filter: Option<u32>,
+    },
+}
+
+/// Tuning for the SERDES attached to a particular DEV
+///
+/// This is keyed by DEV (rather than SERDES), because that's how links are
+/// usually described in board documentation, e.g. "DEV10G_0 to Tofino".
+///
+/// A QSGMII SERDES carries four DEVs, and is configured once for the quad; to
+/// tune it, key the override on the quad's base DEV (the one whose index is a
+/// multiple of 4).  Overrides for the other three DEVs are ignored.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SerdesOverride {
+    pub dev: (PortDev, u8),
+    pub tuning: SerdesTuning,
+}
+
 /// The VSC7448 has 52 physical ports.  The port mode uniquely determines the
 /// port device type (1G, 2G5, etc) and device number.
 #[derive(Copy, Clone, Debug)]
@@ -15,7 +15,10 @@ mod port;
 mod serdes10g;
 mod serdes1g;
 
-use crate::config::{PortConfig, PortDev, PortMap, PortMode, PortSerdes};
+use crate::config::{
+    PortConfig, PortDev, PortMap, PortMode, PortSerdes, SerdesOverride,
+    SerdesTuning,
+};
 use userlib::{hl::sleep_for, UnwrapLite};
 use vsc7448_pac::{types::RegisterAddress, *};
 
@@ -152,44 +155,55 @@ impl<'a, R: Vsc7448Rw> Vsc7448<'a, R> {
     }
 
     /// Configures all ports in the system from a single `PortMap`
+    ///
+    /// `serdes_overrides` lists board-specific SERDES tuning, keyed by DEV;
+    /// ports whose DEV isn't in the list use the default SERDES settings.  A
+    /// QSGMII quad is keyed by its base DEV (see `SerdesOverride`).
     pub fn configure_ports_from_map(
         &self,
         map: &PortMap,
+        serdes_overrides: &[SerdesOverride],
     ) -> Result<(), VscError> {
         for p in 0..map.len() {
             if let Some(cfg) = map.port_config(p as u8) {
-                self.configure_port_from_config(p as u8, cfg)?;
+                let tuning = serdes_overrides
+                    .iter()
+                    .find(|o| o.dev == cfg.dev)
+                    .map(|o| &o.tuning);
+                self.configure_port_from_config(p as u8, cfg, tuning)?;
             }
         }
         self.apply_calendar()?;
         Ok(())
     }
 
-    /// Configures a single port, given its number and the `PortConfig`
+    /// Configures a single port, given its number, the `PortConfig`, and
+    /// tuning for its SERDES
     fn configure_port_from_config(
         &self,
         p: u8,
         cfg: PortConfig,
+        tuning: Option<&SerdesTuning>,
     ) -> Result<(), VscError> {
         match cfg.mode {
             PortMode::Sgmii(_) => match cfg.serdes.0 {
-                PortSerdes::Serdes10g => self.init_10g_sgmii(p, cfg),
+                PortSerdes::Serdes10g => self.init_10g_sgmii(p, cfg, tuning),
                 PortSerdes::Serdes1g | PortSerdes::Serdes6g => {
-                    self.init_sgmii(p, cfg)
+                    self.init_sgmii(p, cfg, tuning)
                 }
             },
             PortMode::Qsgmii(_) => {
                 if p % 4 == 0 {
-                    self.init_qsgmii(p, cfg)
+                    self.init_qsgmii(p, cfg, tuning)
                 } else {
                     // All QSGMII ports are initialized with the base port
                     // of the set, so we ignore other ports here.
                     Ok(())
                 }
             }
-            PortMode::Sfi => self.init_sfi(p, cfg),
+            PortMode::Sfi => self.init_sfi(p, cfg, tuning),
             PortMode::BaseKr => {
-                self.init_sfi(p, cfg)?;
+                self.init_sfi(p, cfg, tuning)?;
                 Dev10g::new(cfg.dev.1)?.init_10gbase_kr(self.rw)?;
                 Ok(())
             }
@@ -200,7 +214,12 @@ impl<'a, R: Vsc7448Rw> Vsc7448<'a, R> {
     /// be in the range 49..=52, otherwise this function will panic.
     ///
     /// This will configure the appropriate DEV10G and SERDES10G.
-    fn init_sfi(&self, p: u8, cfg: PortConfig) -> Result<(), VscError> {
+    fn init_sfi(
+        &self,
+        p: u8,
+        cfg: PortConfig,
+        tuning: Option<&SerdesTuning>,
+    ) -> Result<(), VscError> {
         assert!(matches!(cfg.mode, PortMode::Sfi | PortMode::BaseKr));
         assert_eq!(cfg.dev.0, PortDev::Dev10g);
 
@@ -223,7 +242,8 @@ impl<'a, R: Vsc7448Rw> Vsc7448<'a, R> {
                 PortMode::Sfi => serdes10g::SerdesPresetType::DacHw,
                 PortMode::BaseKr => serdes10g::SerdesPresetType::KrHw,
                 _ => unreachable!(), // checked above
-            }))?;
+            }))?
+            .with_tuning(tuning)?;
         serdes_cfg.apply(cfg.serdes.1, self.rw)?;
 
         self.set_calendar_bandwidth(p, Bandwidth::Bw10G)?;
@@ -234,7 +254,12 @@ impl<'a, R: Vsc7448Rw> Vsc7448<'a, R> {
     /// convert from ports to DEV and SERDES.
     ///
     /// Each value in `ports` must be between 0 and 31, or 48 (the NPI port)
-    fn init_sgmii(&self, p: u8, cfg: PortConfig) -> Result<(), VscError> {
+    fn init_sgmii(
+        &self,
+        p: u8,
+        cfg: PortConfig,
+        tuning: Option<&SerdesTuning>,
+    ) -> Result<(), VscError> {
         assert!(matches!(cfg.mode, PortMode::Sgmii(_)));
 
         let dev = match cfg.dev.0 {
@@ -249,10 +274,12 @@ impl<'a, R: Vsc7448Rw> Vsc7448<'a, R> {
         match cfg.serdes.0 {
             PortSerdes::Serdes1g => {
                 serdes1g::Config::new(serdes1g::Mode::Sgmii)
+                    .with_tuning(tuning)?
                     .apply(cfg.serdes.1, self.rw)?
             }
             PortSerdes::Serdes6g => {
                 serdes6g::Config::new(serdes6g::Mode::Sgmii)
+                    .with_tuning(tuning)?
                     .apply(cfg.serdes.1, self.rw)?
             }
             _ => panic!("Invalid SERDES in init_sgmii"),
@@ -281,13 +308,69 @@ impl<'a, R: Vsc7448Rw> Vsc7448<'a, R> {
         Ok(())
     }
 
+    /// Disables or re-enables a single configured port.
+    ///
+    /// Disabling flushes the port, leaving its MAC and PCS clock domains in
+    /// reset, then turns off the PCS and the port in the queue forwarder.
+    ///
+    /// Re-enabling re-runs the per-device init (e.g. `init_sgmii`) for this
+    /// port's DEV only.  Shared resources (the SERDES and, for QSGMII, the
+    /// other three ports in the quad) are left untouched, so this must only be
+    /// called on a port that was previously configured by
+    /// `configure_ports_from_map`.
+    pub fn set_port_enabled(
+        &self,
+        p: u8,
+        cfg: PortConfig,
+        enabled: bool,
+    ) -> Result<(), VscError> {
+        match cfg.dev.0 {
+            PortDev::Dev1g | PortDev::Dev2g5 => {
+                let dev = match cfg.dev.0 {
+                    PortDev::Dev1g => DevGeneric::new_1g,
+                    PortDev::Dev2g5 => DevGeneric::new_2g5,
+                    _ => unreachable!(),
+                }(cfg.dev.1)?;
+                assert_eq!(dev.port(), p);
+                if enabled {
+                    dev.init_sgmii(self.rw, cfg.mode.speed())?;
+                } else {
+                    port::port1g_flush(&dev, self)?;
+                    self.modify(
+                        dev.regs().PCS1G_CFG_STATUS().PCS1G_CFG(),
+                        |r| r.set_pcs_ena(0),
+                    )?;
+                }
+            }
+            PortDev::Dev10g => {
+                let dev = Dev10g::new(cfg.dev.1)?;
+                assert_eq!(dev.port(), p);
+                if enabled {
+                    dev.init_sfi(self.rw)?;
+                    if matches!(cfg.mode, PortMode::BaseKr) {
+                        dev.init_10gbase_kr(self.rw)?;
+                    }
+                } else {
+                    // `port10g_flush` also disables the 10G PCS blocks
+                    port::port10g_flush(&dev, self)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Enables QSGMII mode for blocks of four ports beginning at `start_port`.
     /// This will configure the appropriate DEV1G or DEV2G5 devices, and the
     /// appropriate SERDES6G, based on Table 8 in the datasheet;
     ///
     /// Each value in `start_ports` must be divisible by 4 and below 48;
     /// otherwise, this function will panic.
-    fn init_qsgmii(&self, p: u8, cfg: PortConfig) -> Result<(), VscError> {
+    fn init_qsgmii(
+        &self,
+        p: u8,
+        cfg: PortConfig,
+        tuning: Option<&SerdesTuning>,
+    ) -> Result<(), VscError> {
         assert!(matches!(cfg.mode, PortMode::Qsgmii(_)));
         assert_eq!(p % 4, 0);
 
@@ -317,7 +400,8 @@ impl<'a, R: Vsc7448Rw> Vsc7448<'a, R> {
         }
 
         assert_eq!(cfg.serdes.0, PortSerdes::Serdes6g);
-        let qsgmii_cfg = serdes6g::Config::new(serdes6g::Mode::Qsgmii);
+        let qsgmii_cfg = serdes6g::Config::new(serdes6g::Mode::Qsgmii)
+            .with_tuning(tuning)?;
         qsgmii_cfg.apply(cfg.serdes.1, self.rw)?;
 
         for dev in cfg.dev.1..(cfg.dev.1 + 4) {
@@ -336,7 +420,12 @@ impl<'a, R: Vsc7448Rw> Vsc7448<'a, R> {
     ///
     /// This is only valid for ports 49-52, and will panic otherwise; see
     /// Table 9 for details.
-    fn init_10g_sgmii(&self, p: u8, cfg: PortConfig) -> Result<(), VscError> {
+    fn init_10g_sgmii(
+        &self,
+        p: u8,
+        cfg: PortConfig,
+        tuning: Option<&SerdesTuning>,
+    ) -> Result<(), VscError> {
         assert!(p >= 49);
         assert!(p <= 52);
 
@@ -344,7 +433,8 @@ impl<'a, R: Vsc7448Rw> Vsc7448<'a, R> {
         assert_eq!(cfg.serdes.0, PortSerdes::Serdes10g);
 
         let serdes10g_cfg_sgmii =
-            serdes10g::Config::new(serdes10g::Mode::Sgmii)?;
+            serdes10g::Config::new(serdes10g::Mode::Sgmii)?
+                .with_tuning(tuning)?;
 
         assert_eq!(cfg.dev.0, PortDev::Dev2g5);
         let d2g5 = DevGeneric::new_2g5(cfg.dev.1).unwrap_lite();
@@ -899,6 +989,69 @@ impl<'a, R: Vsc7448Rw> Vsc7448<'a, R> {
     pub fn check_10gbase_kr_aneg(&self, dev: u8) -> Result<bool, VscError> {
         Dev10g::new(dev)?.check_10gbase_kr_aneg(self)
     }
+
+    /// Reads the receive-side status of the SERDES behind the given port, as
+    /// seen by its PCS.
+    ///
+    /// Unlike the DEV-level link status, this distinguishes a SERDES which
+    /// has lost signal or lock from a port which is merely disabled.
+    pub fn serdes_status(
+        &self,
+        cfg: &PortConfig,
+    ) -> Result<SerdesStatus, VscError> {
+        match cfg.dev.0 {
+            PortDev::Dev1g | PortDev::Dev2g5 => {
+                let dev = match cfg.dev.0 {
+                    PortDev::Dev1g => DevGeneric::new_1g(cfg.dev.1),
+                    PortDev::Dev2g5 => DevGeneric::new_2g5(cfg.dev.1),
+                    _ => unreachable!(),
+                }?;
+                let pcs = dev.regs().PCS1G_CFG_STATUS();
+                let status = self.read(pcs.PCS1G_LINK_STATUS())?;
+                let link_down_count = self.read(pcs.PCS1G_LINK_DOWN_CNT())?;
+                Ok(SerdesStatus {
+                    serdes: cfg.serdes,
+                    signal_detect: Some(status.signal_detect() != 0),
+                    sync: status.sync_status() != 0,
+                    error_count: link_down_count.into(),
+                })
+            }
+            PortDev::Dev10g => {
+                let pcs = PCS10G_BR(cfg.dev.1).PCS_10GBR_STATUS();
+                let status = self.read(pcs.PCS_STATUS())?;
+                let errored_blocks = self.read(pcs.RX_ERRBLK_CNT())?;
+                Ok(SerdesStatus {
+                    serdes: cfg.serdes,
+                    signal_detect: None,
+                    sync: status.rx_block_lock() != 0,
+                    error_count: errored_blocks.into(),
+                })
+            }
+        }
+    }
+}
+
+/// Receive-side status of the SERDES behind a port, as reported by its PCS
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    serde::Serialize,
+    serde::Deserialize,
+    hubpack::SerializedSize,
+)]
+pub struct SerdesStatus {
+    /// The SERDES instance that this status describes
+    pub serdes: (PortSerdes, u8),
+    /// Whether the PCS sees a signal from the SERDES.  The 10G PCS doesn't
+    /// report this, so it's `None` for 10G ports.
+    pub signal_detect: Option<bool>,
+    /// Whether the PCS is synchronized: 8b/10b sync for 1G and 2.5G ports, or
+    /// block lock for 10G ports.
+    pub sync: bool,
+    /// Error counter from the PCS: the number of link-down events for 1G and
+    /// 2.5G ports, or the number of errored blocks received for 10G ports.
+    pub error_count: u32,
 }
 
 enum Bandwidth {
@@ -921,6 +1074,15 @@ impl Bandwidth {
 
 /// Sets the frequency of the reference clock.  The specific values are based
 /// on the REFCLK_SEL pins.
+/// Checks that a `SerdesTuning` value fits in its `bits`-wide register field
+fn serdes_tuning_field(value: u32, bits: u32) -> Result<u32, VscError> {
+    if value >> bits == 0 {
+        Ok(value)
+    } else {
+        Err(VscError::OutOfRange)
+    }
+}
+
 #[derive(Copy, Clone)]
 pub enum RefClockFreq {
     Clk25MHz = 0b100,
@@ -3,7 +3,7 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 /// Tools for working with the 10G SERDES (sd10g65 in the SDK)
-use crate::{Vsc7448Rw, VscError};
+use crate::{config::SerdesTuning, serdes_tuning_field, Vsc7448Rw, VscError};
 use userlib::hl;
 use vsc7448_pac::*;
 
@@ -23,6 +23,8 @@ pub struct Config {
     if_width: u32,
     if_mode_sel: u32,
     ob_cfg2_d_filter: u32,
+    /// Output amplitude override; if `None`, `OB_CFG1` is left at defaults
+    ob_cfg1_lev: Option<u32>,
 
     half_rate_mode: bool,
     high_data_rate: bool,
@@ -121,9 +123,32 @@ impl Config {
             pll_lpf_res,
             pllf_ref_cnt_end,
             ob_cfg2_d_filter,
+            ob_cfg1_lev: None,
             mode,
         })
     }
+
+    /// Applies board-specific tuning (if any) on top of the mode's defaults
+    pub fn with_tuning(
+        mut self,
+        t: Option<&SerdesTuning>,
+    ) -> Result<Self, VscError> {
+        match t {
+            None => (),
+            Some(&SerdesTuning::Serdes10g { lev, d_filter }) => {
+                if let Some(lev) = lev {
+                    self.ob_cfg1_lev =
+                        Some(serdes_tuning_field(lev.into(), 6)?);
+                }
+                if let Some(d_filter) = d_filter {
+                    self.ob_cfg2_d_filter = serdes_tuning_field(d_filter, 24)?;
+                }
+            }
+            Some(_) => return Err(VscError::OutOfRange),
+        }
+        Ok(self)
+    }
+
     /// Based on `jaguar2c_sd10g_*_register_cfg`.  Any variables which aren't
     /// changed are converted into direct register assignments (rather than
     /// passing them around in the config struct).
@@ -241,7 +266,13 @@ impl Config {
         v.modify(ob.SD10G65_OB_CFG0(), |r| {
             r.set_sel_ifw(self.if_mode_sel);
         })?;
-        // The SDK also writes to OB_CFG1, but only to set defaults
+        // The SDK also writes to OB_CFG1, but only to set defaults, so we only
+        // touch it if the board has asked for a different amplitude.
+        if let Some(lev) = self.ob_cfg1_lev {
+            v.modify(ob.SD10G65_OB_CFG1(), |r| {
+                r.set_lev(lev);
+            })?;
+        }
 
         v.modify(ob.SD10G65_OB_CFG2(), |r| {
             r.set_d_filter(self.ob_cfg2_d_filter);
@@ -2,7 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use crate::{Vsc7448Rw, VscError};
+use crate::{config::SerdesTuning, serdes_tuning_field, Vsc7448Rw, VscError};
 use vsc7448_pac::*;
 
 pub enum Mode {
@@ -85,6 +85,24 @@ impl Config {
             },
         }
     }
+
+    /// Applies board-specific tuning (if any) on top of the mode's defaults
+    pub fn with_tuning(
+        mut self,
+        t: Option<&SerdesTuning>,
+    ) -> Result<Self, VscError> {
+        match t {
+            None => (),
+            Some(&SerdesTuning::Serdes1g { ob_amp_ctrl }) => {
+                if let Some(amp) = ob_amp_ctrl {
+                    self.ob_amp_ctrl = serdes_tuning_field(amp.into(), 4)?;
+                }
+            }
+            Some(_) => return Err(VscError::OutOfRange),
+        }
+        Ok(self)
+    }
+
     pub fn apply(
         &self,
         instance: u8,
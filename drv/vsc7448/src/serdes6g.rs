@@ -2,7 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use crate::{Vsc7448Rw, VscError};
+use crate::{config::SerdesTuning, serdes_tuning_field, Vsc7448Rw, VscError};
 use userlib::hl;
 use vsc7448_pac::*;
 
@@ -20,6 +20,10 @@ pub struct Config {
     qrate: u32,
     if_mode: u32,
     des_bw_ana: u32,
+
+    /// Optional emphasis overrides, left at their reset values if `None`
+    ob_prec: Option<u32>,
+    ob_post0: Option<u32>,
 }
 
 /// This controls how many times we poll the SERDES1 register after a
@@ -79,6 +83,8 @@ impl Config {
                 qrate: 0,
                 if_mode: 3,
                 des_bw_ana: 5,
+                ob_prec: None,
+                ob_post0: None,
             },
             Mode::Sgmii => Self {
                 ob_ena1v_mode: 1,
@@ -88,10 +94,39 @@ impl Config {
                 qrate: 1,
                 if_mode: 1,
                 des_bw_ana: 3,
+                ob_prec: None,
+                ob_post0: None,
             },
         }
     }
 
+    /// Applies board-specific tuning (if any) on top of the mode's defaults
+    pub fn with_tuning(
+        mut self,
+        t: Option<&SerdesTuning>,
+    ) -> Result<Self, VscError> {
+        match t {
+            None => (),
+            Some(&SerdesTuning::Serdes6g {
+                ob_lev,
+                ob_prec,
+                ob_post0,
+            }) => {
+                if let Some(lev) = ob_lev {
+                    self.ob_lev = serdes_tuning_field(lev.into(), 6)?;
+                }
+                if let Some(prec) = ob_prec {
+                    self.ob_prec = Some(serdes_tuning_field(prec.into(), 5)?);
+                }
+                if let Some(post0) = ob_post0 {
+                    self.ob_post0 = Some(serdes_tuning_field(post0.into(), 6)?);
+                }
+            }
+            Some(_) => return Err(VscError::OutOfRange),
+        }
+        Ok(self)
+    }
+
     pub fn apply(
         &self,
         instance: u8,
@@ -117,6 +152,12 @@ impl Config {
         v.modify(ana_cfg.SERDES6G_OB_CFG1(), |r| {
             r.set_ob_lev(self.ob_lev);
         })?;
+        if let Some(prec) = self.ob_prec {
+            v.modify(ana_cfg.SERDES6G_OB_CFG(), |r| r.set_ob_prec(prec))?;
+        }
+        if let Some(post0) = self.ob_post0 {
+            v.modify(ana_cfg.SERDES6G_OB_CFG(), |r| r.set_ob_post0(post0))?;
+        }
         v.modify(ana_cfg.SERDES6G_DES_CFG(), |r| {
             r.set_des_bw_ana(self.des_bw_ana);
         })?;
@@ -0,0 +1,67 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Reading the `ImageHeader` of a Hubris image in flash
+//!
+//! The header is placed directly after the vector table, so it's found at a
+//! fixed offset from the start of the image: the size of the vector table,
+//! which is set by the part. This works from the bootloader as well as from
+//! tasks, which need the image's flash mapped (e.g. through `extern-regions`)
+//! to read it.
+
+#![no_std]
+
+use abi::{ImageHeader, HEADER_MAGIC};
+
+/// A Hubris image in flash, with a copy of its header.
+pub struct Image {
+    start: u32,
+    header: ImageHeader,
+}
+
+impl Image {
+    /// Reads the header of the image starting at `start`, whose vector table is
+    /// `vector_size` bytes long. Returns `None` if there's no header there, as
+    /// judged by its magic number.
+    ///
+    /// The header is copied out, so that the flash holding it can be
+    /// rewritten (e.g. by an update) without invalidating the `Image`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must be able to read `size_of::<ImageHeader>()` bytes from
+    /// `start + vector_size`, i.e. that range must be mapped and, on parts
+    /// that fault on reading erased flash, programmed.
+    pub unsafe fn read(start: u32, vector_size: u32) -> Option<Self> {
+        let header = core::ptr::read_volatile(
+            (start + vector_size) as *const ImageHeader,
+        );
+        if header.magic != HEADER_MAGIC {
+            return None;
+        }
+        Some(Self { start, header })
+    }
+
+    /// Returns the image version.
+    pub fn version(&self) -> u32 {
+        self.header.version
+    }
+
+    /// Returns the image epoch.
+    pub fn epoch(&self) -> u32 {
+        self.header.epoch
+    }
+
+    /// Returns the total length of the image in flash, including the caboose
+    /// if there is one.
+    pub fn total_image_len(&self) -> u32 {
+        self.header.total_image_len
+    }
+
+    /// Returns the address of the image's vector table, which is also the
+    /// start of the image.
+    pub fn vector_table(&self) -> u32 {
+        self.start
+    }
+}
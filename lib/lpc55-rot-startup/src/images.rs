@@ -244,31 +244,22 @@ impl Image {
         self.get_vectors().nxp_image_type
     }
 
-    // Get a pointer to where the ImageHeader should be.
-    // Note that it may not be present if the image
-    // is corrupted or is a bootloader.
-    fn get_header_ptr(&self) -> *const ImageHeader {
-        let vector_size = core::ptr::addr_of!(__vector_size) as u32;
-        (self.get_img_start() + vector_size) as *const ImageHeader
-    }
-
-    fn get_imageheader(&self) -> Result<&ImageHeader, ImageError> {
+    fn get_imageheader(&self) -> Result<image_header::Image, ImageError> {
         // Check Hubris header.
-        // Note that bootloaders without Hubris headers have been released.
-        let header_ptr = self.get_header_ptr();
+        // Note that it may not be present if the image is corrupted or is a
+        // bootloader, and bootloaders without Hubris headers have been
+        // released.
+        let vector_size = core::ptr::addr_of!(__vector_size) as u32;
 
         // Safety: We've validated the header location is programmed so this
         // will not trigger a fault.
         // The values used are all link-time constants.
-        let header = unsafe { &*header_ptr };
-        if header.magic != abi::HEADER_MAGIC {
-            return Err(ImageError::BadMagic);
-        }
-        Ok(header)
+        unsafe { image_header::Image::read(self.get_img_start(), vector_size) }
+            .ok_or(ImageError::BadMagic)
     }
 
     fn get_imageheader_total_image_len(&self) -> Result<u32, ImageError> {
-        Ok(self.get_imageheader()?.total_image_len)
+        Ok(self.get_imageheader()?.total_image_len())
     }
 
     /// Test an image for viability.
@@ -163,6 +163,15 @@
 //! counted_ringbuf!(MyEvent, 16, MyEvent::NothingHappened, no_dedup);
 //! ```
 //!
+//! ### Timestamps
+//!
+//! If the "timestamps" feature is enabled, each entry also records the value
+//! of `sys_get_timer().now` at the time it was recorded, in a `timestamp`
+//! field at the end of [`RingbufEntry`].  When an entry is de-duplicated, the
+//! timestamp is that of the first occurrence.  This adds 8 bytes to each
+//! entry and depends on `userlib`, so it is off by default and cannot be used
+//! from the kernel.
+//!
 //! ## Inspecting a ring buffer via Humility
 //!
 //! Humility has built-in support for dumping a ring buffer, and will (by
@@ -214,6 +223,9 @@
 //!          3
 //!        ))
 //!    },...
+//!  ],
+//!  total: 36
+//! }
 //! ```
 //!
 //! To inspect a ring buffer that is in a dependency, the full crate will need
@@ -235,6 +247,9 @@
 //!        core::result::Result<[u8; 2], drv_i2c_api::ResponseCode>::Err(0)
 //!      )
 //!    },...
+//!  ],
+//!  total: 2559
+//! }
 //! ```
 #![no_std]
 #[cfg(feature = "counters")]
@@ -284,25 +299,17 @@ macro_rules! ringbuf {
         static $name: $crate::StaticCell<$crate::Ringbuf<$t, u16, $n>> =
             $crate::StaticCell::new($crate::Ringbuf {
                 last: None,
-                buffer: [$crate::RingbufEntry {
-                    line: 0,
-                    generation: 0,
-                    count: 0,
-                    payload: $init,
-                }; $n],
+                buffer: [$crate::RingbufEntry::new(0, $init); $n],
+                total: 0,
             });
     };
     ($name:ident, $t:ty, $n:expr, $init:expr, no_dedup) => {
         #[used]
-        static $name: $crate::StaticCell<$crate::Ringbuf<$t, () $n>> =
+        static $name: $crate::StaticCell<$crate::Ringbuf<$t, (), $n>> =
             $crate::StaticCell::new($crate::Ringbuf {
                 last: None,
-                buffer: [$crate::RingbufEntry {
-                    line: 0,
-                    generation: 0,
-                    count: (),
-                    payload: $init,
-                }; $n],
+                buffer: [$crate::RingbufEntry::new((), $init); $n],
+                total: 0,
             });
     };
     ($t:ty, $n:expr, $init:expr, no_dedup) => {
@@ -343,12 +350,8 @@ macro_rules! counted_ringbuf {
             $crate::CountedRingbuf {
                 ringbuf: $crate::StaticCell::new($crate::Ringbuf {
                     last: None,
-                    buffer: [$crate::RingbufEntry {
-                        line: 0,
-                        generation: 0,
-                        count: 0,
-                        payload: $init,
-                    }; $n],
+                    buffer: [$crate::RingbufEntry::new(0, $init); $n],
+                    total: 0,
                 }),
                 counters: <$t as $crate::Count>::NEW_COUNTERS,
             };
@@ -359,12 +362,8 @@ macro_rules! counted_ringbuf {
             $crate::CountedRingbuf {
                 ringbuf: $crate::StaticCell::new($crate::Ringbuf {
                     last: None,
-                    buffer: [$crate::RingbufEntry {
-                        line: 0,
-                        generation: 0,
-                        count: (),
-                        payload: $init,
-                    }; $n],
+                    buffer: [$crate::RingbufEntry::new((), $init); $n],
+                    total: 0,
                 }),
                 counters: <$t as $crate::Count>::NEW_COUNTERS,
             };
@@ -496,22 +495,52 @@ macro_rules! ringbuf_entry_root {
 /// the most recent entry (in terms of both `line` and `payload`), `count` will
 /// be incremented rather than generating a new entry.
 ///
+/// If the `timestamps` feature is enabled, each entry also records the kernel
+/// timestamp (`sys_get_timer().now`) at which it was first recorded; this
+/// field is placed last so that the layout of the other fields is unchanged.
+///
 #[derive(Debug, Copy, Clone)]
 pub struct RingbufEntry<T: Copy, C> {
     pub line: u16,
     pub generation: u16,
     pub payload: T,
     pub count: C,
+    #[cfg(feature = "timestamps")]
+    pub timestamp: u64,
+}
+
+impl<T: Copy, C> RingbufEntry<T, C> {
+    /// Returns an empty entry, used to statically initialize a [`Ringbuf`].
+    ///
+    /// This is used by the [`ringbuf!`] and [`counted_ringbuf!`] macros
+    /// instead of a struct literal, because the set of fields depends on this
+    /// crate's features (which the macro's caller can't see).
+    pub const fn new(count: C, payload: T) -> Self {
+        Self {
+            line: 0,
+            generation: 0,
+            payload,
+            count,
+            #[cfg(feature = "timestamps")]
+            timestamp: 0,
+        }
+    }
 }
 
 ///
 /// A ring buffer of parametrized type and size.  In practice, instantiating
 /// this directly is strange -- see the [`ringbuf!`] macro.
 ///
+/// `total` counts every call that recorded an entry (including those that were
+/// de-duplicated into an existing entry's `count`), wrapping on overflow; by
+/// comparing it against the number of entries retained in `buffer`, one can
+/// tell how many events have fallen off the end of the ring.
+///
 #[derive(Debug)]
 pub struct Ringbuf<T: Copy, C, const N: usize> {
     pub last: Option<usize>,
     pub buffer: [RingbufEntry<T, C>; N],
+    pub total: u32,
 }
 
 ///
@@ -583,6 +612,8 @@ impl<T: Copy + PartialEq, const N: usize> RecordEntry<T>
         let Some(mut ring) = self.try_borrow_mut() else {
             return;
         };
+        ring.total = ring.total.wrapping_add(1);
+
         // If this is the first time this ringbuf has been poked, last will be
         // None. In this specific case we want to make sure we don't add to the
         // count of an existing entry, and also that we deposit the first entry
@@ -622,6 +653,8 @@ impl<T: Copy, const N: usize> RecordEntry<T>
         let Some(mut ring) = self.try_borrow_mut() else {
             return;
         };
+        ring.total = ring.total.wrapping_add(1);
+
         // If this is the first time this ringbuf has been poked, last will be
         // None. In this specific case we want to make sure we don't add to the
         // count of an existing entry, and also that we deposit the first entry
@@ -689,6 +722,8 @@ impl<T: Copy, C, const N: usize> Ringbuf<T, C, N> {
             payload,
             count,
             generation: ent.generation.wrapping_add(1),
+            #[cfg(feature = "timestamps")]
+            timestamp: userlib::sys_get_timer().now,
         };
 
         self.last = Some(ndx);
@@ -165,6 +165,7 @@ struct PacketCountConvert(drv_monorail_api::PacketCount);
 
 impl From<PacketCountConvert> for PacketCount {
     fn from(PacketCountConvert(c): PacketCountConvert) -> Self {
+        // `crc_err` has nowhere to go in the MGS message, so it stays IPC-only
         Self {
             multicast: c.multicast,
             unicast: c.unicast,
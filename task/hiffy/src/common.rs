@@ -646,7 +646,7 @@ pub(crate) fn spi_read(
 
     let spi = drv_spi_api::Spi::from(task);
 
-    func_err(spi.exchange(device, &data[0..len], &mut rval[0..rlen]))?;
+    func_err(spi.exchange(device, false, &data[0..len], &mut rval[0..rlen]))?;
     Ok(rlen)
 }
 
@@ -664,7 +664,7 @@ pub(crate) fn spi_write(
 
     let spi = drv_spi_api::Spi::from(task);
 
-    func_err(spi.write(device, &data[0..len]))?;
+    func_err(spi.write(device, false, &data[0..len]))?;
     Ok(0)
 }
 
@@ -73,6 +73,7 @@ mod map {
     use vsc7448::config::{
         PortMap,
         PortMode::{self, *},
+        SerdesOverride,
         Speed::*,
     };
     const SGMII: Option<PortMode> = Some(Sgmii(Speed100M));
@@ -136,8 +137,13 @@ mod map {
         SGMII, // 51 | DEV2G5_27 | SERDES10G_2 | Cubby 30 (shadows DEV10G_2)
         SGMII, // 52 | DEV2G5_28 | SERDES10G_3 | Cubby 31 (shadows DEV10G_3)
     ]);
+
+    /// Board-specific SERDES tuning, keyed by DEV (e.g. to raise the output
+    /// amplitude on a long trace).  Links which aren't listed here use the
+    /// default settings from the SDK.
+    pub const SERDES_OVERRIDES: &[SerdesOverride] = &[];
 }
-pub use map::PORT_MAP;
+pub use map::{PORT_MAP, SERDES_OVERRIDES};
 
 pub fn preinit() {
     // Nothing to do here, just stubbing out for the BSP interface
@@ -209,7 +215,8 @@ impl<'a, R: Vsc7448Rw> Bsp<'a, R> {
 
         self.phy_vsc8504_init()?;
 
-        self.vsc7448.configure_ports_from_map(&PORT_MAP)?;
+        self.vsc7448
+            .configure_ports_from_map(&PORT_MAP, SERDES_OVERRIDES)?;
         self.vsc7448.configure_vlan_sidecar_unlocked()?;
         self.vsc7448_postconfig()?;
 
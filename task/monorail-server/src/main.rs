@@ -50,11 +50,25 @@ task_slot!(SYS, sys);
 enum Trace {
     #[count(skip)]
     None,
-    BspInit(u64),
-    BspInitFailed(#[count(children)] VscError),
+    BspInit {
+        elapsed: u64,
+        attempts: u32,
+    },
+    BspInitFailed {
+        attempt: u32,
+        #[count(children)]
+        err: VscError,
+        delay_ms: u32,
+    },
     WakeErr(#[count(children)] VscError),
 }
-counted_ringbuf!(Trace, 2, Trace::None);
+counted_ringbuf!(Trace, 8, Trace::None);
+
+/// Initial delay before retrying a failed BSP initialization
+const INIT_RETRY_MIN_DELAY_MS: u32 = 10;
+
+/// Ceiling on the exponential backoff between BSP initialization attempts
+const INIT_RETRY_MAX_DELAY_MS: u32 = 2000;
 
 #[export_name = "main"]
 fn main() -> ! {
@@ -68,44 +82,35 @@ fn main() -> ! {
     bsp::preinit();
 
     let t0 = sys_get_timer().now;
-    let bsp = match Bsp::new(&vsc7448) {
-        Ok(bsp) => {
-            let t1 = sys_get_timer().now;
-            ringbuf_entry!(Trace::BspInit(t1 - t0));
-            bsp
-        }
-        Err(e) => {
-            // BSP initialization has failed. We intend to retry it. Restarting
-            // the server is a convenient way of doing this.
-            //
-            // The first thing the BSP does when initialized is to assert the
-            // reset line on the VSC7448, which will put it into a known state
-            // for us to attempt initialization again. This _appears_ to reset
-            // all state that could potentially have caused a panic within the
-            // BSP, and in practice this panic occurs rarely and at most once.
-            //
-            // Writing the error into the ringbuf before panicking ensures that
-            // it's available in the dump for inspection, in case we're curious.
-            //
-            // It's tempting to reach for the non-dumping task restart operation
-            // (`Jefe::restart_me`) here, but it's probably not worth it for the
-            // following reasons:
-            //
-            // 1. This happens infrequently -- it's not like we crash on every
-            //    startup. We've seen this single-digit times so far.
-            //
-            // 2. Recording the dump in that infrequent case is actually useful,
-            //    since it gets us the error that caused the panic. (Dumping the
-            //    entire task burns 8 kiB to record a couple of bytes of error,
-            //    but, only when the problem happens.)
-            //
-            // Should someone in the future want to remove this panic for
-            // whatever reason, the _correct_ path is likely to retain the
-            // ringbuf entry, and then wrap this code with a retry loop. This
-            // will ensure that any errors are available for inspection (which
-            // `Jefe::restart_me` would not) while making the restart cheaper.
-            ringbuf_entry!(Trace::BspInitFailed(e));
-            panic!();
+    let mut attempt = 1;
+    let mut delay_ms = INIT_RETRY_MIN_DELAY_MS;
+    let bsp = loop {
+        match Bsp::new(&vsc7448) {
+            Ok(bsp) => {
+                let t1 = sys_get_timer().now;
+                ringbuf_entry!(Trace::BspInit {
+                    elapsed: t1 - t0,
+                    attempts: attempt,
+                });
+                break bsp;
+            }
+            Err(err) => {
+                // BSP initialization has failed, so we back off and try
+                // again. The first thing the BSP does when initialized is to
+                // assert the reset line on the VSC7448, which puts it into a
+                // known state for us to attempt initialization again.
+                //
+                // Each failure is recorded in the ringbuf (and counted), so
+                // that a flapping bring-up is visible without needing a dump.
+                ringbuf_entry!(Trace::BspInitFailed {
+                    attempt,
+                    err,
+                    delay_ms,
+                });
+                hl::sleep_for(u64::from(delay_ms));
+                attempt = attempt.saturating_add(1);
+                delay_ms = (delay_ms * 2).min(INIT_RETRY_MAX_DELAY_MS);
+            }
         }
     };
 
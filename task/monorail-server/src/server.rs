@@ -8,7 +8,7 @@ use crate::{
 };
 use drv_monorail_api::{
     LinkStatus, MacTableEntry, MonorailError, PacketCount, PhyStatus, PhyType,
-    PortCounters, PortDev, PortStatus, VscError,
+    PortCounters, PortDev, PortStatus, SerdesStatus, VscError,
 };
 use idol_runtime::{NotificationHandler, RequestError};
 use userlib::{sys_get_timer, sys_set_timer};
@@ -210,6 +210,10 @@ impl<'a, R: Vsc7448Rw> idl::InOrderMonorailImpl for ServerImpl<'a, R> {
                     .vsc7448
                     .read(stats.TX_MC_CNT())
                     .map_err(MonorailError::from)?;
+                let rx_crc_err = self
+                    .vsc7448
+                    .read(stats.RX_CRC_ERR_CNT())
+                    .map_err(MonorailError::from)?;
 
                 // TODO: if this port uses a PHY, then should we be checking
                 // the PHY's status instead of ours?
@@ -229,11 +233,13 @@ impl<'a, R: Vsc7448Rw> idl::InOrderMonorailImpl for ServerImpl<'a, R> {
                     unicast: tx_uc.into(),
                     multicast: tx_mc.into(),
                     broadcast: tx_bc.into(),
+                    crc_err: 0,
                 };
                 let rx = PacketCount {
                     unicast: rx_uc.into(),
                     multicast: rx_mc.into(),
                     broadcast: rx_bc.into(),
+                    crc_err: rx_crc_err.into(),
                 };
                 let link_down_sticky = link_down.link_down_sticky() != 0
                     || link_down.out_of_sync_sticky() != 0;
@@ -287,15 +293,21 @@ impl<'a, R: Vsc7448Rw> idl::InOrderMonorailImpl for ServerImpl<'a, R> {
                     .vsc7448
                     .read(stats.TX_MC_CNT())
                     .map_err(MonorailError::from)?;
+                let rx_crc_err = self
+                    .vsc7448
+                    .read(stats.RX_CRC_ERR_CNT())
+                    .map_err(MonorailError::from)?;
                 let tx = PacketCount {
                     unicast: tx_uc.into(),
                     multicast: tx_mc.into(),
                     broadcast: tx_bc.into(),
+                    crc_err: 0,
                 };
                 let rx = PacketCount {
                     unicast: rx_uc.into(),
                     multicast: rx_mc.into(),
                     broadcast: rx_bc.into(),
+                    crc_err: rx_crc_err.into(),
                 };
 
                 let intr = self
@@ -350,6 +362,9 @@ impl<'a, R: Vsc7448Rw> idl::InOrderMonorailImpl for ServerImpl<'a, R> {
                 self.vsc7448
                     .write(stats.TX_MC_CNT(), 0.into())
                     .map_err(MonorailError::from)?;
+                self.vsc7448
+                    .write(stats.RX_CRC_ERR_CNT(), 0.into())
+                    .map_err(MonorailError::from)?;
 
                 let dev = match cfg.dev.0 {
                     PortDev::Dev1g => DevGeneric::new_1g(cfg.dev.1),
@@ -399,6 +414,9 @@ impl<'a, R: Vsc7448Rw> idl::InOrderMonorailImpl for ServerImpl<'a, R> {
                 self.vsc7448
                     .write(stats.TX_MC_CNT(), 0.into())
                     .map_err(MonorailError::from)?;
+                self.vsc7448
+                    .write(stats.RX_CRC_ERR_CNT(), 0.into())
+                    .map_err(MonorailError::from)?;
 
                 self.vsc7448
                     .write_with(
@@ -450,6 +468,24 @@ impl<'a, R: Vsc7448Rw> idl::InOrderMonorailImpl for ServerImpl<'a, R> {
         }
     }
 
+    fn get_serdes_status(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        port: u8,
+    ) -> Result<SerdesStatus, RequestError<MonorailError>> {
+        if usize::from(port) >= self.map.len() {
+            return Err(MonorailError::InvalidPort.into());
+        }
+        let cfg = match self.map.port_config(port) {
+            None => return Err(MonorailError::UnconfiguredPort.into()),
+            Some(cfg) => cfg,
+        };
+        Ok(self
+            .vsc7448
+            .serdes_status(&cfg)
+            .map_err(MonorailError::from)?)
+    }
+
     fn get_phy_status(
         &mut self,
         _msg: &userlib::RecvMessage,
@@ -809,6 +845,25 @@ impl<'a, R: Vsc7448Rw> idl::InOrderMonorailImpl for ServerImpl<'a, R> {
     ) -> Result<(), RequestError<MonorailError>> {
         self.bsp.lock_vlans().map_err(RequestError::from)
     }
+
+    fn set_port_enabled(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        port: u8,
+        enabled: bool,
+    ) -> Result<(), RequestError<MonorailError>> {
+        if usize::from(port) >= self.map.len() {
+            return Err(MonorailError::InvalidPort.into());
+        }
+        let cfg = match self.map.port_config(port) {
+            None => return Err(MonorailError::UnconfiguredPort.into()),
+            Some(cfg) => cfg,
+        };
+        self.vsc7448
+            .set_port_enabled(port, cfg, enabled)
+            .map_err(MonorailError::from)
+            .map_err(RequestError::from)
+    }
 }
 
 impl<'a, R> NotificationHandler for ServerImpl<'a, R> {
@@ -7,7 +7,7 @@ compile_error!("this BSP requires the ksz8463 and mgmt features");
 
 use crate::{
     bsp_support::{self, Ksz8463},
-    mgmt,
+    mgmt::{self, LinkDebounce},
     miim_bridge::MiimBridge,
     pins,
 };
@@ -44,10 +44,15 @@ enum Trace {
     // because they don't really seem to represent a countable event, just the
     // state of a register, and skipping all of them lets us avoid several words
     // of counters. Instead, we just count the number of error event variants.
+    //
+    // The two status entries are only recorded when the port's link state has
+    // changed and then held steady (see `LinkDebounce`), along with the number
+    // of times the link has flapped so far.
     #[count(skip)]
     Ksz8463Status {
         port: KszPort,
         status: u16,
+        flaps: u32,
     },
     #[count(skip)]
     Ksz8463Control {
@@ -64,6 +69,7 @@ enum Trace {
     Vsc8552Status {
         port: u8,
         status: phy::standard::MODE_STATUS,
+        flaps: u32,
     },
     #[count(skip)]
     Vsc8552MacPcsStatus {
@@ -112,6 +118,8 @@ counted_ringbuf!(Trace, 32, Trace::None);
 pub struct BspImpl {
     mgmt: mgmt::Bsp,
     leds: UserLeds,
+    ksz8463_links: [LinkDebounce; 2],
+    vsc8552_links: [LinkDebounce; 2],
 }
 
 impl bsp_support::Bsp for BspImpl {
@@ -175,7 +183,12 @@ impl bsp_support::Bsp for BspImpl {
         leds.led_on(0).unwrap();
         leds.led_off(3).unwrap();
 
-        Self { mgmt, leds }
+        Self {
+            mgmt,
+            leds,
+            ksz8463_links: Default::default(),
+            vsc8552_links: Default::default(),
+        }
     }
 
     fn wake(&self, eth: &eth::Ethernet) {
@@ -183,18 +196,22 @@ impl bsp_support::Bsp for BspImpl {
         // ringbuf; we'll still do verbose logging of full registers below.
         self.mgmt.wake(eth);
 
-        for port in [KszPhyPort::One, KszPhyPort::Two] {
-            ringbuf_entry!(match self
-                .mgmt
-                .ksz8463
-                .read(KszRegister::PxMBSR(port))
-            {
-                Ok(status) => Trace::Ksz8463Status {
-                    port: port.into(),
-                    status
-                },
-                Err(err) => Trace::KszErr { err },
-            });
+        for (port, link) in [KszPhyPort::One, KszPhyPort::Two]
+            .into_iter()
+            .zip(&self.ksz8463_links)
+        {
+            match self.mgmt.ksz8463.read(KszRegister::PxMBSR(port)) {
+                Ok(status) => {
+                    if link.sample(status & (1 << 2) != 0) {
+                        ringbuf_entry!(Trace::Ksz8463Status {
+                            port: port.into(),
+                            status,
+                            flaps: link.flaps(),
+                        });
+                    }
+                }
+                Err(err) => ringbuf_entry!(Trace::KszErr { err }),
+            }
             ringbuf_entry!(match self
                 .mgmt
                 .ksz8463
@@ -222,14 +239,22 @@ impl bsp_support::Bsp for BspImpl {
         let mut any_comma = false;
         let mut any_link = false;
         let rw = &mut MiimBridge::new(eth);
-        for i in [0, 1] {
+        for (i, link) in [0, 1].into_iter().zip(&self.vsc8552_links) {
             let phy = self.mgmt.vsc85x2.phy(i, rw).phy;
             let port = phy.port;
 
-            ringbuf_entry!(match phy.read(phy::STANDARD::MODE_STATUS()) {
-                Ok(status) => Trace::Vsc8552Status { port, status },
-                Err(err) => Trace::Vsc8552Err { err },
-            });
+            match phy.read(phy::STANDARD::MODE_STATUS()) {
+                Ok(status) => {
+                    if link.sample(status.0 & (1 << 2) != 0) {
+                        ringbuf_entry!(Trace::Vsc8552Status {
+                            port,
+                            status,
+                            flaps: link.flaps(),
+                        });
+                    }
+                }
+                Err(err) => ringbuf_entry!(Trace::Vsc8552Err { err }),
+            }
 
             // This is a non-standard register address
             let extended_status =
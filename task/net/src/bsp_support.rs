@@ -66,6 +66,11 @@ pub trait Bsp: Sized {
     /// `wake`. If you change one, change the other.
     const WAKE_INTERVAL: Option<u64> = None;
 
+    /// Divider for the MDIO clock.  The default is slow enough for any PHY;
+    /// boards can pick a faster setting if their PHYs tolerate it, which
+    /// speeds up PHY initialization.
+    const MDC_DIVIDER: eth::MdcDivider = eth::MdcDivider::DEFAULT;
+
     /// Opportunity to do any work before the Ethernet peripheral is turned on.
     fn preinit();
 
@@ -202,6 +202,7 @@ fn main() -> ! {
         rx_ring,
         unsafe { &*device::TIM16::ptr() },
         notifications::MDIO_TIMER_IRQ_MASK,
+        BspImpl::MDC_DIVIDER,
     );
 
     // Set up the network stack.
@@ -3,6 +3,7 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::{bsp_support::Ksz8463, miim_bridge::MiimBridge};
+use core::cell::Cell;
 use drv_stm32h7_eth::Ethernet;
 use drv_stm32xx_sys_api::{self as sys_api, OutputType, Pull, Speed, Sys};
 use ksz8463::{
@@ -24,6 +25,7 @@ use vsc85xx::{vsc85x2::Vsc85x2, Counter, VscError};
 /// This is flagged with allow(dead_code) because each BSP may only use one
 /// or the other behavior, and we only compile one BSP at a time.
 #[allow(dead_code)]
+#[derive(Copy, Clone)]
 pub enum Ksz8463ResetSpeed {
     Slow,
     Normal,
@@ -43,10 +45,44 @@ enum Trace {
         #[count(children)]
         err: VscError,
     },
+    /// The KSZ8463 returned an implausible port status
+    Ksz8463BadStatus {
+        port: KszPort,
+        status: u16,
+    },
+    /// The KSZ8463 is being reset
+    Ksz8463Reset,
+    /// The VSC85x2 returned an implausible status
+    Vsc85x2BadStatus {
+        port: u8,
+        status: u16,
+    },
+    /// A device failed its liveness check too many times in a row, so we're
+    /// resetting it
+    RecoveryTriggered {
+        device: Device,
+        failures: u32,
+    },
+    Ksz8463ResetFailed(#[count(children)] KszError),
+    Vsc85x2ResetFailed(#[count(children)] VscError),
 }
 
 counted_ringbuf!(Trace, 16, Trace::None);
 
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Device {
+    Ksz8463,
+    Vsc85x2,
+}
+
+/// Number of consecutive failed (or implausible) status reads from the
+/// KSZ8463 before we reset it.
+const KSZ8463_FAILURE_THRESHOLD: u32 = 3;
+
+/// Number of consecutive failed (or implausible) status reads from the
+/// VSC85x2 before we reset and reinitialize it.
+const VSC85X2_FAILURE_THRESHOLD: u32 = 3;
+
 /// Configuration struct for the rest of the management network hardware,
 /// which is a KSZ8463 switch attached to a VSC8552 or VSC8562 PHY.
 pub struct Config {
@@ -76,35 +112,29 @@ impl Config {
         // The VSC8552 connects the KSZ switch to the management network
         // over SGMII
         let vsc85x2 = self.configure_vsc85x2(sys, eth);
+        let vsc85x2_reset = Vsc85x2Reset {
+            sys: sys.clone(),
+            nrst: self.vsc85x2_nrst,
+            coma_mode: self.vsc85x2_coma_mode,
+            base_port: self.vsc85x2_base_port,
+        };
 
-        // The KSZ8463 connects to the SP over RMII, then sends data to the
-        // VSC8552 over 100-BASE FX
-        let ksz8463 = self.configure_ksz8463(sys);
-
-        Bsp { ksz8463, vsc85x2 }
-    }
-
-    fn configure_ksz8463(self, sys: &Sys) -> Ksz8463 {
-        // The datasheet recommends a particular combination of diodes and
-        // capacitors which dramatically slow down the rise of the reset
-        // line, meaning you have to wait for extra long here.
-        //
-        // Otherwise, the minimum wait time is 1 µs, so 1 ms is fine.
-        sys.gpio_init_reset_pulse(
-            self.ksz8463_nrst,
-            10,
-            match self.ksz8463_rst_type {
-                Ksz8463ResetSpeed::Slow => 150,
-                Ksz8463ResetSpeed::Normal => 1,
-            },
-        );
-
-        // The KSZ8463 connects to the SP over RMII, then sends data to the
-        // VSC8552 over 100-BASE FX
-        self.ksz8463
-            .configure(ksz8463::Mode::Fiber, self.ksz8463_vlan_mode)
-            .unwrap_lite();
-        self.ksz8463
+        let ksz8463_reset = Ksz8463Reset {
+            sys: sys.clone(),
+            nrst: self.ksz8463_nrst,
+            rst_type: self.ksz8463_rst_type,
+            vlan_mode: self.ksz8463_vlan_mode,
+        };
+        ksz8463_reset.reset(&self.ksz8463).unwrap_lite();
+
+        Bsp {
+            ksz8463: self.ksz8463,
+            ksz8463_reset,
+            ksz8463_failures: Cell::new(0),
+            vsc85x2,
+            vsc85x2_reset,
+            vsc85x2_failures: Cell::new(0),
+        }
     }
 
     fn configure_vsc85x2(&self, sys: &Sys, eth: &Ethernet) -> Vsc85x2 {
@@ -166,9 +196,87 @@ impl Config {
     }
 }
 
+/// Everything needed to reset and reconfigure the KSZ8463, so that we can
+/// recover it at runtime if it stops responding sensibly.
+struct Ksz8463Reset {
+    sys: Sys,
+    nrst: sys_api::PinSet,
+    rst_type: Ksz8463ResetSpeed,
+    vlan_mode: ksz8463::VLanMode,
+}
+
+impl Ksz8463Reset {
+    /// Pulses the KSZ8463's reset line, then configures it from scratch
+    fn reset(&self, ksz8463: &Ksz8463) -> Result<(), KszError> {
+        ringbuf_entry!(Trace::Ksz8463Reset);
+
+        // The datasheet recommends a particular combination of diodes and
+        // capacitors which dramatically slow down the rise of the reset
+        // line, meaning you have to wait for extra long here.
+        //
+        // Otherwise, the minimum wait time is 1 µs, so 1 ms is fine.
+        self.sys.gpio_init_reset_pulse(
+            self.nrst,
+            10,
+            match self.rst_type {
+                Ksz8463ResetSpeed::Slow => 150,
+                Ksz8463ResetSpeed::Normal => 1,
+            },
+        );
+
+        // The KSZ8463 connects to the SP over RMII, then sends data to the
+        // VSC8552 over 100-BASE FX
+        ksz8463.configure(ksz8463::Mode::Fiber, self.vlan_mode)
+    }
+}
+
+/// Everything needed to reset and reinitialize the VSC85x2 at runtime.  This
+/// skips the power sequencing in `Config::configure_vsc85x2`, since the PHY is
+/// already powered by the time we're recovering it.
+struct Vsc85x2Reset {
+    sys: Sys,
+    nrst: sys_api::PinSet,
+    coma_mode: Option<sys_api::PinSet>,
+    base_port: u8,
+}
+
+impl Vsc85x2Reset {
+    /// Pulses the VSC85x2's reset line, then initializes it from scratch
+    ///
+    /// This blocks the net task for about 130 ms, mostly waiting for the PHY
+    /// to come out of reset.  That's acceptable because we only get here from
+    /// `Bsp::wake` after `VSC85X2_FAILURE_THRESHOLD` consecutive failed
+    /// checks, at which point the management network isn't passing traffic
+    /// anyway, so the net task has nothing better to do until it's back.
+    fn reset(&self, eth: &Ethernet) -> Result<(), VscError> {
+        // Hold the PHY in COMA_MODE until it's configured again
+        if let Some(coma_mode) = self.coma_mode {
+            self.sys.gpio_set(coma_mode);
+        }
+
+        // Same timing as the initial bringup: wait 120 ms for the chip to
+        // come out of reset.
+        self.sys.gpio_init_reset_pulse(self.nrst, 10, 120);
+
+        let rw = &mut MiimBridge::new(eth);
+        let out = Vsc85x2::init_sgmii(self.base_port, rw).map(|_| ());
+
+        if let Some(coma_mode) = self.coma_mode {
+            self.sys.gpio_reset(coma_mode);
+        }
+        out
+    }
+}
+
 pub struct Bsp {
     pub ksz8463: Ksz8463,
+    ksz8463_reset: Ksz8463Reset,
+    /// Consecutive failed liveness checks of the KSZ8463
+    ksz8463_failures: Cell<u32>,
     pub vsc85x2: Vsc85x2,
+    vsc85x2_reset: Vsc85x2Reset,
+    /// Consecutive failed liveness checks of the VSC85x2
+    vsc85x2_failures: Cell<u32>,
 }
 
 impl Bsp {
@@ -211,8 +319,81 @@ impl Bsp {
         }
     }
 
-    pub fn wake(&self, _eth: &Ethernet) {
-        // Nothing to do here
+    /// Checks that the KSZ8463 and VSC85x2 are still alive, resetting them
+    /// if not.
+    ///
+    /// If either chip wedges, its status registers read back as all-ones (or
+    /// the transaction fails outright).  After a few consecutive failures
+    /// (`KSZ8463_FAILURE_THRESHOLD` and `VSC85X2_FAILURE_THRESHOLD`), we
+    /// pulse the chip's reset line and reconfigure it, rather than requiring
+    /// a power cycle.  A link that's merely down doesn't count as a failure,
+    /// since that's what an unplugged cable looks like.
+    pub fn wake(&self, eth: &Ethernet) {
+        let ksz8463_ok = self.ksz8463_alive();
+        if track_failures(
+            &self.ksz8463_failures,
+            ksz8463_ok,
+            KSZ8463_FAILURE_THRESHOLD,
+            Device::Ksz8463,
+        ) {
+            if let Err(e) = self.ksz8463_reset.reset(&self.ksz8463) {
+                ringbuf_entry!(Trace::Ksz8463ResetFailed(e));
+            }
+        }
+
+        let vsc85x2_ok = self.vsc85x2_alive(eth);
+        if track_failures(
+            &self.vsc85x2_failures,
+            vsc85x2_ok,
+            VSC85X2_FAILURE_THRESHOLD,
+            Device::Vsc85x2,
+        ) {
+            if let Err(e) = self.vsc85x2_reset.reset(eth) {
+                ringbuf_entry!(Trace::Vsc85x2ResetFailed(e));
+            }
+        }
+    }
+
+    /// Reads a port status register from the KSZ8463, returning `false` if
+    /// the read failed or returned something implausible.
+    fn ksz8463_alive(&self) -> bool {
+        let port = KszPhyPort::One;
+        match self.ksz8463.read(KszRegister::PxMBSR(port)) {
+            Ok(0xFFFF) => {
+                ringbuf_entry!(Trace::Ksz8463BadStatus {
+                    port: port.into(),
+                    status: 0xFFFF,
+                });
+                false
+            }
+            Ok(_) => true,
+            Err(err) => {
+                ringbuf_entry!(Trace::Ksz8463Err {
+                    port: port.into(),
+                    err
+                });
+                false
+            }
+        }
+    }
+
+    /// Reads the status register from the VSC85x2's first port, returning
+    /// `false` if the read failed or returned something implausible.
+    fn vsc85x2_alive(&self, eth: &Ethernet) -> bool {
+        let port = 0;
+        let rw = &mut MiimBridge::new(eth);
+        let phy = self.vsc85x2.phy(port, rw);
+        match phy.phy.read(phy::STANDARD::MODE_STATUS()) {
+            Ok(sr) if sr.0 == 0xFFFF => {
+                ringbuf_entry!(Trace::Vsc85x2BadStatus { port, status: sr.0 });
+                false
+            }
+            Ok(_) => true,
+            Err(err) => {
+                ringbuf_entry!(Trace::Vsc85x2Err { port, err });
+                false
+            }
+        }
     }
 
     pub fn management_link_status(
@@ -357,3 +538,87 @@ impl Bsp {
         Ok(out)
     }
 }
+
+/// Updates a device's count of consecutive liveness failures, returning
+/// `true` (and resetting the count) if it has reached `threshold` and the
+/// device should be recovered.
+fn track_failures(
+    failures: &Cell<u32>,
+    ok: bool,
+    threshold: u32,
+    device: Device,
+) -> bool {
+    if ok {
+        failures.set(0);
+        return false;
+    }
+    let n = failures.get() + 1;
+    if n >= threshold {
+        ringbuf_entry!(Trace::RecoveryTriggered {
+            device,
+            failures: n
+        });
+        failures.set(0);
+        true
+    } else {
+        failures.set(n);
+        false
+    }
+}
+
+/// Number of consecutive polls a link must hold the same state before
+/// [`LinkDebounce`] reports it.
+#[allow(dead_code)]
+pub const LINK_DEBOUNCE_POLLS: u8 = 3;
+
+/// Debounces a link-up bit that's sampled periodically, so that a link
+/// bouncing at the margin shows up as a rising flap count rather than a
+/// stream of up/down events.
+///
+/// This is flagged with allow(dead_code) because only some BSPs poll link
+/// state, and we only compile one BSP at a time.
+#[allow(dead_code)]
+#[derive(Default)]
+pub struct LinkDebounce {
+    /// Most recent sample
+    last: Cell<Option<bool>>,
+    /// Number of consecutive samples matching `last`
+    stable: Cell<u8>,
+    /// Last state returned from `sample`
+    reported: Cell<Option<bool>>,
+    /// Number of times the raw sample has changed, stable or not
+    flaps: Cell<u32>,
+}
+
+#[allow(dead_code)]
+impl LinkDebounce {
+    /// Records a new sample of the link state, returning `true` if the link
+    /// has now held a state other than the last-reported one for
+    /// `LINK_DEBOUNCE_POLLS` consecutive samples.
+    pub fn sample(&self, up: bool) -> bool {
+        if self.last.get() == Some(up) {
+            self.stable.set(self.stable.get().saturating_add(1));
+        } else {
+            if self.last.get().is_some() {
+                self.flaps.set(self.flaps.get().wrapping_add(1));
+            }
+            self.last.set(Some(up));
+            self.stable.set(1);
+        }
+
+        if self.stable.get() >= LINK_DEBOUNCE_POLLS
+            && self.reported.get() != Some(up)
+        {
+            self.reported.set(Some(up));
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns the number of times the link state has changed between
+    /// samples, including changes that didn't last long enough to report.
+    pub fn flaps(&self) -> u32 {
+        self.flaps.get()
+    }
+}